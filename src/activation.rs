@@ -0,0 +1,75 @@
+use clap::ValueEnum;
+use ndarray::Array2;
+use serde::{Deserialize, Serialize};
+
+use crate::math::{sigmoid, sigmoid_derivative};
+
+pub const LEAKY_RELU_SLOPE: f32 = 0.01;
+pub const ELU_ALPHA: f32 = 1.0;
+// sqrt(2 / pi), used by the tanh approximation of GELU
+pub const GELU_SQRT_2_OVER_PI: f32 = 0.797_884_6;
+pub const GELU_COEFF: f32 = 0.044_715;
+
+/// Element-wise activation applied to the output of a dense layer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Activation {
+  Sigmoid,
+  Relu,
+  LeakyRelu,
+  Tanh,
+  Gelu,
+  Elu,
+  Silu,
+  Identity,
+}
+
+impl Activation {
+  /// Apply the activation to the pre-activation values `z`.
+  pub fn apply(&self, z: &Array2<f32>) -> Array2<f32> {
+    match self {
+      Activation::Sigmoid => sigmoid(z),
+      Activation::Relu => z.mapv(|v| v.max(0.0)),
+      Activation::LeakyRelu => z.mapv(|v| if v > 0.0 { v } else { LEAKY_RELU_SLOPE * v }),
+      Activation::Tanh => z.mapv(f32::tanh),
+      Activation::Gelu => z.mapv(|v| 0.5 * v * (1.0 + gelu_inner(v).tanh())),
+      Activation::Elu => z.mapv(|v| if v > 0.0 { v } else { ELU_ALPHA * v.exp_m1() }),
+      Activation::Silu => z.mapv(|v| v / (1.0 + (-v).exp())),
+      Activation::Identity => z.clone(),
+    }
+  }
+
+  /// Derivative of the activation with respect to `z`. `a` must be `apply(z)`; some
+  /// derivatives are cheaper to compute from the activated values.
+  pub fn derivative(&self, z: &Array2<f32>, a: &Array2<f32>) -> Array2<f32> {
+    match self {
+      Activation::Sigmoid => sigmoid_derivative(a),
+      Activation::Relu => z.mapv(|v| if v > 0.0 { 1.0 } else { 0.0 }),
+      Activation::LeakyRelu => z.mapv(|v| if v > 0.0 { 1.0 } else { LEAKY_RELU_SLOPE }),
+      Activation::Tanh => a.mapv(|v| 1.0 - v * v),
+      Activation::Gelu => z.mapv(|v| {
+        let t = gelu_inner(v).tanh();
+        let inner_derivative = GELU_SQRT_2_OVER_PI * (1.0 + 3.0 * GELU_COEFF * v * v);
+        0.5 * (1.0 + t) + 0.5 * v * (1.0 - t * t) * inner_derivative
+      }),
+      Activation::Elu => {
+        let mut d = a.mapv(|v| v + ELU_ALPHA);
+        d.zip_mut_with(z, |d, &v| {
+          if v > 0.0 {
+            *d = 1.0
+          }
+        });
+        d
+      }
+      Activation::Silu => z.mapv(|v| {
+        let s = 1.0 / (1.0 + (-v).exp());
+        s * (1.0 + v * (1.0 - s))
+      }),
+      Activation::Identity => Array2::ones(z.dim()),
+    }
+  }
+}
+
+fn gelu_inner(v: f32) -> f32 {
+  GELU_SQRT_2_OVER_PI * (v + GELU_COEFF * v * v * v)
+}
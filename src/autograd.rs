@@ -0,0 +1,354 @@
+use ndarray::{Array2, Axis};
+use std::cell::{Ref, RefCell};
+
+use crate::activation::Activation;
+
+/// A value recorded on a `Tape`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Var(usize);
+
+/// Gradients of an operation's inputs given the gradient of its output, for operations
+/// recorded with `Tape::custom`. Also gets whether each input needs its gradient; the
+/// ones that don't may be `None`.
+pub type BackwardFn = Box<dyn Fn(&Array2<f32>, &[bool]) -> Vec<Option<Array2<f32>>>>;
+
+enum Op {
+  /// A value the tape did not compute: an input, a constant or a parameter.
+  Leaf,
+  MatMul(Var, Var),
+  Add(Var, Var),
+  Sub(Var, Var),
+  Mul(Var, Var),
+  Scale(Var, f32),
+  Powf(Var, f32),
+  Activation(Var, Activation),
+  LogSoftmax(Var),
+  CrossEntropy {
+    log_probs: Var,
+    targets: Array2<f32>,
+  },
+  Sum(Var),
+  Mean(Var),
+  MeanAxis(Var, Axis),
+  Custom {
+    inputs: Vec<Var>,
+    backward: BackwardFn,
+  },
+}
+
+struct Node {
+  value: Array2<f32>,
+  op: Op,
+  /// Whether the value depends on a parameter, so gradients need to flow through it.
+  requires_grad: bool,
+}
+
+/// Records operations on `Array2<f32>` values as they are computed, so `backward` can
+/// apply the chain rule through them in reverse (reverse-mode automatic differentiation).
+/// Each operation only needs to know its own derivative; whatever is built out of them
+/// gets its gradients for free.
+///
+/// Operations take `&self`, so a recorded forward pass can be extended (with a loss, say)
+/// without being rebuilt.
+#[derive(Default)]
+pub struct Tape {
+  nodes: RefCell<Vec<Node>>,
+}
+
+/// Gradients of a scalar with respect to the parameters of a tape.
+pub struct Grads(Vec<Option<Array2<f32>>>);
+
+impl Grads {
+  pub fn get(&self, var: Var) -> Option<&Array2<f32>> {
+    self.0[var.0].as_ref()
+  }
+
+  pub fn take(&mut self, var: Var) -> Option<Array2<f32>> {
+    self.0[var.0].take()
+  }
+}
+
+/// Sum `grad` down to `dim`, undoing the broadcast of a `dim` input to the shape of the
+/// output.
+fn unbroadcast(grad: &Array2<f32>, dim: (usize, usize)) -> Array2<f32> {
+  let mut grad = grad.clone();
+  if dim.0 == 1 && grad.nrows() != 1 {
+    grad = grad.sum_axis(Axis(0)).insert_axis(Axis(0));
+  }
+  if dim.1 == 1 && grad.ncols() != 1 {
+    grad = grad.sum_axis(Axis(1)).insert_axis(Axis(1));
+  }
+  grad
+}
+
+/// Add the gradient `grad` computes to the total of `var`. Values that need no gradient,
+/// e.g. the input of the first layer, skip computing it.
+fn accumulate(
+  nodes: &[Node],
+  grads: &mut [Option<Array2<f32>>],
+  var: Var,
+  grad: impl FnOnce() -> Array2<f32>,
+) {
+  if !nodes[var.0].requires_grad {
+    return;
+  }
+  match &mut grads[var.0] {
+    Some(total) => *total += &grad(),
+    slot => *slot = Some(grad()),
+  }
+}
+
+impl Tape {
+  pub fn new() -> Self {
+    Tape::default()
+  }
+
+  fn push(&self, value: Array2<f32>, op: Op, requires_grad: bool) -> Var {
+    let mut nodes = self.nodes.borrow_mut();
+    nodes.push(Node {
+      value,
+      op,
+      requires_grad,
+    });
+    Var(nodes.len() - 1)
+  }
+
+  fn requires_grad(&self, vars: &[Var]) -> bool {
+    let nodes = self.nodes.borrow();
+    vars.iter().any(|var| nodes[var.0].requires_grad)
+  }
+
+  /// A value no gradient is needed for, such as an input.
+  pub fn constant(&self, value: Array2<f32>) -> Var {
+    self.push(value, Op::Leaf, false)
+  }
+
+  /// A value `backward` computes the gradient for.
+  pub fn parameter(&self, value: Array2<f32>) -> Var {
+    self.push(value, Op::Leaf, true)
+  }
+
+  pub fn value(&self, var: Var) -> Ref<'_, Array2<f32>> {
+    Ref::map(self.nodes.borrow(), |nodes| &nodes[var.0].value)
+  }
+
+  /// Record an operation whose value and backward pass come from the caller, for
+  /// operations that only move values around. The model's layers use it for
+  /// convolutions' `im2col` (differentiated by `col2im`) and the reordering of their
+  /// output into samples, and for pooling, whose gradients go back to the values each
+  /// window read. Every other gradient, normalizations' included, comes from the
+  /// operations below.
+  pub fn custom(&self, inputs: &[Var], value: Array2<f32>, backward: BackwardFn) -> Var {
+    let requires_grad = self.requires_grad(inputs);
+    let op = Op::Custom {
+      inputs: inputs.to_vec(),
+      backward,
+    };
+    self.push(value, op, requires_grad)
+  }
+
+  /// Matrix product `a . b`.
+  pub fn matmul(&self, a: Var, b: Var) -> Var {
+    let value = self.value(a).dot(&*self.value(b));
+    self.push(value, Op::MatMul(a, b), self.requires_grad(&[a, b]))
+  }
+
+  /// `a + b`, broadcasting rows or columns of length 1.
+  pub fn add(&self, a: Var, b: Var) -> Var {
+    let value = &*self.value(a) + &*self.value(b);
+    self.push(value, Op::Add(a, b), self.requires_grad(&[a, b]))
+  }
+
+  /// `a - b`, broadcasting rows or columns of length 1.
+  pub fn sub(&self, a: Var, b: Var) -> Var {
+    let value = &*self.value(a) - &*self.value(b);
+    self.push(value, Op::Sub(a, b), self.requires_grad(&[a, b]))
+  }
+
+  /// Element-wise `a * b`, broadcasting rows or columns of length 1.
+  pub fn mul(&self, a: Var, b: Var) -> Var {
+    let value = &*self.value(a) * &*self.value(b);
+    self.push(value, Op::Mul(a, b), self.requires_grad(&[a, b]))
+  }
+
+  /// `a * factor`.
+  pub fn scale(&self, a: Var, factor: f32) -> Var {
+    let value = &*self.value(a) * factor;
+    self.push(value, Op::Scale(a, factor), self.requires_grad(&[a]))
+  }
+
+  /// Element-wise `a` to the power `exponent`.
+  pub fn powf(&self, a: Var, exponent: f32) -> Var {
+    let value = self.value(a).mapv(|v| v.powf(exponent));
+    self.push(value, Op::Powf(a, exponent), self.requires_grad(&[a]))
+  }
+
+  pub fn activation(&self, a: Var, activation: Activation) -> Var {
+    let value = activation.apply(&self.value(a));
+    self.push(
+      value,
+      Op::Activation(a, activation),
+      self.requires_grad(&[a]),
+    )
+  }
+
+  /// Log of the softmax of each column, computed without the exponentials overflowing.
+  pub fn log_softmax(&self, a: Var) -> Var {
+    let mut value = self.value(a).clone();
+    for mut column in value.columns_mut() {
+      let max = column.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+      let log_sum = column.iter().map(|&v| (v - max).exp()).sum::<f32>().ln();
+      column.mapv_inplace(|v| v - max - log_sum);
+    }
+    self.push(value, Op::LogSoftmax(a), self.requires_grad(&[a]))
+  }
+
+  /// Mean cross-entropy (1x1) of the columns of `log_probs` against the probabilities in
+  /// the same columns of `targets`, usually one-hot.
+  pub fn cross_entropy(&self, log_probs: Var, targets: &Array2<f32>) -> Var {
+    let value = -(&*self.value(log_probs) * targets).sum() / targets.ncols() as f32;
+    let op = Op::CrossEntropy {
+      log_probs,
+      targets: targets.clone(),
+    };
+    self.push(
+      Array2::from_elem((1, 1), value),
+      op,
+      self.requires_grad(&[log_probs]),
+    )
+  }
+
+  /// Sum of every value, as a 1x1 value.
+  pub fn sum(&self, a: Var) -> Var {
+    let value = Array2::from_elem((1, 1), self.value(a).sum());
+    self.push(value, Op::Sum(a), self.requires_grad(&[a]))
+  }
+
+  /// Mean of every value, as a 1x1 value.
+  pub fn mean(&self, a: Var) -> Var {
+    let value = Array2::from_elem((1, 1), self.value(a).mean().unwrap_or(0.0));
+    self.push(value, Op::Mean(a), self.requires_grad(&[a]))
+  }
+
+  /// Mean along `axis`, which is kept with length 1 so the result broadcasts against `a`:
+  /// `features x 1` for the mean of each row, `1 x batch` for that of each column.
+  pub fn mean_axis(&self, a: Var, axis: Axis) -> Var {
+    let value = self.value(a).mean_axis(axis).unwrap().insert_axis(axis);
+    self.push(value, Op::MeanAxis(a, axis), self.requires_grad(&[a]))
+  }
+
+  /// Gradients of `loss`, a 1x1 value, with respect to every parameter it depends on.
+  pub fn backward(&self, loss: Var) -> Grads {
+    let nodes = self.nodes.borrow();
+    assert_eq!(
+      nodes[loss.0].value.dim(),
+      (1, 1),
+      "backward needs a scalar loss"
+    );
+
+    let mut grads: Vec<Option<Array2<f32>>> = (0..nodes.len()).map(|_| None).collect();
+    grads[loss.0] = Some(Array2::ones((1, 1)));
+    // every node comes after its inputs, so walking backwards visits a node only once
+    // all of its uses have added to its gradient
+    for i in (0..=loss.0).rev() {
+      let node = &nodes[i];
+      if !node.requires_grad || matches!(node.op, Op::Leaf) {
+        continue;
+      }
+      // intermediate gradients are dropped once passed on, only parameters keep theirs
+      let Some(grad) = grads[i].take() else {
+        continue;
+      };
+      let value = |var: Var| &nodes[var.0].value;
+
+      match &node.op {
+        Op::Leaf => {}
+        Op::MatMul(a, b) => {
+          accumulate(&nodes, &mut grads, *a, || grad.dot(&value(*b).t()));
+          accumulate(&nodes, &mut grads, *b, || value(*a).t().dot(&grad));
+        }
+        Op::Add(a, b) => {
+          accumulate(&nodes, &mut grads, *a, || {
+            unbroadcast(&grad, value(*a).dim())
+          });
+          accumulate(&nodes, &mut grads, *b, || {
+            unbroadcast(&grad, value(*b).dim())
+          });
+        }
+        Op::Sub(a, b) => {
+          accumulate(&nodes, &mut grads, *a, || {
+            unbroadcast(&grad, value(*a).dim())
+          });
+          accumulate(&nodes, &mut grads, *b, || {
+            -unbroadcast(&grad, value(*b).dim())
+          });
+        }
+        Op::Mul(a, b) => {
+          accumulate(&nodes, &mut grads, *a, || {
+            unbroadcast(&(&grad * value(*b)), value(*a).dim())
+          });
+          accumulate(&nodes, &mut grads, *b, || {
+            unbroadcast(&(&grad * value(*a)), value(*b).dim())
+          });
+        }
+        Op::Scale(a, factor) => accumulate(&nodes, &mut grads, *a, || grad * *factor),
+        Op::Powf(a, exponent) => {
+          accumulate(&nodes, &mut grads, *a, || {
+            grad * value(*a).mapv(|v| exponent * v.powf(exponent - 1.0))
+          });
+        }
+        Op::Activation(a, activation) => {
+          let derivative = activation.derivative(value(*a), &node.value);
+          accumulate(&nodes, &mut grads, *a, || grad * derivative);
+        }
+        Op::LogSoftmax(a) => {
+          // d/dz_i sum_j g_j (z_j - log sum_k e^z_k) = g_i - softmax_i * sum_j g_j
+          let total = grad.sum_axis(Axis(0)).insert_axis(Axis(0));
+          let softmax = node.value.mapv(f32::exp);
+          accumulate(&nodes, &mut grads, *a, || grad - softmax * total);
+        }
+        Op::CrossEntropy { log_probs, targets } => {
+          let scale = -grad[[0, 0]] / targets.ncols() as f32;
+          accumulate(&nodes, &mut grads, *log_probs, || targets * scale);
+        }
+        Op::Sum(a) => {
+          let dim = value(*a).dim();
+          accumulate(&nodes, &mut grads, *a, || {
+            Array2::from_elem(dim, grad[[0, 0]])
+          });
+        }
+        Op::Mean(a) => {
+          let dim = value(*a).dim();
+          let share = grad[[0, 0]] / (dim.0 * dim.1) as f32;
+          accumulate(&nodes, &mut grads, *a, || Array2::from_elem(dim, share));
+        }
+        Op::MeanAxis(a, axis) => {
+          let dim = value(*a).dim();
+          let n = value(*a).len_of(*axis) as f32;
+          accumulate(&nodes, &mut grads, *a, || {
+            grad.broadcast(dim).unwrap().mapv(|g| g / n)
+          });
+        }
+        Op::Custom { inputs, backward } => {
+          let needed: Vec<bool> = inputs
+            .iter()
+            .map(|var| nodes[var.0].requires_grad)
+            .collect();
+          let input_grads = backward(&grad, &needed);
+          assert_eq!(
+            input_grads.len(),
+            inputs.len(),
+            "custom backward must return one gradient per input"
+          );
+          for (&var, input_grad) in inputs.iter().zip(input_grads) {
+            if let Some(input_grad) = input_grad {
+              accumulate(&nodes, &mut grads, var, || input_grad);
+            }
+          }
+        }
+      }
+    }
+
+    Grads(grads)
+  }
+}
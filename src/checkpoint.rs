@@ -0,0 +1,228 @@
+use ndarray::Array2;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::config::TrainingConfig;
+use crate::early_stopping::EarlyStopping;
+use crate::inferrable_model::InferrableModel;
+use crate::optimizer::{Optimizer, OptimizerState};
+use crate::schedule::LrScheduler;
+use crate::serializable_model::TrainingInfo;
+use crate::serialization::{load_safetensors, save_safetensors};
+use crate::stats::TrainingStats;
+
+const CHECKPOINT_KEY: &str = "checkpoint";
+const OPTIMIZER_PREFIX: &str = "optimizer.";
+const BEST_MODEL_PREFIX: &str = "best.";
+const OPTIMIZER_STEP_KEY: &str = "optimizer_step";
+
+/// Position of a `ChaCha8Rng` seeded with `seed_from_u64(seed)`. Restoring it continues
+/// the exact same random stream.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RngState {
+  pub seed: u64,
+  pub word_pos: u128,
+}
+
+impl RngState {
+  pub fn new(seed: u64, rng: &ChaCha8Rng) -> Self {
+    RngState {
+      seed,
+      word_pos: rng.get_word_pos(),
+    }
+  }
+
+  pub fn restore(&self) -> ChaCha8Rng {
+    let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+    rng.set_word_pos(self.word_pos);
+    rng
+  }
+}
+
+/// Everything besides the weights and optimizer tensors needed to continue a run.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CheckpointMetadata {
+  pub config: TrainingConfig,
+  /// Epoch (0-based) the run was in when the checkpoint was written.
+  pub epoch: usize,
+  /// Number of batches of `epoch` that were already trained on.
+  pub step_in_epoch: usize,
+  /// Number of optimizer steps taken since the start of the run.
+  pub global_step: usize,
+  pub rng: RngState,
+  /// RNG state at the start of `epoch`, before its images were shuffled.
+  pub epoch_rng: RngState,
+  pub scheduler: LrScheduler,
+  /// Loss and accuracy over the batches of `epoch` trained so far.
+  pub epoch_stats: TrainingStats,
+  /// Lowest validation loss seen so far, or training loss if nothing is held out.
+  pub best_loss: Option<f32>,
+  pub early_stopping: Option<EarlyStopping>,
+}
+
+pub struct Checkpoint {
+  pub metadata: CheckpointMetadata,
+  pub model: InferrableModel,
+  /// Best weights so far when early stopping is on.
+  pub best_model: Option<InferrableModel>,
+  /// The optimizer of `metadata.config`, with the state it had.
+  pub optimizer: Box<dyn Optimizer>,
+}
+
+/// Write a checkpoint holding the model weights, the optimizer state (as tensors
+/// prefixed with `optimizer.`), the best weights early stopping has seen (prefixed with
+/// `best.`) and the training progress.
+///
+/// The weights use the same tensor names as a regular model file, so a checkpoint can be
+/// passed to `validate` or `infer` as-is. The file is written to a temporary path and
+/// renamed, so an interrupted save never leaves a truncated checkpoint behind.
+pub fn save_checkpoint<P: AsRef<Path>>(
+  path: P,
+  metadata: &CheckpointMetadata,
+  model: &InferrableModel,
+  best_model: Option<&InferrableModel>,
+  optimizer: &dyn Optimizer,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let path = path.as_ref();
+  let mut serializable = model.to_serializable_model();
+  serializable.training = Some(TrainingInfo::new(&metadata.config)?);
+  let (mut tensors, mut header) = serializable.to_tensors()?;
+
+  if let Some(best_model) = best_model {
+    let (best_tensors, _) = best_model.to_serializable_model().to_tensors()?;
+    for (name, arr) in best_tensors {
+      tensors.push((format!("{}{}", BEST_MODEL_PREFIX, name), arr));
+    }
+  }
+
+  let optimizer_state = optimizer.state();
+  let mut optimizer_names: Vec<&String> = optimizer_state.tensors.keys().collect();
+  optimizer_names.sort();
+  for name in optimizer_names {
+    tensors.push((
+      format!("{}{}", OPTIMIZER_PREFIX, name),
+      optimizer_state.tensors[name].clone(),
+    ));
+  }
+
+  header.insert(CHECKPOINT_KEY.to_string(), serde_json::to_string(metadata)?);
+  header.insert(
+    OPTIMIZER_STEP_KEY.to_string(),
+    optimizer_state.step.to_string(),
+  );
+
+  let named: Vec<(&str, &Array2<f32>)> = tensors
+    .iter()
+    .map(|(name, arr)| (name.as_str(), arr))
+    .collect();
+
+  let tmp_path = path.with_extension("tmp");
+  save_safetensors(&tmp_path, &named, Some(header))?;
+  std::fs::rename(&tmp_path, path)?;
+  Ok(())
+}
+
+pub fn load_checkpoint<P: AsRef<Path>>(path: P) -> Result<Checkpoint, Box<dyn std::error::Error>> {
+  let path = path.as_ref();
+  let mut model = InferrableModel::load(path)?;
+  // training would update the float weights and leave the int8 ones behind
+  if model.is_quantized() {
+    return Err(
+      format!(
+        "{} holds a quantized model, which can only be evaluated",
+        path.display()
+      )
+      .into(),
+    );
+  }
+
+  let (tensors, header) = load_safetensors(path)?;
+  let metadata: CheckpointMetadata = match header.get(CHECKPOINT_KEY) {
+    Some(json) => serde_json::from_str(json)?,
+    None => {
+      return Err(
+        format!(
+          "{} is not a checkpoint (no training progress recorded)",
+          path.display()
+        )
+        .into(),
+      );
+    }
+  };
+
+  let optimizer_step = match header.get(OPTIMIZER_STEP_KEY) {
+    Some(step) => step.parse()?,
+    None => 0,
+  };
+
+  // the best weights share the current model's architecture; fill a copy of it with them
+  let has_best_model = tensors
+    .iter()
+    .any(|(name, _)| name.starts_with(BEST_MODEL_PREFIX));
+  let best_model = if has_best_model {
+    let mut best_model = model.clone();
+    for (name, param) in best_model.named_tensors_mut() {
+      let name = format!("{}{}", BEST_MODEL_PREFIX, name);
+      match tensors.iter().find(|(n, _)| *n == name) {
+        Some((_, arr)) if arr.dim() == param.dim() => param.assign(arr),
+        _ => return Err(format!("checkpoint is missing best weights {}", name).into()),
+      }
+    }
+    Some(best_model)
+  } else {
+    None
+  };
+
+  let optimizer_state = OptimizerState {
+    step: optimizer_step,
+    tensors: tensors
+      .into_iter()
+      .filter_map(|(name, arr)| {
+        name
+          .strip_prefix(OPTIMIZER_PREFIX)
+          .map(|name| (name.to_string(), arr))
+      })
+      .collect(),
+  };
+  let shapes: Vec<(usize, usize)> = model.parameters_mut().iter().map(|p| p.dim()).collect();
+  let mut optimizer = metadata.config.optimizer.build();
+  optimizer.load_state(optimizer_state, &shapes)?;
+
+  Ok(Checkpoint {
+    metadata,
+    model,
+    best_model,
+    optimizer,
+  })
+}
+
+/// When and where `run_train` writes checkpoints.
+pub struct CheckpointPolicy {
+  pub path: PathBuf,
+  /// Write a checkpoint after every this many epochs.
+  pub every_epochs: Option<usize>,
+  /// Write a checkpoint after every this many optimizer steps.
+  pub every_steps: Option<usize>,
+}
+
+impl CheckpointPolicy {
+  /// Default checkpoint path next to a model, e.g.
+  /// `model.safetensors` -> `model.checkpoint.safetensors`.
+  pub fn default_path(model_path: &str) -> PathBuf {
+    Path::new(model_path).with_extension("checkpoint.safetensors")
+  }
+
+  pub fn after_epoch(&self, epochs_done: usize) -> bool {
+    self
+      .every_epochs
+      .is_some_and(|n| n > 0 && epochs_done.is_multiple_of(n))
+  }
+
+  pub fn after_step(&self, steps_done: usize) -> bool {
+    self
+      .every_steps
+      .is_some_and(|n| n > 0 && steps_done.is_multiple_of(n))
+  }
+}
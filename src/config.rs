@@ -0,0 +1,557 @@
+use clap::{Args, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::activation::Activation;
+use crate::conv::{ConvLayerConfig, Shape, conv_output_shape};
+use crate::early_stopping::{EarlyStoppingConfig, StopMetric};
+use crate::norm::NormKind;
+use crate::optimizer::{OptimizerConfig, OptimizerKind};
+use crate::preprocessing::{Normalization, PreprocessingConfig};
+use crate::regularization::RegularizationConfig;
+use crate::schedule::{ScheduleConfig, ScheduleKind};
+use crate::training::TRAINING_SIZE;
+
+/// Every hyperparameter of a training run.
+///
+/// Built from the defaults below, then a `--config` file, then command line flags, each
+/// overriding the previous. The resolved config is printed at the start of training and
+/// embedded in the saved model.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TrainingConfig {
+  /// Convolutional and pooling layers applied to the image, in order; their output is
+  /// flattened into the first of `layers`
+  pub conv_layers: Vec<ConvLayerConfig>,
+  /// Dense layer widths, including input and output
+  pub layers: Vec<usize>,
+  /// One activation per hidden layer, or a single one for all of them
+  pub activations: Vec<Activation>,
+  /// Fraction of each hidden layer's outputs dropped during training, one rate per hidden
+  /// layer or a single one for all of them
+  pub dropout: Vec<f32>,
+  /// Normalization between each hidden layer's `w . x + b` and its activation, one per
+  /// hidden layer or a single one for all of them
+  pub norm_layers: Vec<NormKind>,
+  pub epochs: usize,
+  /// Number of MNIST training images to use
+  pub training_size: usize,
+  pub batch_size: usize,
+  /// Fraction of the training images held out to validate on after every epoch
+  pub val_split: f32,
+  /// Number of samples the progress bar's rolling loss is averaged over
+  pub rolling_mean_size: usize,
+  /// Seed for every random choice in training; a random one is picked (and recorded) if unset
+  pub seed: Option<u64>,
+  pub optimizer: OptimizerConfig,
+  pub schedule: ScheduleConfig,
+  pub early_stopping: EarlyStoppingConfig,
+  pub preprocessing: PreprocessingConfig,
+  pub regularization: RegularizationConfig,
+}
+
+impl Default for TrainingConfig {
+  fn default() -> Self {
+    TrainingConfig {
+      conv_layers: Vec::new(),
+      layers: vec![784, 128, 10],
+      activations: vec![Activation::Sigmoid],
+      dropout: vec![0.0],
+      norm_layers: vec![NormKind::None],
+      epochs: 15,
+      training_size: TRAINING_SIZE,
+      batch_size: 1,
+      val_split: 0.0,
+      rolling_mean_size: 1000,
+      seed: None,
+      optimizer: OptimizerConfig::default(),
+      schedule: ScheduleConfig::default(),
+      early_stopping: EarlyStoppingConfig::default(),
+      preprocessing: PreprocessingConfig::default(),
+      regularization: RegularizationConfig::default(),
+    }
+  }
+}
+
+impl TrainingConfig {
+  /// Read a config from a `.toml` or `.json` file. Missing keys keep their defaults.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
+    let path = path.as_ref();
+    let contents = std::fs::read_to_string(path)?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+      Some("toml") => Ok(toml::from_str(&contents)?),
+      Some("json") => Ok(serde_json::from_str(&contents)?),
+      _ => Err(
+        format!(
+          "unsupported config file {}, expected .toml or .json",
+          path.display()
+        )
+        .into(),
+      ),
+    }
+  }
+
+  /// `values` as one value per hidden layer, expanding a single value to all of them.
+  fn per_hidden_layer<T: Clone>(&self, values: &[T]) -> Vec<T> {
+    let hidden_layers = self.layers.len().saturating_sub(2);
+    if values.len() == 1 {
+      vec![values[0].clone(); hidden_layers]
+    } else {
+      values.to_vec()
+    }
+  }
+
+  /// One activation per hidden layer, expanding a single activation to all of them.
+  pub fn hidden_activations(&self) -> Vec<Activation> {
+    self.per_hidden_layer(&self.activations)
+  }
+
+  /// One dropout rate per hidden layer, expanding a single rate to all of them.
+  pub fn hidden_dropout(&self) -> Vec<f32> {
+    self.per_hidden_layer(&self.dropout)
+  }
+
+  /// One normalization per hidden layer, expanding a single one to all of them.
+  pub fn hidden_norm_layers(&self) -> Vec<NormKind> {
+    self.per_hidden_layer(&self.norm_layers)
+  }
+
+  pub fn validate(&self) -> Result<(), String> {
+    if let Some(components) = self.preprocessing.pca_components
+      && !(1..=28 * 28).contains(&components)
+    {
+      return Err("PCA components must be between 1 and 784".into());
+    }
+
+    if !self.conv_layers.is_empty() {
+      if self.preprocessing.pca_components.is_some() {
+        return Err("convolutional layers need the whole image, not PCA components".into());
+      }
+      self.conv_output_shape()?;
+    }
+
+    let input_size = self.input_size();
+    if self.layers.len() < 2 || self.layers[0] != input_size || *self.layers.last().unwrap() != 10 {
+      return Err(format!(
+        "layers must start with {} ({}) and end with 10 (digits)",
+        input_size,
+        if !self.conv_layers.is_empty() {
+          "flattened convolutional layer outputs"
+        } else if self.preprocessing.pca_components.is_some() {
+          "PCA components"
+        } else {
+          "input pixels"
+        }
+      ));
+    }
+
+    let hidden_layers = self.layers.len() - 2;
+    if self.hidden_activations().len() != hidden_layers {
+      return Err(format!(
+        "activations needs one value, or one per hidden layer ({} given for {} hidden layers)",
+        self.activations.len(),
+        hidden_layers
+      ));
+    }
+
+    if self.hidden_dropout().len() != hidden_layers {
+      return Err(format!(
+        "dropout needs one rate, or one per hidden layer ({} given for {} hidden layers)",
+        self.dropout.len(),
+        hidden_layers
+      ));
+    }
+    if !self.dropout.iter().all(|rate| (0.0..1.0).contains(rate)) {
+      return Err("dropout rates must be at least 0 and less than 1".into());
+    }
+
+    if self.hidden_norm_layers().len() != hidden_layers {
+      return Err(format!(
+        "norm layers needs one value, or one per hidden layer ({} given for {} hidden layers)",
+        self.norm_layers.len(),
+        hidden_layers
+      ));
+    }
+
+    self.optimizer.validate()?;
+    self.schedule.validate()?;
+    self.early_stopping.validate()?;
+    self.regularization.validate()?;
+
+    if self.rolling_mean_size == 0 {
+      return Err("rolling mean size must be at least 1".into());
+    }
+    if self.batch_size == 0 {
+      return Err("batch size must be at least 1".into());
+    }
+    if self.batch_size < 2 && self.norm_layers.contains(&NormKind::BatchNorm) {
+      return Err("batch norm needs a batch size of at least 2".into());
+    }
+
+    if self.training_size == 0 || self.training_size > TRAINING_SIZE {
+      return Err(format!(
+        "training size must be between 1 and {}",
+        TRAINING_SIZE
+      ));
+    }
+
+    if !(0.0..1.0).contains(&self.val_split) {
+      return Err("validation split must be at least 0 and less than 1".into());
+    }
+    if self.validation_size() >= self.training_size {
+      return Err("validation split leaves no images to train on".into());
+    }
+
+    if self.early_stopping.patience.is_some()
+      && self.early_stopping.metric.needs_validation()
+      && self.validation_size() == 0
+    {
+      return Err(format!(
+        "early stopping on {} needs a validation split (--val-split)",
+        self.early_stopping.metric.name()
+      ));
+    }
+
+    Ok(())
+  }
+
+  /// Check that a run resumed from a checkpoint written with `checkpoint` keeps every
+  /// setting the saved weights, optimizer state, position in the data and random stream
+  /// depend on.
+  pub fn check_resumable(&self, checkpoint: &TrainingConfig) -> Result<(), String> {
+    fn same<T: PartialEq + std::fmt::Debug>(
+      name: &str,
+      resumed: &T,
+      saved: &T,
+    ) -> Result<(), String> {
+      if resumed == saved {
+        Ok(())
+      } else {
+        Err(format!(
+          "cannot change {} when resuming (checkpoint has {:?}, got {:?})",
+          name, saved, resumed
+        ))
+      }
+    }
+
+    same("conv_layers", &self.conv_layers, &checkpoint.conv_layers)?;
+    same("layers", &self.layers, &checkpoint.layers)?;
+    same(
+      "activations",
+      &self.hidden_activations(),
+      &checkpoint.hidden_activations(),
+    )?;
+    same(
+      "norm_layers",
+      &self.hidden_norm_layers(),
+      &checkpoint.hidden_norm_layers(),
+    )?;
+    same(
+      "preprocessing",
+      &self.preprocessing,
+      &checkpoint.preprocessing,
+    )?;
+    same(
+      "training_size",
+      &self.training_size,
+      &checkpoint.training_size,
+    )?;
+    same("batch_size", &self.batch_size, &checkpoint.batch_size)?;
+    same("val_split", &self.val_split, &checkpoint.val_split)?;
+    same("seed", &self.seed, &checkpoint.seed)?;
+    // each dropped layer draws its masks from the run's RNG
+    same(
+      "dropout",
+      &self.hidden_dropout(),
+      &checkpoint.hidden_dropout(),
+    )?;
+    // the best score so far only means something for the metric it was measured with
+    if self.early_stopping.patience.is_some() && checkpoint.early_stopping.patience.is_some() {
+      same(
+        "early_stopping.metric",
+        &self.early_stopping.metric,
+        &checkpoint.early_stopping.metric,
+      )?;
+    }
+    same(
+      "optimizer",
+      &self.optimizer.kind,
+      &checkpoint.optimizer.kind,
+    )
+  }
+
+  /// Shape of the feature maps the convolutional layers produce from a 28x28 image.
+  pub fn conv_output_shape(&self) -> Result<Shape, String> {
+    conv_output_shape(&self.conv_layers, (1, 28, 28))
+  }
+
+  /// Width of the dense layers' input: one value per pixel, per PCA component, or per
+  /// value of the convolutional layers' output.
+  pub fn input_size(&self) -> usize {
+    match self.conv_output_shape() {
+      Ok((channels, height, width)) if !self.conv_layers.is_empty() => channels * height * width,
+      _ => self.preprocessing.pca_components.unwrap_or(28 * 28),
+    }
+  }
+
+  /// Number of the `training_size` images held out for validation.
+  pub fn validation_size(&self) -> usize {
+    (self.training_size as f32 * self.val_split).round() as usize
+  }
+}
+
+/// Preset model architectures for `--arch`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Arch {
+  /// Dense layers only, 784,128,10 unless --layers says otherwise
+  Mlp,
+  /// LeNet-5 style: 5x5 convolutions to 6 then 16 channels, each followed by 2x2 max
+  /// pooling, then dense layers of 120 and 84
+  Lenet,
+}
+
+impl Arch {
+  /// Replace the layers and activations of `config` with the preset's. Flags such as
+  /// `--layers` and `--activations` are applied afterwards and still override them.
+  pub fn apply(self, config: &mut TrainingConfig) {
+    match self {
+      Arch::Mlp => {
+        let defaults = TrainingConfig::default();
+        config.conv_layers = defaults.conv_layers;
+        config.layers = defaults.layers;
+        config.activations = defaults.activations;
+      }
+      Arch::Lenet => {
+        let conv = |channels, padding| ConvLayerConfig::Conv2d {
+          channels,
+          kernel_size: 5,
+          stride: 1,
+          padding,
+          activation: Activation::Relu,
+        };
+        let pool = ConvLayerConfig::MaxPool2d {
+          size: 2,
+          stride: None,
+        };
+        // 28x28 -> 6x28x28 -> 6x14x14 -> 16x10x10 -> 16x5x5
+        config.conv_layers = vec![conv(6, 2), pool.clone(), conv(16, 0), pool];
+        config.layers = vec![16 * 5 * 5, 120, 84, 10];
+        config.activations = vec![Activation::Relu];
+      }
+    }
+  }
+}
+
+/// Command line flags for `train`. Every flag is optional so that only the ones given
+/// override the `--config` file.
+#[derive(Args, Debug)]
+pub struct TrainArgs {
+  /// TOML or JSON file with training settings; flags given on the command line take precedence
+  #[arg(long)]
+  pub config: Option<String>,
+
+  /// Model architecture preset, replacing the layers of --config; the other layer flags still apply on top of it
+  #[arg(long, value_enum)]
+  pub arch: Option<Arch>,
+
+  /// Comma separated dense layer widths, including input and output (e.g. 784,256,128,10) [default: 784,128,10]
+  #[arg(long, value_delimiter = ',')]
+  pub layers: Option<Vec<usize>>,
+
+  /// Comma separated hidden layer activations, one for all hidden layers or one per layer [default: sigmoid]
+  #[arg(long, value_enum, value_delimiter = ',')]
+  pub activations: Option<Vec<Activation>>,
+
+  /// Comma separated fraction of hidden layer outputs to drop while training, one for all hidden layers or one per layer (e.g. 0.2) [default: 0]
+  #[arg(long, value_delimiter = ',')]
+  pub dropout: Option<Vec<f32>>,
+
+  /// Comma separated normalization applied before each hidden layer's activation, one for all hidden layers or one per layer [default: none]
+  #[arg(long, value_enum, value_delimiter = ',')]
+  pub norm_layers: Option<Vec<NormKind>>,
+
+  /// Number of passes over the training set [default: 15]
+  #[arg(long)]
+  pub epochs: Option<usize>,
+
+  /// Number of MNIST training images to use [default: 60000]
+  #[arg(long)]
+  pub training_size: Option<usize>,
+
+  /// Number of images per gradient update; gradients are averaged over the batch [default: 1]
+  #[arg(long)]
+  pub batch_size: Option<usize>,
+
+  /// Fraction of the training images held out and evaluated after every epoch (e.g. 0.1) [default: 0]
+  #[arg(long)]
+  pub val_split: Option<f32>,
+
+  /// Number of samples the progress bar's rolling loss is averaged over [default: 1000]
+  #[arg(long)]
+  pub rolling_mean_size: Option<usize>,
+
+  /// Seed for weight initialization and data shuffling [default: random]
+  #[arg(long)]
+  pub seed: Option<u64>,
+
+  /// Optimizer used to update the weights [default: sgd]
+  #[arg(long, value_enum)]
+  pub optimizer: Option<OptimizerKind>,
+
+  /// Learning rate [default: 0.001]
+  #[arg(long)]
+  pub lr: Option<f32>,
+
+  /// Momentum coefficient (momentum and nesterov optimizers) [default: 0.9]
+  #[arg(long)]
+  pub momentum: Option<f32>,
+
+  /// Decoupled weight decay (adamw optimizer) [default: 0.01]
+  #[arg(long)]
+  pub weight_decay: Option<f32>,
+
+  /// Learning rate schedule [default: constant]
+  #[arg(long, value_enum)]
+  pub lr_schedule: Option<ScheduleKind>,
+
+  /// Linearly ramp the learning rate up from zero over this many steps [default: 0]
+  #[arg(long)]
+  pub warmup_steps: Option<usize>,
+
+  /// Epochs between decays (step schedule) [default: 5]
+  #[arg(long)]
+  pub lr_step_size: Option<usize>,
+
+  /// Decay factor (step, exponential and plateau schedules) [default: 0.5]
+  #[arg(long)]
+  pub lr_gamma: Option<f32>,
+
+  /// Length in epochs of the first cosine cycle [default: 5]
+  #[arg(long)]
+  pub lr_cycle_epochs: Option<f32>,
+
+  /// Each cosine cycle is this many times longer than the previous one [default: 1]
+  #[arg(long)]
+  pub lr_cycle_mult: Option<f32>,
+
+  /// Lower bound for the cosine and plateau schedules [default: 0]
+  #[arg(long)]
+  pub lr_min: Option<f32>,
+
+  /// Epochs without improvement before the plateau schedule decays [default: 2]
+  #[arg(long)]
+  pub lr_patience: Option<usize>,
+
+  /// L1 penalty coefficient on the weights, applied with every optimizer [default: 0]
+  #[arg(long)]
+  pub l1: Option<f32>,
+
+  /// L2 penalty coefficient on the weights (weight decay of l2 * w added to the gradients), applied with every optimizer; with adamw it comes on top of --weight-decay [default: 0]
+  #[arg(long)]
+  pub l2: Option<f32>,
+
+  /// Rescale each neuron's incoming weights after every step so their L2 norm stays below this [default: off]
+  #[arg(long)]
+  pub max_norm: Option<f32>,
+
+  /// How pixels are normalized before entering the network [default: scale]
+  #[arg(long, value_enum)]
+  pub normalize: Option<Normalization>,
+
+  /// Whiten the inputs onto this many principal components of the training set; --layers must then start with this width [default: off]
+  #[arg(long)]
+  pub pca_components: Option<usize>,
+
+  /// Stop training once the monitored metric has not improved for this many epochs, keeping the best weights [default: off]
+  #[arg(long)]
+  pub early_stopping_patience: Option<usize>,
+
+  /// Metric early stopping monitors [default: val-loss]
+  #[arg(long, value_enum)]
+  pub early_stopping_metric: Option<StopMetric>,
+
+  /// Smallest change in the monitored metric that counts as an improvement [default: 0]
+  #[arg(long)]
+  pub early_stopping_min_delta: Option<f32>,
+}
+
+impl TrainArgs {
+  /// Resolve the final config: `base` (or the defaults), overridden by the `--config`
+  /// file, overridden by any flags given on the command line.
+  pub fn resolve(
+    &self,
+    base: Option<TrainingConfig>,
+  ) -> Result<TrainingConfig, Box<dyn std::error::Error>> {
+    let mut config = match &self.config {
+      Some(path) => {
+        TrainingConfig::load(path).map_err(|e| format!("failed to read config {}: {}", path, e))?
+      }
+      None => base.unwrap_or_default(),
+    };
+
+    fn set<T: Clone>(target: &mut T, value: &Option<T>) {
+      if let Some(value) = value {
+        *target = value.clone();
+      }
+    }
+
+    if let Some(arch) = self.arch {
+      arch.apply(&mut config);
+    }
+    set(&mut config.layers, &self.layers);
+    set(&mut config.activations, &self.activations);
+    set(&mut config.dropout, &self.dropout);
+    set(&mut config.norm_layers, &self.norm_layers);
+    set(&mut config.epochs, &self.epochs);
+    set(&mut config.training_size, &self.training_size);
+    set(&mut config.batch_size, &self.batch_size);
+    set(&mut config.val_split, &self.val_split);
+    set(&mut config.rolling_mean_size, &self.rolling_mean_size);
+    if self.seed.is_some() {
+      config.seed = self.seed;
+    }
+
+    set(&mut config.optimizer.kind, &self.optimizer);
+    set(&mut config.optimizer.lr, &self.lr);
+    set(&mut config.optimizer.momentum, &self.momentum);
+    set(&mut config.optimizer.weight_decay, &self.weight_decay);
+
+    set(&mut config.schedule.kind, &self.lr_schedule);
+    set(&mut config.schedule.warmup_steps, &self.warmup_steps);
+    set(&mut config.schedule.step_size, &self.lr_step_size);
+    set(&mut config.schedule.gamma, &self.lr_gamma);
+    set(&mut config.schedule.cycle_epochs, &self.lr_cycle_epochs);
+    set(&mut config.schedule.cycle_mult, &self.lr_cycle_mult);
+    set(&mut config.schedule.min_lr, &self.lr_min);
+    set(&mut config.schedule.patience, &self.lr_patience);
+
+    set(&mut config.regularization.l1, &self.l1);
+    set(&mut config.regularization.l2, &self.l2);
+    if self.max_norm.is_some() {
+      config.regularization.max_norm = self.max_norm;
+    }
+
+    set(&mut config.preprocessing.normalization, &self.normalize);
+    if self.pca_components.is_some() {
+      config.preprocessing.pca_components = self.pca_components;
+    }
+
+    if self.early_stopping_patience.is_some() {
+      config.early_stopping.patience = self.early_stopping_patience;
+    }
+    set(
+      &mut config.early_stopping.metric,
+      &self.early_stopping_metric,
+    );
+    set(
+      &mut config.early_stopping.min_delta,
+      &self.early_stopping_min_delta,
+    );
+
+    // record the seed we ended up using so the run can be reproduced
+    config.seed.get_or_insert_with(rand::random);
+
+    config.validate()?;
+    Ok(config)
+  }
+}
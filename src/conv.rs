@@ -0,0 +1,589 @@
+use clap::ValueEnum;
+use ndarray::{Array2, Array4};
+use rand::Rng;
+use rand::distr::Uniform;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::activation::Activation;
+use crate::autograd::{Tape, Var};
+
+/// Shape of one image or feature map: channels, height, width.
+pub type Shape = (usize, usize, usize);
+
+/// `dims` (channels, height, width) as a `Shape`.
+pub fn shape_of(dims: &[usize]) -> Option<Shape> {
+  match *dims {
+    [channels, height, width] => Some((channels, height, width)),
+    _ => None,
+  }
+}
+
+/// A 2d convolution with square `kernel_size x kernel_size` kernels over every input
+/// channel, followed by an activation. `w` holds one row per output channel with its
+/// weights laid out channel by channel, then row by row, which is the order of the rows
+/// `im2col` builds, so the whole convolution is one matrix product.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Conv2d {
+  /// `out_channels x (in_channels * kernel_size * kernel_size)`
+  pub w: Array2<f32>,
+  /// `out_channels x 1`
+  pub b: Array2<f32>,
+  pub in_channels: usize,
+  pub kernel_size: usize,
+  pub stride: usize,
+  /// Zeros added on every side of the input.
+  pub padding: usize,
+  pub activation: Activation,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PoolKind {
+  Max,
+  Avg,
+}
+
+/// Max or average pooling of each channel over `size x size` windows `stride` apart.
+/// Windows that would run past the edge are left out.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Pool2d {
+  pub kind: PoolKind,
+  pub size: usize,
+  pub stride: usize,
+}
+
+/// A layer working on feature maps, before the flatten into the dense layers.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum ConvLayer {
+  Conv2d(Conv2d),
+  Pool2d(Pool2d),
+}
+
+/// One entry of `TrainingConfig::conv_layers`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ConvLayerConfig {
+  Conv2d {
+    /// Number of output channels (filters)
+    channels: usize,
+    kernel_size: usize,
+    #[serde(default = "default_stride")]
+    stride: usize,
+    #[serde(default)]
+    padding: usize,
+    activation: Activation,
+  },
+  MaxPool2d {
+    size: usize,
+    /// Defaults to `size`, i.e. windows that don't overlap
+    stride: Option<usize>,
+  },
+  AvgPool2d {
+    size: usize,
+    stride: Option<usize>,
+  },
+}
+
+fn default_stride() -> usize {
+  1
+}
+
+impl ConvLayerConfig {
+  /// A randomly initialized layer reading `in_channels` channels, with weights drawn
+  /// from `rng`.
+  pub fn build<R: Rng>(&self, in_channels: usize, rng: &mut R) -> ConvLayer {
+    match *self {
+      ConvLayerConfig::Conv2d {
+        channels,
+        kernel_size,
+        stride,
+        padding,
+        activation,
+      } => ConvLayer::Conv2d(Conv2d::new(
+        in_channels,
+        channels,
+        kernel_size,
+        stride,
+        padding,
+        activation,
+        rng,
+      )),
+      ConvLayerConfig::MaxPool2d { size, stride } => ConvLayer::Pool2d(Pool2d {
+        kind: PoolKind::Max,
+        size,
+        stride: stride.unwrap_or(size),
+      }),
+      ConvLayerConfig::AvgPool2d { size, stride } => ConvLayer::Pool2d(Pool2d {
+        kind: PoolKind::Avg,
+        size,
+        stride: stride.unwrap_or(size),
+      }),
+    }
+  }
+
+  /// Shape of the layer's output for an `input` of the given shape, or why it doesn't fit.
+  pub fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+    match *self {
+      ConvLayerConfig::Conv2d {
+        channels,
+        kernel_size,
+        stride,
+        padding,
+        ..
+      } => {
+        if channels == 0 {
+          return Err("needs at least 1 output channel".into());
+        }
+        let (height, width) = window_output(input, kernel_size, stride, padding)?;
+        Ok((channels, height, width))
+      }
+      ConvLayerConfig::MaxPool2d { size, stride } | ConvLayerConfig::AvgPool2d { size, stride } => {
+        let (height, width) = window_output(input, size, stride.unwrap_or(size), 0)?;
+        Ok((input.0, height, width))
+      }
+    }
+  }
+}
+
+/// Output height and width of `size x size` windows `stride` apart over `input` padded
+/// with `padding` zeros on every side.
+fn window_output(
+  input: Shape,
+  size: usize,
+  stride: usize,
+  padding: usize,
+) -> Result<(usize, usize), String> {
+  if size == 0 || stride == 0 {
+    return Err("needs a window size and stride of at least 1".into());
+  }
+  let (_, height, width) = input;
+  let (height, width) = (height + 2 * padding, width + 2 * padding);
+  if size > height || size > width {
+    return Err(format!(
+      "does not fit: {}x{} windows over {}x{} inputs",
+      size, size, height, width
+    ));
+  }
+  Ok(((height - size) / stride + 1, (width - size) / stride + 1))
+}
+
+/// Shape of the feature maps the `layers` produce from `input` images, or which layer
+/// doesn't fit and why.
+pub fn conv_output_shape(layers: &[ConvLayerConfig], input: Shape) -> Result<Shape, String> {
+  layers
+    .iter()
+    .enumerate()
+    .try_fold(input, |shape, (i, layer)| {
+      layer
+        .output_shape(shape)
+        .map_err(|e| format!("convolutional layer {} {}", i + 1, e))
+    })
+}
+
+/// What a pooling layer's forward pass keeps for its backward pass.
+pub struct PoolCache {
+  /// For max pooling, the index into the input of the value each output took.
+  argmax: Vec<usize>,
+  input_dim: (usize, usize, usize, usize),
+}
+
+/// Gradients for a `Conv2d`'s `w` and `b`, averaged over the samples in the batch.
+pub struct ConvGradients {
+  pub dw: Array2<f32>,
+  pub db: Array2<f32>,
+}
+
+impl Conv2d {
+  pub fn new<R: Rng>(
+    in_channels: usize,
+    out_channels: usize,
+    kernel_size: usize,
+    stride: usize,
+    padding: usize,
+    activation: Activation,
+    rng: &mut R,
+  ) -> Self {
+    // He uniform initialization: kernels see far fewer inputs than dense layers, so the
+    // dense layers' fixed -0.5..0.5 range would leave their outputs tiny
+    let fan_in = in_channels * kernel_size * kernel_size;
+    let limit = (6.0 / fan_in as f32).sqrt();
+    let uniform = Uniform::new(-limit, limit).unwrap();
+    Conv2d {
+      w: Array2::from_shape_simple_fn((out_channels, fan_in), || rng.sample(uniform)),
+      b: Array2::zeros((out_channels, 1)),
+      in_channels,
+      kernel_size,
+      stride,
+      padding,
+      activation,
+    }
+  }
+
+  pub fn out_channels(&self) -> usize {
+    self.w.nrows()
+  }
+
+  /// Output height and width for an input of `input_dim`.
+  fn output_size(&self, input_dim: (usize, usize, usize, usize)) -> (usize, usize) {
+    let (_, channels, height, width) = input_dim;
+    window_output(
+      (channels, height, width),
+      self.kernel_size,
+      self.stride,
+      self.padding,
+    )
+    .expect("input does not fit the layer")
+  }
+
+  /// Call `f(row, column, input_index)` for every kernel tap that lands inside the input
+  /// (`batch x in_channels x height x width`, flattened in that order): `row` is the tap's
+  /// row in the `im2col` matrix and `column` the output position, batch then row then
+  /// column. Taps over the padding are skipped.
+  fn for_each_tap(
+    &self,
+    input_dim: (usize, usize, usize, usize),
+    output: (usize, usize),
+    mut f: impl FnMut(usize, usize, usize),
+  ) {
+    let (batch, channels, height, width) = input_dim;
+    let (out_height, out_width) = output;
+    let k = self.kernel_size;
+    let position = |out: usize, offset: usize, size: usize| {
+      (out * self.stride + offset)
+        .checked_sub(self.padding)
+        .filter(|&i| i < size)
+    };
+    for c in 0..channels {
+      for ky in 0..k {
+        for kx in 0..k {
+          let row = (c * k + ky) * k + kx;
+          for b in 0..batch {
+            let plane = (b * channels + c) * height;
+            for oy in 0..out_height {
+              let Some(iy) = position(oy, ky, height) else {
+                continue;
+              };
+              for ox in 0..out_width {
+                if let Some(ix) = position(ox, kx, width) {
+                  f(
+                    row,
+                    (b * out_height + oy) * out_width + ox,
+                    (plane + iy) * width + ix,
+                  );
+                }
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+
+  /// Unfold every kernel-sized patch of `x` into a column, so that `w . cols` is the
+  /// convolution: `(in_channels * k * k) x (batch * out_height * out_width)`.
+  fn im2col(&self, x: &Array4<f32>, output: (usize, usize)) -> Array2<f32> {
+    let input_dim = x.dim();
+    let (batch, channels, _, _) = input_dim;
+    let columns = batch * output.0 * output.1;
+    let rows = channels * self.kernel_size * self.kernel_size;
+    let x = x.as_standard_layout();
+    let x = x.as_slice().unwrap();
+    let mut cols = vec![0.0; rows * columns];
+    self.for_each_tap(input_dim, output, |row, column, i| {
+      cols[row * columns + column] = x[i];
+    });
+    Array2::from_shape_vec((rows, columns), cols).unwrap()
+  }
+
+  /// The reverse of `im2col`: add each column's values back onto the input positions
+  /// they were read from.
+  fn col2im(
+    &self,
+    cols: &Array2<f32>,
+    input_dim: (usize, usize, usize, usize),
+    output: (usize, usize),
+  ) -> Array4<f32> {
+    let columns = cols.ncols();
+    let cols = cols.as_standard_layout();
+    let cols = cols.as_slice().unwrap();
+    let mut x = Array4::zeros(input_dim);
+    let values = x.as_slice_mut().unwrap();
+    self.for_each_tap(input_dim, output, |row, column, i| {
+      values[i] += cols[row * columns + column];
+    });
+    x
+  }
+
+  /// Run the convolution on `x` (`batch x channels x height x width`).
+  pub fn apply(&self, x: &Array4<f32>) -> Array4<f32> {
+    let output = self.output_size(x.dim());
+    let z = &self.w.dot(&self.im2col(x, output)) + &self.b;
+    to_nchw(&self.activation.apply(&z), x.dim().0, output)
+  }
+
+  /// Record the convolution on `tape`, reading `x`: one `input_shape` sample per column,
+  /// flattened. Returns the output, flattened the same way, and the variables of `w` and
+  /// `b`. Unfolding the patches and putting the output back in sample order are custom
+  /// operations that only move values around; the product with `w`, the bias and the
+  /// activation are the tape's own.
+  fn record(&self, tape: &Tape, x: Var, input_shape: Shape) -> (Var, (Var, Var)) {
+    let (channels, height, width) = input_shape;
+    let batch = tape.value(x).ncols();
+    let input_dim = (batch, channels, height, width);
+    let output = self.output_size(input_dim);
+
+    let unfolded = self.im2col(&unflatten(&tape.value(x), input_shape), output);
+    let conv = self.clone();
+    let cols = tape.custom(
+      &[x],
+      unfolded,
+      Box::new(move |grad, needed| {
+        vec![needed[0].then(|| flatten(&conv.col2im(grad, input_dim, output)))]
+      }),
+    );
+
+    let w = tape.parameter(self.w.clone());
+    let b = tape.parameter(self.b.clone());
+    let a = tape.activation(tape.add(tape.matmul(w, cols), b), self.activation);
+
+    let output_shape = (self.out_channels(), output.0, output.1);
+    let samples = flatten(&to_nchw(&tape.value(a), batch, output));
+    let y = tape.custom(
+      &[a],
+      samples,
+      Box::new(move |grad, needed| {
+        vec![needed[0].then(|| from_nchw(&unflatten(grad, output_shape)))]
+      }),
+    );
+    (y, (w, b))
+  }
+}
+
+impl Pool2d {
+  /// Index into the input (flattened `batch x channels x height x width`) of every value
+  /// in each output position's window, outputs in `batch x channels x height x width`
+  /// order.
+  fn windows(
+    &self,
+    input_dim: (usize, usize, usize, usize),
+    output: (usize, usize),
+  ) -> impl Iterator<Item = impl Iterator<Item = usize>> {
+    let (batch, channels, height, width) = input_dim;
+    let (out_height, out_width) = output;
+    let (size, stride) = (self.size, self.stride);
+    (0..batch * channels).flat_map(move |plane| {
+      (0..out_height).flat_map(move |oy| {
+        (0..out_width).map(move |ox| {
+          (0..size).flat_map(move |ky| {
+            (0..size).map(move |kx| (plane * height + oy * stride + ky) * width + ox * stride + kx)
+          })
+        })
+      })
+    })
+  }
+
+  /// Output height and width for an input of `input_dim`.
+  fn output_size(&self, input_dim: (usize, usize, usize, usize)) -> (usize, usize) {
+    let (_, channels, height, width) = input_dim;
+    window_output((channels, height, width), self.size, self.stride, 0)
+      .expect("input does not fit the layer")
+  }
+
+  /// Pool `x` (`batch x channels x height x width`), keeping what the backward pass
+  /// needs.
+  pub fn forward(&self, x: &Array4<f32>) -> (Array4<f32>, PoolCache) {
+    let input_dim = x.dim();
+    let output = self.output_size(input_dim);
+    let x = x.as_standard_layout();
+    let x = x.as_slice().unwrap();
+    let mut argmax = Vec::new();
+    let values: Vec<f32> = self
+      .windows(input_dim, output)
+      .map(|window| match self.kind {
+        PoolKind::Max => {
+          let best = window
+            .reduce(|best, i| if x[i] > x[best] { i } else { best })
+            .unwrap();
+          argmax.push(best);
+          x[best]
+        }
+        PoolKind::Avg => window.map(|i| x[i]).sum::<f32>() / (self.size * self.size) as f32,
+      })
+      .collect();
+    let (batch, channels, _, _) = input_dim;
+    let y = Array4::from_shape_vec((batch, channels, output.0, output.1), values).unwrap();
+    (y, PoolCache { argmax, input_dim })
+  }
+
+  /// Back-propagate `dy`, the gradient with respect to the pooled output, to the input.
+  pub fn backward(&self, dy: &Array4<f32>, cache: &PoolCache) -> Array4<f32> {
+    let input_dim = cache.input_dim;
+    let dy = dy.as_standard_layout();
+    let dy = dy.as_slice().unwrap();
+    let mut dx = Array4::zeros(input_dim);
+    let values = dx.as_slice_mut().unwrap();
+    match self.kind {
+      // only the largest value in each window affected the output
+      PoolKind::Max => {
+        for (&i, &d) in cache.argmax.iter().zip(dy) {
+          values[i] += d;
+        }
+      }
+      PoolKind::Avg => {
+        let share = 1.0 / (self.size * self.size) as f32;
+        for (window, &d) in self.windows(input_dim, self.output_size(input_dim)).zip(dy) {
+          for i in window {
+            values[i] += d * share;
+          }
+        }
+      }
+    }
+    dx
+  }
+
+  /// Record the pooling on `tape`, reading `x` as `Conv2d::record` does. The tape runs
+  /// `backward` to route each gradient back to the values its window read.
+  fn record(&self, tape: &Tape, x: Var, input_shape: Shape) -> Var {
+    let (y, cache) = self.forward(&unflatten(&tape.value(x), input_shape));
+    let (_, channels, height, width) = y.dim();
+    let output_shape = (channels, height, width);
+    let pool = self.clone();
+    let backward = move |grad: &Array2<f32>, needed: &[bool]| {
+      vec![needed[0].then(|| flatten(&pool.backward(&unflatten(grad, output_shape), &cache)))]
+    };
+    tape.custom(&[x], flatten(&y), Box::new(backward))
+  }
+}
+
+/// The result of a convolution's matrix product, `channels x (batch * height * width)`,
+/// as `batch x channels x height x width`.
+fn to_nchw(z: &Array2<f32>, batch: usize, output: (usize, usize)) -> Array4<f32> {
+  let channels = z.nrows();
+  z.as_standard_layout()
+    .into_owned()
+    .into_shape_with_order((channels, batch, output.0, output.1))
+    .unwrap()
+    .permuted_axes([1, 0, 2, 3])
+    .as_standard_layout()
+    .into_owned()
+}
+
+/// The reverse of `to_nchw`: `channels x (batch * height * width)`.
+fn from_nchw(x: &Array4<f32>) -> Array2<f32> {
+  let (batch, channels, height, width) = x.dim();
+  x.view()
+    .permuted_axes([1, 0, 2, 3])
+    .as_standard_layout()
+    .into_owned()
+    .into_shape_with_order((channels, batch * height * width))
+    .unwrap()
+}
+
+impl ConvLayer {
+  pub fn as_conv2d(&self) -> Option<&Conv2d> {
+    match self {
+      ConvLayer::Conv2d(conv) => Some(conv),
+      ConvLayer::Pool2d(_) => None,
+    }
+  }
+
+  pub fn as_conv2d_mut(&mut self) -> Option<&mut Conv2d> {
+    match self {
+      ConvLayer::Conv2d(conv) => Some(conv),
+      ConvLayer::Pool2d(_) => None,
+    }
+  }
+
+  /// Shape of the layer's output for an `input` of the given shape, or why it doesn't fit.
+  pub fn output_shape(&self, input: Shape) -> Result<Shape, String> {
+    match self {
+      ConvLayer::Conv2d(conv) => {
+        if input.0 != conv.in_channels {
+          return Err(format!(
+            "expects {} channels but receives {}",
+            conv.in_channels, input.0
+          ));
+        }
+        let (height, width) = window_output(input, conv.kernel_size, conv.stride, conv.padding)?;
+        Ok((conv.out_channels(), height, width))
+      }
+      ConvLayer::Pool2d(pool) => {
+        let (height, width) = window_output(input, pool.size, pool.stride, 0)?;
+        Ok((input.0, height, width))
+      }
+    }
+  }
+
+  /// Run the layer on `x` (`batch x channels x height x width`).
+  pub fn apply(&self, x: &Array4<f32>) -> Array4<f32> {
+    match self {
+      ConvLayer::Conv2d(conv) => conv.apply(x),
+      ConvLayer::Pool2d(pool) => pool.forward(x).0,
+    }
+  }
+
+  /// Record the layer on `tape`, reading `x`: one `input_shape` sample per column,
+  /// flattened. Returns the output, flattened the same way, and for a `Conv2d` the
+  /// variables of its `w` and `b`.
+  pub fn record(&self, tape: &Tape, x: Var, input_shape: Shape) -> (Var, Option<(Var, Var)>) {
+    match self {
+      ConvLayer::Conv2d(conv) => {
+        let (y, vars) = conv.record(tape, x, input_shape);
+        (y, Some(vars))
+      }
+      ConvLayer::Pool2d(pool) => (pool.record(tape, x, input_shape), None),
+    }
+  }
+}
+
+impl fmt::Display for ConvLayer {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ConvLayer::Conv2d(conv) => write!(
+        f,
+        "conv2d {}->{} {}x{} stride {} padding {} {}",
+        conv.in_channels,
+        conv.out_channels(),
+        conv.kernel_size,
+        conv.kernel_size,
+        conv.stride,
+        conv.padding,
+        conv.activation.to_possible_value().unwrap().get_name()
+      ),
+      ConvLayer::Pool2d(pool) => write!(
+        f,
+        "{} {}x{} stride {}",
+        match pool.kind {
+          PoolKind::Max => "max-pool2d",
+          PoolKind::Avg => "avg-pool2d",
+        },
+        pool.size,
+        pool.size,
+        pool.stride
+      ),
+    }
+  }
+}
+
+/// The flatten between the convolutional and the dense layers: each sample's `channels x
+/// height x width` values, in that order, become one column (features x batch).
+pub fn flatten(x: &Array4<f32>) -> Array2<f32> {
+  let (batch, channels, height, width) = x.dim();
+  x.as_standard_layout()
+    .into_owned()
+    .into_shape_with_order((batch, channels * height * width))
+    .unwrap()
+    .reversed_axes()
+}
+
+/// The reverse of `flatten`, for inputs and gradients: each column's values as a `shape`
+/// feature map.
+pub fn unflatten(x: &Array2<f32>, shape: Shape) -> Array4<f32> {
+  let (channels, height, width) = shape;
+  x.t()
+    .as_standard_layout()
+    .into_owned()
+    .into_shape_with_order((x.ncols(), channels, height, width))
+    .unwrap()
+}
@@ -0,0 +1,162 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::stats::TrainingStats;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum StopMetric {
+  /// Mean loss on the validation split (needs `--val-split`)
+  ValLoss,
+  /// Accuracy on the validation split (needs `--val-split`)
+  ValAccuracy,
+  /// Mean training loss of the epoch
+  TrainLoss,
+  /// Training accuracy of the epoch
+  TrainAccuracy,
+}
+
+impl StopMetric {
+  pub fn needs_validation(&self) -> bool {
+    matches!(self, StopMetric::ValLoss | StopMetric::ValAccuracy)
+  }
+
+  fn higher_is_better(&self) -> bool {
+    matches!(self, StopMetric::ValAccuracy | StopMetric::TrainAccuracy)
+  }
+
+  pub fn name(&self) -> &'static str {
+    match self {
+      StopMetric::ValLoss => "val-loss",
+      StopMetric::ValAccuracy => "val-accuracy",
+      StopMetric::TrainLoss => "train-loss",
+      StopMetric::TrainAccuracy => "train-accuracy",
+    }
+  }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EarlyStoppingConfig {
+  pub metric: StopMetric,
+  /// Epochs without improvement before training stops; early stopping is off if unset
+  pub patience: Option<usize>,
+  /// Smallest change in the metric that counts as an improvement
+  pub min_delta: f32,
+}
+
+impl Default for EarlyStoppingConfig {
+  fn default() -> Self {
+    EarlyStoppingConfig {
+      metric: StopMetric::ValLoss,
+      patience: None,
+      min_delta: 0.0,
+    }
+  }
+}
+
+impl EarlyStoppingConfig {
+  pub fn validate(&self) -> Result<(), String> {
+    // a patience of 0 would stop after the first epoch, improved or not
+    if self.patience == Some(0) {
+      return Err("early stopping patience must be at least 1".into());
+    }
+    if !(self.min_delta.is_finite() && self.min_delta >= 0.0) {
+      return Err("early stopping minimum delta must not be negative".into());
+    }
+    Ok(())
+  }
+}
+
+/// Tracks the monitored metric across epochs and decides when to stop.
+///
+/// Serializable so checkpoints can restore the best score and patience counter.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EarlyStopping {
+  config: EarlyStoppingConfig,
+  best_score: Option<f32>,
+  /// Number of epochs completed when the best score was reached.
+  best_epoch: usize,
+  bad_epochs: usize,
+}
+
+impl EarlyStopping {
+  pub fn new(config: &EarlyStoppingConfig) -> Self {
+    EarlyStopping {
+      config: config.clone(),
+      best_score: None,
+      best_epoch: 0,
+      bad_epochs: 0,
+    }
+  }
+
+  /// Continue with the patience and minimum improvement of `config`, e.g. when resuming
+  /// with new flags, keeping the best score and the count of epochs without improvement.
+  /// The metric must be the same, scores of different metrics can't be compared.
+  pub fn reconfigure(&mut self, config: &EarlyStoppingConfig) {
+    assert_eq!(
+      self.config.metric, config.metric,
+      "early stopping can't switch metrics"
+    );
+    self.config = config.clone();
+  }
+
+  pub fn best_epoch(&self) -> usize {
+    self.best_epoch
+  }
+
+  /// Report the stats of a finished epoch (`epochs_done` counts it). Returns whether it
+  /// scored better than every epoch before it.
+  pub fn end_epoch(
+    &mut self,
+    epochs_done: usize,
+    train: &TrainingStats,
+    validation: Option<&TrainingStats>,
+  ) -> bool {
+    let stats = if self.config.metric.needs_validation() {
+      validation.expect("early stopping on a validation metric needs a validation split")
+    } else {
+      train
+    };
+    let score = if self.config.metric.higher_is_better() {
+      stats.accuracy()
+    } else {
+      stats.mean_loss()
+    };
+
+    let improved = match self.best_score {
+      None => true,
+      Some(best) if self.config.metric.higher_is_better() => score > best + self.config.min_delta,
+      Some(best) => score < best - self.config.min_delta,
+    };
+
+    if improved {
+      self.best_score = Some(score);
+      self.best_epoch = epochs_done;
+      self.bad_epochs = 0;
+    } else {
+      self.bad_epochs += 1;
+    }
+    improved
+  }
+
+  /// Whether the metric has gone `patience` epochs without improving.
+  pub fn should_stop(&self) -> bool {
+    self
+      .config
+      .patience
+      .is_some_and(|patience| self.bad_epochs >= patience)
+  }
+
+  /// Human readable reason for stopping, for the training log.
+  pub fn reason(&self) -> String {
+    format!(
+      "{} has not improved by more than {} for {} epochs; best was {:.4} after epoch {}",
+      self.config.metric.name(),
+      self.config.min_delta,
+      self.bad_epochs,
+      self.best_score.unwrap_or(f32::NAN),
+      self.best_epoch
+    )
+  }
+}
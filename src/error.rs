@@ -0,0 +1,209 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Why a model file could not be loaded.
+#[derive(Debug)]
+pub enum ModelError {
+  /// The file could not be read.
+  Io(std::io::Error),
+  /// The file is not valid safetensors.
+  InvalidFile(String),
+  /// The file is not a valid ONNX protobuf.
+  InvalidOnnx(String),
+  /// An ONNX graph uses an operator that cannot be imported.
+  UnsupportedOp {
+    op: String,
+    node: String,
+  },
+  /// An ONNX graph is not a chain of layers that can be imported.
+  UnsupportedGraph(String),
+  /// A metadata entry is missing pieces or is not valid JSON.
+  InvalidMetadata {
+    key: String,
+    message: String,
+  },
+  /// The file was written in a format version this build does not understand.
+  UnsupportedVersion {
+    found: Option<u64>,
+    supported: RangeInclusive<u32>,
+  },
+  /// The file holds no layers at all.
+  NoLayers,
+  MissingTensor {
+    name: String,
+  },
+  /// A tensor is stored with a dtype other than the ones it may have.
+  WrongDtype {
+    name: String,
+    dtype: String,
+    expected: Vec<String>,
+  },
+  /// A tensor does not have the number of dimensions expected of it.
+  WrongRank {
+    name: String,
+    expected: usize,
+    shape: Vec<usize>,
+  },
+  /// A tensor's data is shorter or longer than its shape requires.
+  TruncatedData {
+    name: String,
+    expected_bytes: usize,
+    actual_bytes: usize,
+  },
+  /// A tensor's shape does not fit the layer it belongs to.
+  ShapeMismatch {
+    name: String,
+    expected: Vec<usize>,
+    actual: Vec<usize>,
+  },
+  /// A layer's input width differs from the output width of whatever feeds it.
+  LayerMismatch {
+    layer: usize,
+    inputs: usize,
+    received: usize,
+  },
+  /// A convolutional layer does not fit the feature maps it receives.
+  ConvMismatch {
+    layer: usize,
+    message: String,
+  },
+  /// The number of class labels differs from the number of outputs.
+  LabelMismatch {
+    outputs: usize,
+    labels: usize,
+  },
+  /// A tensor contains NaN or infinite values.
+  NonFinite {
+    name: String,
+    count: usize,
+  },
+  /// A batch norm's running variance has negative entries.
+  NegativeVariance {
+    name: String,
+    count: usize,
+  },
+}
+
+impl fmt::Display for ModelError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ModelError::Io(e) => write!(f, "could not read model file: {}", e),
+      ModelError::InvalidFile(message) => write!(f, "not a valid safetensors file: {}", message),
+      ModelError::InvalidOnnx(message) => write!(f, "not a valid ONNX file: {}", message),
+      ModelError::UnsupportedOp { op, node } => write!(
+        f,
+        "ONNX node {} uses unsupported operator {} (supported: {})",
+        node,
+        op,
+        crate::onnx::import::SUPPORTED_OPS
+      ),
+      ModelError::UnsupportedGraph(message) => write!(f, "unsupported ONNX graph: {}", message),
+      ModelError::InvalidMetadata { key, message } => {
+        write!(f, "invalid \"{}\" metadata: {}", key, message)
+      }
+      ModelError::UnsupportedVersion { found, supported } => match found {
+        Some(found) => write!(
+          f,
+          "unsupported model format version {}, this build reads versions {} to {}",
+          found,
+          supported.start(),
+          supported.end()
+        ),
+        None => write!(
+          f,
+          "model architecture has no format version, this build reads versions {} to {}",
+          supported.start(),
+          supported.end()
+        ),
+      },
+      ModelError::NoLayers => write!(f, "model file contains no layers"),
+      ModelError::MissingTensor { name } => write!(f, "model file has no tensor {}", name),
+      ModelError::WrongDtype {
+        name,
+        dtype,
+        expected,
+      } => write!(
+        f,
+        "tensor {} is stored as {}, expected {}",
+        name,
+        dtype,
+        expected.join(" or ")
+      ),
+      ModelError::WrongRank {
+        name,
+        expected,
+        shape,
+      } => write!(
+        f,
+        "tensor {} has shape {:?}, expected {} dimensions",
+        name, shape, expected
+      ),
+      ModelError::TruncatedData {
+        name,
+        expected_bytes,
+        actual_bytes,
+      } => write!(
+        f,
+        "tensor {} holds {} bytes of data, its shape needs {}",
+        name, actual_bytes, expected_bytes
+      ),
+      ModelError::ShapeMismatch {
+        name,
+        expected,
+        actual,
+      } => write!(
+        f,
+        "tensor {} has shape {:?}, expected {:?}",
+        name, actual, expected
+      ),
+      ModelError::LayerMismatch {
+        layer,
+        inputs,
+        received,
+      } => write!(
+        f,
+        "layer {} expects {} inputs but the previous stage produces {}",
+        layer, inputs, received
+      ),
+      ModelError::ConvMismatch { layer, message } => {
+        write!(f, "convolutional layer {} {}", layer, message)
+      }
+      ModelError::LabelMismatch { outputs, labels } => write!(
+        f,
+        "model has {} outputs but {} class labels",
+        outputs, labels
+      ),
+      ModelError::NonFinite { name, count } => {
+        write!(
+          f,
+          "tensor {} contains {} NaN or infinite values",
+          name, count
+        )
+      }
+      ModelError::NegativeVariance { name, count } => {
+        write!(f, "variance {} has {} negative values", name, count)
+      }
+    }
+  }
+}
+
+impl std::error::Error for ModelError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      ModelError::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<std::io::Error> for ModelError {
+  fn from(e: std::io::Error) -> Self {
+    ModelError::Io(e)
+  }
+}
+
+impl From<safetensors::SafeTensorError> for ModelError {
+  fn from(e: safetensors::SafeTensorError) -> Self {
+    ModelError::InvalidFile(e.to_string())
+  }
+}
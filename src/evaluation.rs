@@ -0,0 +1,400 @@
+use ndarray::Array2;
+use serde::Serialize;
+use std::fmt;
+use std::path::Path;
+
+use crate::infer::csv_field;
+use crate::math::argmax;
+
+/// Number of equal-width confidence bins the expected calibration error is measured over.
+pub const CALIBRATION_BINS: usize = 15;
+/// The k values top-k accuracy is reported for, as far as there are classes.
+pub const TOP_K: [usize; 3] = [1, 3, 5];
+
+#[derive(Clone, Debug, Serialize)]
+pub struct ClassMetrics {
+  pub label: String,
+  /// Number of samples of this class.
+  pub support: usize,
+  /// Number of samples predicted as this class.
+  pub predicted: usize,
+  pub precision: f32,
+  pub recall: f32,
+  pub f1: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AverageMetrics {
+  pub precision: f32,
+  pub recall: f32,
+  pub f1: f32,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TopKAccuracy {
+  pub k: usize,
+  pub accuracy: f32,
+}
+
+/// Samples whose top probability falls in `[lower, upper)`.
+#[derive(Clone, Debug, Serialize)]
+pub struct CalibrationBin {
+  pub lower: f32,
+  pub upper: f32,
+  pub samples: usize,
+  /// Mean top probability of the samples in the bin.
+  pub confidence: f32,
+  /// Fraction of the samples in the bin classified correctly.
+  pub accuracy: f32,
+}
+
+/// Everything we measure about a model's predictions on a labelled dataset.
+#[derive(Clone, Debug, Serialize)]
+pub struct EvaluationReport {
+  pub samples: usize,
+  pub accuracy: f32,
+  /// Mean cross-entropy of the true class probabilities.
+  pub mean_loss: f32,
+  pub expected_calibration_error: f32,
+  pub top_k: Vec<TopKAccuracy>,
+  pub classes: Vec<ClassMetrics>,
+  /// Unweighted mean of the per-class metrics.
+  pub macro_average: AverageMetrics,
+  /// Metrics over all predictions pooled; with one label per sample these all equal the
+  /// accuracy.
+  pub micro_average: AverageMetrics,
+  /// `confusion_matrix[actual][predicted]` sample counts.
+  pub confusion_matrix: Vec<Vec<usize>>,
+  pub calibration: Vec<CalibrationBin>,
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f32 {
+  if denominator == 0 {
+    0.0
+  } else {
+    numerator as f32 / denominator as f32
+  }
+}
+
+fn f1(precision: f32, recall: f32) -> f32 {
+  if precision + recall == 0.0 {
+    0.0
+  } else {
+    2.0 * precision * recall / (precision + recall)
+  }
+}
+
+impl EvaluationReport {
+  /// Build the report from `probabilities` (classes x samples, as `InferrableModel::forward`
+  /// returns them) and the true `labels` of the samples. Fails if the labels don't fit the
+  /// outputs, e.g. for an imported model with fewer outputs than the dataset has classes.
+  pub fn new(
+    probabilities: &Array2<f32>,
+    labels: &[u8],
+    class_labels: &[String],
+  ) -> Result<Self, String> {
+    let (classes, samples) = probabilities.dim();
+    if labels.len() != samples {
+      return Err(format!("{} labels for {} samples", labels.len(), samples));
+    }
+    if class_labels.len() != classes {
+      return Err(format!(
+        "model has {} outputs but {} class labels",
+        classes,
+        class_labels.len()
+      ));
+    }
+    if let Some((sample, &y)) = labels
+      .iter()
+      .enumerate()
+      .find(|&(_, &y)| y as usize >= classes)
+    {
+      return Err(format!(
+        "sample {} is labelled {}, but the model only has {} outputs",
+        sample, y, classes
+      ));
+    }
+
+    let top_k: Vec<usize> = TOP_K.iter().copied().filter(|&k| k <= classes).collect();
+    let mut confusion_matrix = vec![vec![0; classes]; classes];
+    let mut top_k_correct = vec![0; top_k.len()];
+    let mut total_loss = 0.0;
+    let mut bin_samples = [0usize; CALIBRATION_BINS];
+    let mut bin_correct = [0usize; CALIBRATION_BINS];
+    let mut bin_confidence = [0f32; CALIBRATION_BINS];
+
+    for (column, &y) in probabilities.columns().into_iter().zip(labels) {
+      let y = y as usize;
+      let predicted = argmax(column);
+      confusion_matrix[y][predicted] += 1;
+
+      let correct_probability = column[y];
+      total_loss += -((correct_probability + 1e-10).ln());
+
+      // the true class is within the top k if fewer than k classes beat it
+      let rank = column.iter().filter(|&&p| p > correct_probability).count();
+      for (correct, &k) in top_k_correct.iter_mut().zip(&top_k) {
+        if rank < k {
+          *correct += 1;
+        }
+      }
+
+      let confidence = column[predicted];
+      let bin = ((confidence * CALIBRATION_BINS as f32) as usize).min(CALIBRATION_BINS - 1);
+      bin_samples[bin] += 1;
+      bin_confidence[bin] += confidence;
+      if predicted == y {
+        bin_correct[bin] += 1;
+      }
+    }
+
+    let correct: usize = (0..classes).map(|c| confusion_matrix[c][c]).sum();
+    let accuracy = ratio(correct, samples);
+
+    let class_metrics: Vec<ClassMetrics> = class_labels
+      .iter()
+      .enumerate()
+      .map(|(c, label)| {
+        let support: usize = confusion_matrix[c].iter().sum();
+        let predicted: usize = confusion_matrix.iter().map(|row| row[c]).sum();
+        let precision = ratio(confusion_matrix[c][c], predicted);
+        let recall = ratio(confusion_matrix[c][c], support);
+        ClassMetrics {
+          label: label.clone(),
+          support,
+          predicted,
+          precision,
+          recall,
+          f1: f1(precision, recall),
+        }
+      })
+      .collect();
+
+    let mean = |metric: fn(&ClassMetrics) -> f32| {
+      class_metrics.iter().map(metric).sum::<f32>() / classes as f32
+    };
+    let macro_average = AverageMetrics {
+      precision: mean(|m| m.precision),
+      recall: mean(|m| m.recall),
+      f1: mean(|m| m.f1),
+    };
+    // every sample has exactly one label and one prediction, so pooled false positives
+    // and false negatives are both the misclassified samples
+    let micro_average = AverageMetrics {
+      precision: accuracy,
+      recall: accuracy,
+      f1: accuracy,
+    };
+
+    let calibration: Vec<CalibrationBin> = (0..CALIBRATION_BINS)
+      .map(|bin| CalibrationBin {
+        lower: bin as f32 / CALIBRATION_BINS as f32,
+        upper: (bin + 1) as f32 / CALIBRATION_BINS as f32,
+        samples: bin_samples[bin],
+        confidence: if bin_samples[bin] == 0 {
+          0.0
+        } else {
+          bin_confidence[bin] / bin_samples[bin] as f32
+        },
+        accuracy: ratio(bin_correct[bin], bin_samples[bin]),
+      })
+      .collect();
+    let expected_calibration_error = calibration
+      .iter()
+      .map(|bin| ratio(bin.samples, samples) * (bin.accuracy - bin.confidence).abs())
+      .sum();
+
+    Ok(Self {
+      samples,
+      accuracy,
+      mean_loss: if samples == 0 {
+        0.0
+      } else {
+        total_loss / samples as f32
+      },
+      expected_calibration_error,
+      top_k: top_k
+        .iter()
+        .zip(&top_k_correct)
+        .map(|(&k, &correct)| TopKAccuracy {
+          k,
+          accuracy: ratio(correct, samples),
+        })
+        .collect(),
+      classes: class_metrics,
+      macro_average,
+      micro_average,
+      confusion_matrix,
+      calibration,
+    })
+  }
+
+  /// One `metric,class,predicted,value` row per number in the report. `class` is empty
+  /// for dataset-wide metrics, and `predicted` is only set for confusion matrix counts.
+  pub fn to_csv(&self) -> String {
+    let mut rows = vec!["metric,class,predicted,value".to_string()];
+    let mut row = |metric: &str, class: &str, predicted: &str, value: String| {
+      rows.push(format!(
+        "{},{},{},{}",
+        metric,
+        csv_field(class),
+        csv_field(predicted),
+        value
+      ));
+    };
+
+    row("samples", "", "", self.samples.to_string());
+    row("accuracy", "", "", self.accuracy.to_string());
+    for top_k in &self.top_k {
+      row(
+        &format!("top_{}_accuracy", top_k.k),
+        "",
+        "",
+        top_k.accuracy.to_string(),
+      );
+    }
+    row("mean_loss", "", "", self.mean_loss.to_string());
+    row(
+      "expected_calibration_error",
+      "",
+      "",
+      self.expected_calibration_error.to_string(),
+    );
+    for (name, average) in [
+      ("macro", &self.macro_average),
+      ("micro", &self.micro_average),
+    ] {
+      row(
+        &format!("{}_precision", name),
+        "",
+        "",
+        average.precision.to_string(),
+      );
+      row(
+        &format!("{}_recall", name),
+        "",
+        "",
+        average.recall.to_string(),
+      );
+      row(&format!("{}_f1", name), "", "", average.f1.to_string());
+    }
+    for class in &self.classes {
+      row("support", &class.label, "", class.support.to_string());
+      row("precision", &class.label, "", class.precision.to_string());
+      row("recall", &class.label, "", class.recall.to_string());
+      row("f1", &class.label, "", class.f1.to_string());
+    }
+    for (actual, counts) in self.classes.iter().zip(&self.confusion_matrix) {
+      for (predicted, count) in self.classes.iter().zip(counts) {
+        row(
+          "confusion",
+          &actual.label,
+          &predicted.label,
+          count.to_string(),
+        );
+      }
+    }
+    rows.push(String::new());
+    rows.join("\n")
+  }
+
+  /// Write the report to `path` as JSON or CSV, going by its extension.
+  pub fn write<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let contents = match path.extension().and_then(|ext| ext.to_str()) {
+      Some("json") => serde_json::to_string_pretty(self)?,
+      Some("csv") => self.to_csv(),
+      _ => {
+        return Err(std::io::Error::new(
+          std::io::ErrorKind::InvalidInput,
+          format!("{} should end in .json or .csv", path.display()),
+        ));
+      }
+    };
+    std::fs::write(path, contents)
+  }
+}
+
+impl fmt::Display for EvaluationReport {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let width = self
+      .classes
+      .iter()
+      .map(|class| class.label.len())
+      .max()
+      .unwrap_or(0)
+      .max("Class".len());
+    let percent = |value: f32| format!("{:.2}%", value * 100.0);
+
+    writeln!(
+      f,
+      "{:<width$} {:>8} {:>10} {:>10} {:>10}",
+      "Class", "Support", "Precision", "Recall", "F1"
+    )?;
+    for class in &self.classes {
+      writeln!(
+        f,
+        "{:<width$} {:>8} {:>10} {:>10} {:>10}",
+        class.label,
+        class.support,
+        percent(class.precision),
+        percent(class.recall),
+        percent(class.f1)
+      )?;
+    }
+    for (name, average) in [
+      ("macro", &self.macro_average),
+      ("micro", &self.micro_average),
+    ] {
+      writeln!(
+        f,
+        "{:<width$} {:>8} {:>10} {:>10} {:>10}",
+        name,
+        self.samples,
+        percent(average.precision),
+        percent(average.recall),
+        percent(average.f1)
+      )?;
+    }
+
+    writeln!(f)?;
+    writeln!(f, "Confusion matrix (rows: actual, columns: predicted)")?;
+    let counts = self
+      .confusion_matrix
+      .iter()
+      .flatten()
+      .map(|count| count.to_string().len())
+      .max()
+      .unwrap_or(0);
+    let cell = self
+      .classes
+      .iter()
+      .map(|class| class.label.len())
+      .max()
+      .unwrap_or(0)
+      .max(counts);
+    write!(f, "{:<width$}", "")?;
+    for class in &self.classes {
+      write!(f, " {:>cell$}", class.label)?;
+    }
+    writeln!(f)?;
+    for (class, row) in self.classes.iter().zip(&self.confusion_matrix) {
+      write!(f, "{:<width$}", class.label)?;
+      for count in row {
+        write!(f, " {:>cell$}", count)?;
+      }
+      writeln!(f)?;
+    }
+
+    writeln!(f)?;
+    for top_k in &self.top_k {
+      writeln!(f, "Top-{} accuracy: {}", top_k.k, percent(top_k.accuracy))?;
+    }
+    writeln!(f, "Mean cross-entropy loss: {:.4}", self.mean_loss)?;
+    write!(
+      f,
+      "Expected calibration error: {}",
+      percent(self.expected_calibration_error)
+    )
+  }
+}
@@ -5,6 +5,8 @@ use ndarray::Array2;
 use std::cell::RefCell;
 use std::rc::Rc;
 
+use crate::image_input::downsample_to_mnist;
+
 const BRUSH_SIZE: f64 = 20.0;
 const CANVAS_SIZE: i32 = 280;
 pub struct DrawingAreaUI {
@@ -34,9 +36,9 @@ impl DrawingAreaUI {
       .build();
 
     let component = Rc::new(RefCell::new(DrawingAreaUI {
-      drawing_area: drawing_area,
-      surface: surface,
-      on_drawing_updated_cb: on_drawing_updated_cb,
+      drawing_area,
+      surface,
+      on_drawing_updated_cb,
     }));
 
     // Set up the draw function to display the surface
@@ -147,46 +149,17 @@ impl DrawingAreaUI {
 
   /// Convert the drawn image to a 28x28 ndarray by extracting pixel data from the surface
   pub fn get_image_data(&mut self) -> Array2<u8> {
+    let stride = self.surface.stride() as usize;
     let data = self.surface.data().expect("Failed to get surface data");
 
-    // Convert ARGB32 data to grayscale and downsample
-    let mut downsampled = Array2::<u8>::zeros((28, 28));
-
-    for i in 0..28 {
-      for j in 0..28 {
-        let mut sum = 0.0;
-        let mut count = 0;
-
-        // Average over a 10x10 block
-        for di in 0..10 {
-          for dj in 0..10 {
-            let y = i * 10 + di;
-            let x = j * 10 + dj;
-            if y < 280 && x < 280 {
-              // ARGB32 format: each pixel is 4 bytes (B, G, R, A)
-              let pixel_index = ((y * 280 + x) * 4) as usize;
-              if pixel_index + 3 < data.len() {
-                let b = data[pixel_index] as f32 / 255.0;
-                let g = data[pixel_index + 1] as f32 / 255.0;
-                let r = data[pixel_index + 2] as f32 / 255.0;
-
-                // Convert to grayscale (inverted - black pixels = 1, white = 0)
-                let gray = 1.0 - (0.299 * r + 0.587 * g + 0.114 * b);
-                sum += gray;
-                count += 1;
-              }
-            }
-          }
-        }
-
-        downsampled[[i, j]] = if count > 0 {
-          (sum / count as f32 * 255.0) as u8
-        } else {
-          0
-        };
-      }
-    }
-
-    downsampled
+    // Convert ARGB32 data to grayscale (inverted - black pixels = 1, white = 0) and downsample
+    downsample_to_mnist(CANVAS_SIZE as usize, CANVAS_SIZE as usize, true, |x, y| {
+      // ARGB32 format: each pixel is 4 bytes (B, G, R, A)
+      let pixel_index = y * stride + x * 4;
+      let b = data[pixel_index] as f32 / 255.0;
+      let g = data[pixel_index + 1] as f32 / 255.0;
+      let r = data[pixel_index + 2] as f32 / 255.0;
+      (r, g, b)
+    })
   }
 }
@@ -2,44 +2,24 @@ use gio::ApplicationFlags;
 use glib::clone::Downgrade;
 use gtk4::prelude::*;
 use gtk4::{Application, ApplicationWindow, Box, Button, Label, Orientation};
-use ndarray::{Array, Array2, Axis, Ix2};
+use ndarray::Array2;
 use std::rc::Rc;
 
 use crate::inferrable_model::InferrableModel;
-use crate::math::{flatten_2d_to_1d, sigmoid, softmax};
-use crate::serializable_model::SerializableModel;
 
 use super::drawing_area_ui::DrawingAreaUI;
 
-fn run_inference(model: &InferrableModel, image_data: &Array2<u8>) -> Array<f32, Ix2> {
-  let image = flatten_2d_to_1d(image_data)
-    .mapv(|x| x as f32)
-    .insert_axis(Axis(1));
-
-  // Forward pass
-  let z1 = &model.w1.dot(&image) + &model.b1;
-  let a1 = sigmoid(&z1);
-  let z2 = model.w2.dot(&a1) + &model.b2;
-  softmax(&z2)
-}
-
 pub fn create_window(model_path: &str) {
   // Load the neural network model
-  let model = match SerializableModel::load_from_safetensors(model_path) {
-    Ok(serialized_model) => {
-      let model = InferrableModel::from_serializable_model(&serialized_model);
+  let model = match InferrableModel::load(model_path) {
+    Ok(model) => {
       println!("Successfully loaded model from: {}", model_path);
-      println!(
-        "Model dimensions: w1={:?}, b1={:?}, w2={:?}, b2={:?}",
-        model.w1.dim(),
-        model.b1.dim(),
-        model.w2.dim(),
-        model.b2.dim()
-      );
+      println!("Model layers: {:?}", model.layer_sizes());
       Rc::new(model)
     }
     Err(e) => {
-      panic!("Failed to load model from {}: {}", model_path, e);
+      eprintln!("Failed to load model from {}: {}", model_path, e);
+      std::process::exit(1);
     }
   };
 
@@ -88,7 +68,7 @@ Draw a digit to see prediction...";
       let model_for_cb = model.clone();
       let output_label_for_cb = output_label.clone();
       DrawingAreaUI::new(std::boxed::Box::new(move |image_data_2d: Array2<u8>| {
-        let a2 = run_inference(&model_for_cb, &image_data_2d);
+        let a2 = model_for_cb.predict(&image_data_2d);
 
         // Format predictions and update label on the main thread
         let mut s = String::new();
@@ -138,7 +118,7 @@ Draw a digit to see prediction...";
             println!();
           }
 
-          let a2 = run_inference(&model, &image_data_2d);
+          let a2 = model.predict(&image_data_2d);
 
           println!("Predictions: {:?}", a2)
         }
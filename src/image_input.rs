@@ -0,0 +1,216 @@
+use ndarray::Array2;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+pub const IMAGE_SIZE: usize = 28;
+
+// idx3-ubyte magic: two zero bytes, 0x08 (unsigned byte data), 0x03 (3 dimensions)
+const IDX3_UBYTE_MAGIC: [u8; 4] = [0x00, 0x00, 0x08, 0x03];
+
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "bmp", "gif"];
+
+/// A single 28x28 grayscale image ready to be fed into the network, along with
+/// a human readable name describing where it came from.
+pub struct InputImage {
+  pub name: String,
+  pub pixels: Array2<u8>,
+}
+
+/// Downsample an RGB image into a 28x28 grayscale array.
+///
+/// Each output pixel is the average over its block of source pixels. When `invert`
+/// is set, dark ink on a light background becomes bright pixels on black, which is
+/// what the MNIST digits look like. `rgb` returns the (r, g, b) value of the source
+/// pixel at (x, y), each in 0..1.
+pub fn downsample_to_mnist<F>(width: usize, height: usize, invert: bool, rgb: F) -> Array2<u8>
+where
+  F: Fn(usize, usize) -> (f32, f32, f32),
+{
+  let mut downsampled = Array2::<u8>::zeros((IMAGE_SIZE, IMAGE_SIZE));
+
+  for i in 0..IMAGE_SIZE {
+    // make sure every block covers at least one pixel, even for images smaller than 28x28
+    let y_start = i * height / IMAGE_SIZE;
+    let y_end = ((i + 1) * height / IMAGE_SIZE).max(y_start + 1).min(height);
+    for j in 0..IMAGE_SIZE {
+      let x_start = j * width / IMAGE_SIZE;
+      let x_end = ((j + 1) * width / IMAGE_SIZE).max(x_start + 1).min(width);
+
+      let mut sum = 0.0;
+      let mut count = 0;
+      for y in y_start..y_end {
+        for x in x_start..x_end {
+          let (r, g, b) = rgb(x, y);
+          let luma = 0.299 * r + 0.587 * g + 0.114 * b;
+          sum += if invert { 1.0 - luma } else { luma };
+          count += 1;
+        }
+      }
+
+      downsampled[[i, j]] = if count > 0 {
+        (sum / count as f32 * 255.0) as u8
+      } else {
+        0
+      };
+    }
+  }
+
+  downsampled
+}
+
+/// Load an image file (png, jpeg, bmp, gif) and downsample it to 28x28.
+/// Transparent pixels are composited over a white background, like the GUI canvas.
+pub fn load_image_file(
+  path: &Path,
+  invert: bool,
+) -> Result<Array2<u8>, Box<dyn std::error::Error>> {
+  let img = image::open(path)?.to_rgba8();
+  let (width, height) = img.dimensions();
+
+  Ok(downsample_to_mnist(
+    width as usize,
+    height as usize,
+    invert,
+    |x, y| {
+      let [r, g, b, a] = img.get_pixel(x as u32, y as u32).0;
+      let alpha = a as f32 / 255.0;
+      let over_white = |c: u8| (c as f32 / 255.0) * alpha + (1.0 - alpha);
+      (over_white(r), over_white(g), over_white(b))
+    },
+  ))
+}
+
+/// Returns true if the file starts with the idx3-ubyte magic number used by the MNIST
+/// image files (e.g. `t10k-images-idx3-ubyte`).
+pub fn is_idx_file(path: &Path) -> bool {
+  let mut magic = [0u8; 4];
+  File::open(path)
+    .and_then(|mut f| f.read_exact(&mut magic))
+    .map(|_| magic == IDX3_UBYTE_MAGIC)
+    .unwrap_or(false)
+}
+
+/// Read every image out of a raw MNIST-format (idx3-ubyte) file. The pixels are used
+/// as-is since they are already 28x28 white-on-black digits.
+pub fn load_idx_file(path: &Path) -> Result<Vec<InputImage>, Box<dyn std::error::Error>> {
+  let mut buffer = Vec::new();
+  File::open(path)?.read_to_end(&mut buffer)?;
+
+  if buffer.len() < 16 || buffer[0..4] != IDX3_UBYTE_MAGIC {
+    return Err(format!("{} is not an idx3-ubyte file", path.display()).into());
+  }
+
+  let read_u32 = |offset: usize| {
+    u32::from_be_bytes([
+      buffer[offset],
+      buffer[offset + 1],
+      buffer[offset + 2],
+      buffer[offset + 3],
+    ]) as usize
+  };
+  let count = read_u32(4);
+  let rows = read_u32(8);
+  let cols = read_u32(12);
+
+  if rows != IMAGE_SIZE || cols != IMAGE_SIZE {
+    return Err(
+      format!(
+        "{} contains {}x{} images, expected {}x{}",
+        path.display(),
+        rows,
+        cols,
+        IMAGE_SIZE,
+        IMAGE_SIZE
+      )
+      .into(),
+    );
+  }
+
+  let image_len = rows * cols;
+  let data = &buffer[16..];
+  if data.len() < count * image_len {
+    return Err(
+      format!(
+        "{} is truncated: header declares {} images but only {} bytes of pixel data follow",
+        path.display(),
+        count,
+        data.len()
+      )
+      .into(),
+    );
+  }
+
+  Ok(
+    data
+      .chunks_exact(image_len)
+      .take(count)
+      .enumerate()
+      .map(|(i, chunk)| InputImage {
+        name: format!("{}[{}]", path.display(), i),
+        pixels: Array2::from_shape_vec((rows, cols), chunk.to_vec()).unwrap(),
+      })
+      .collect(),
+  )
+}
+
+fn has_image_extension(path: &Path) -> bool {
+  path
+    .extension()
+    .and_then(|ext| ext.to_str())
+    .map(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+    .unwrap_or(false)
+}
+
+/// Expand the inputs given on the command line into a list of files.
+///
+/// Each input can be a file, a directory (its image and idx files are used, sorted by
+/// name) or a glob pattern such as `digits/*.png`.
+pub fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+  let mut paths = Vec::new();
+
+  for input in inputs {
+    let path = Path::new(input);
+    if path.is_dir() {
+      let mut entries: Vec<PathBuf> = std::fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && (has_image_extension(p) || is_idx_file(p)))
+        .collect();
+      entries.sort();
+      paths.extend(entries);
+    } else if path.exists() {
+      paths.push(path.to_path_buf());
+    } else {
+      let matches: Vec<PathBuf> = glob::glob(input)?.filter_map(Result::ok).collect();
+      if matches.is_empty() {
+        return Err(format!("no files found matching {}", input).into());
+      }
+      paths.extend(matches);
+    }
+  }
+
+  Ok(paths)
+}
+
+/// Load every image referenced by `paths`. idx files can contribute many images each.
+pub fn load_inputs(
+  paths: &[PathBuf],
+  invert: bool,
+) -> Result<Vec<InputImage>, Box<dyn std::error::Error>> {
+  let mut images = Vec::new();
+
+  for path in paths {
+    if is_idx_file(path) {
+      images.extend(load_idx_file(path)?);
+    } else {
+      let pixels = load_image_file(path, invert)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+      images.push(InputImage {
+        name: path.display().to_string(),
+        pixels,
+      });
+    }
+  }
+
+  Ok(images)
+}
@@ -0,0 +1,134 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::image_input::{expand_inputs, load_inputs};
+use crate::inferrable_model::InferrableModel;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+  /// Human readable, one row per input
+  Table,
+  /// One JSON object per input
+  Jsonl,
+  /// One row per (input, rank) pair
+  Csv,
+}
+
+#[derive(Serialize)]
+struct Prediction {
+  class: usize,
+  probability: f32,
+}
+
+#[derive(Serialize)]
+struct InferenceResult {
+  input: String,
+  predictions: Vec<Prediction>,
+}
+
+/// Classify every image referenced by `inputs` and print the `top_k` classes for each.
+///
+/// `inputs` may contain image files, directories, glob patterns and raw MNIST (idx3-ubyte)
+/// files. Set `invert` for dark-on-light images such as scans or GUI screenshots.
+pub fn infer(
+  model_path: &str,
+  inputs: &[String],
+  top_k: usize,
+  format: OutputFormat,
+  invert: bool,
+) {
+  let model = match InferrableModel::load(model_path) {
+    Ok(model) => model,
+    Err(e) => {
+      eprintln!("Failed to load model from {}: {}", model_path, e);
+      std::process::exit(1);
+    }
+  };
+
+  let images = match expand_inputs(inputs).and_then(|paths| load_inputs(&paths, invert)) {
+    Ok(images) => images,
+    Err(e) => {
+      eprintln!("Failed to load inputs: {}", e);
+      std::process::exit(1);
+    }
+  };
+
+  let results: Vec<InferenceResult> = images
+    .iter()
+    .map(|image| {
+      let probabilities = model.predict(&image.pixels);
+      let mut ranked: Vec<(usize, f32)> = probabilities.iter().cloned().enumerate().collect();
+      ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+      ranked.truncate(top_k);
+
+      InferenceResult {
+        input: image.name.clone(),
+        predictions: ranked
+          .into_iter()
+          .map(|(class, probability)| Prediction { class, probability })
+          .collect(),
+      }
+    })
+    .collect();
+
+  match format {
+    OutputFormat::Table => print_table(&results, top_k),
+    OutputFormat::Jsonl => {
+      for result in &results {
+        println!("{}", serde_json::to_string(result).unwrap());
+      }
+    }
+    OutputFormat::Csv => print_csv(&results),
+  }
+}
+
+fn print_table(results: &[InferenceResult], top_k: usize) {
+  let input_width = results
+    .iter()
+    .map(|r| r.input.len())
+    .chain(std::iter::once("INPUT".len()))
+    .max()
+    .unwrap_or(0);
+
+  let mut header = format!("{:<width$}", "INPUT", width = input_width);
+  for rank in 1..=top_k {
+    header.push_str(&format!("  {:<11}", format!("TOP-{}", rank)));
+  }
+  println!("{}", header.trim_end());
+
+  for result in results {
+    let mut row = format!("{:<width$}", result.input, width = input_width);
+    for prediction in &result.predictions {
+      row.push_str(&format!(
+        "  {:<11}",
+        format!("{} ({:.3})", prediction.class, prediction.probability)
+      ));
+    }
+    println!("{}", row.trim_end());
+  }
+}
+
+/// `field` as a CSV field, quoted if it contains separators so file names and labels with
+/// commas survive.
+pub fn csv_field(field: &str) -> String {
+  if field.contains([',', '"', '\n']) {
+    format!("\"{}\"", field.replace('"', "\"\""))
+  } else {
+    field.to_string()
+  }
+}
+
+fn print_csv(results: &[InferenceResult]) {
+  println!("input,rank,class,probability");
+  for result in results {
+    for (rank, prediction) in result.predictions.iter().enumerate() {
+      println!(
+        "{},{},{},{}",
+        csv_field(&result.input),
+        rank + 1,
+        prediction.class,
+        prediction.probability
+      );
+    }
+  }
+}
@@ -1,57 +1,594 @@
-use ndarray_rand::{RandomExt, rand_distr::Uniform};
+use ndarray::{Array2, Axis};
+use rand::distr::Uniform;
+use rand::{Rng, RngCore};
+use std::cell::Ref;
+use std::path::Path;
 
-use ndarray::Array2;
+use crate::activation::Activation;
+use crate::autograd::{Tape, Var};
+use crate::conv::{ConvGradients, ConvLayer, ConvLayerConfig, Shape, flatten, shape_of, unflatten};
+use crate::error::ModelError;
+use crate::math::{flatten_2d_to_1d, softmax_columns};
+use crate::norm::{NormGradients, NormKind, NormLayer, NormVars};
+use crate::onnx::import::load_onnx;
+use crate::preprocessing::Preprocessor;
+use crate::quantization::QuantizedWeights;
+use crate::serializable_model::{
+  SerializableLayer, SerializableModel, digit_labels, mnist_input_shape,
+};
 
-use crate::serializable_model::SerializableModel;
+/// A fully connected layer computing `activation(w . x + b)`, or
+/// `activation(norm(w . x + b))` with a normalization.
+#[derive(Clone)]
+pub struct DenseLayer {
+  pub w: Array2<f32>,
+  pub b: Array2<f32>,
+  pub activation: Activation,
+  /// Int8 weights of a quantized layer. `w` then holds their dequantized values.
+  pub quantized: Option<QuantizedWeights>,
+  pub norm: Option<NormLayer>,
+}
+
+impl DenseLayer {
+  pub fn new<R: Rng>(inputs: usize, outputs: usize, activation: Activation, rng: &mut R) -> Self {
+    let uniform = Uniform::new(-0.5, 0.5).unwrap();
+    DenseLayer {
+      w: Array2::from_shape_simple_fn((outputs, inputs), || rng.sample(uniform)),
+      b: Array2::<f32>::zeros((outputs, 1)),
+      activation,
+      quantized: None,
+      norm: None,
+    }
+  }
+
+  pub fn inputs(&self) -> usize {
+    self.w.ncols()
+  }
+
+  pub fn outputs(&self) -> usize {
+    self.w.nrows()
+  }
+
+  /// Pre-activation values `w . x + b`. `x` holds one sample per column; the bias is
+  /// broadcast across the batch.
+  pub fn linear(&self, x: &Array2<f32>) -> Array2<f32> {
+    &self.w.dot(x) + &self.b
+  }
+
+  /// `linear` computed in integer arithmetic if the layer is quantized, in f32 otherwise.
+  pub fn linear_quantized(&self, x: &Array2<f32>) -> Array2<f32> {
+    match &self.quantized {
+      Some(quantized) => quantized.linear(&quantized.quantize_input(x), &self.b),
+      None => self.linear(x),
+    }
+  }
+}
+
+/// Gradients for every layer of a model, as returned by `InferrableModel::backward`.
+pub struct Gradients {
+  /// One per convolutional layer; pooling layers have no parameters.
+  pub conv: Vec<Option<ConvGradients>>,
+  pub dense: Vec<DenseGradients>,
+}
+
+impl Gradients {
+  /// Every gradient, in the order of `InferrableModel::parameters_mut`.
+  pub fn tensors(&self) -> Vec<&Array2<f32>> {
+    let conv = self.conv.iter().flatten().flat_map(|g| [&g.dw, &g.db]);
+    conv
+      .chain(self.dense.iter().flat_map(|g| g.tensors()))
+      .collect()
+  }
+
+  /// The gradients for the weights (not biases or normalizations), in the order of
+  /// `InferrableModel::weights`.
+  pub fn weights_mut(&mut self) -> impl Iterator<Item = &mut Array2<f32>> {
+    let conv = self.conv.iter_mut().flatten().map(|g| &mut g.dw);
+    conv.chain(self.dense.iter_mut().map(|g| &mut g.dw))
+  }
+}
+
+/// Gradients for a single dense layer, same shapes as the layer's `w` and `b`,
+/// averaged over the samples in the batch.
+pub struct DenseGradients {
+  pub dw: Array2<f32>,
+  pub db: Array2<f32>,
+  /// Gradients for the layer's normalization, if it has one.
+  pub norm: Option<NormGradients>,
+}
+
+impl DenseGradients {
+  /// Every gradient, in the order of the layer's parameters in
+  /// `InferrableModel::parameters_mut`.
+  pub fn tensors(&self) -> Vec<&Array2<f32>> {
+    let mut tensors = vec![&self.dw, &self.db];
+    if let Some(norm) = &self.norm {
+      tensors.extend([&norm.dgamma, &norm.dbeta]);
+    }
+    tensors
+  }
+}
 
+/// Whether a forward pass is part of training or evaluation.
+pub enum Mode<'a> {
+  /// Hidden layer outputs are dropped at the given rates, one per hidden layer, with the
+  /// dropout masks drawn from `rng`.
+  Train {
+    dropout: &'a [f32],
+    rng: &'a mut dyn RngCore,
+  },
+  /// Every output is kept, as when classifying or validating.
+  Eval,
+}
+
+/// Inverted dropout mask: each value is 0 with probability `rate`, and `1 / (1 - rate)`
+/// otherwise so the expected output doesn't change and nothing needs rescaling in eval
+/// mode.
+fn dropout_mask(rate: f32, dim: (usize, usize), rng: &mut dyn RngCore) -> Array2<f32> {
+  let keep = 1.0 / (1.0 - rate);
+  Array2::from_shape_simple_fn(dim, || {
+    if rng.random::<f32>() < rate {
+      0.0
+    } else {
+      keep
+    }
+  })
+}
+
+/// The tape variables of a dense layer.
+struct DenseVars {
+  /// What the layer read.
+  input: Var,
+  w: Var,
+  b: Var,
+  norm: Option<NormVars>,
+  /// The dropout mask applied to the layer's output, if any of it was dropped.
+  mask: Option<Var>,
+}
+
+/// A forward pass recorded on a `Tape` for backprop. Intermediate values stay on the
+/// tape and are read from it on demand.
+pub struct ForwardPass {
+  tape: Tape,
+  /// The variables of each convolutional layer's `w` and `b`; pooling layers have none.
+  conv_vars: Vec<Option<(Var, Var)>>,
+  dense_vars: Vec<DenseVars>,
+  /// Output of the last layer, before softmax.
+  logits: Var,
+  /// Softmax of `logits`.
+  probabilities: Array2<f32>,
+}
+
+impl ForwardPass {
+  /// The class probabilities, classes x batch.
+  pub fn output(&self) -> &Array2<f32> {
+    &self.probabilities
+  }
+
+  /// The input to dense layer `layer`: the flattened output of the convolutional layers,
+  /// if any, for the first, the activated output of the layer before, after dropout,
+  /// for the others.
+  pub fn input(&self, layer: usize) -> Ref<'_, Array2<f32>> {
+    self.tape.value(self.dense_vars[layer].input)
+  }
+
+  /// Values of dense layer `layer` after normalization, before its `gamma` and `beta`
+  /// are applied, if it has a normalization.
+  pub fn normalized(&self, layer: usize) -> Option<Ref<'_, Array2<f32>>> {
+    let norm = self.dense_vars[layer].norm?;
+    Some(self.tape.value(norm.x_hat))
+  }
+
+  /// The dropout mask applied to dense layer `layer`'s output, if any of it was dropped.
+  pub fn mask(&self, layer: usize) -> Option<Ref<'_, Array2<f32>>> {
+    let mask = self.dense_vars[layer].mask?;
+    Some(self.tape.value(mask))
+  }
+}
+
+/// A stack of dense layers, optionally preceded by convolutional layers whose output is
+/// flattened into the first dense layer. Each hidden layer has its own activation; the
+/// output layer produces logits (identity activation) which are turned into probabilities
+/// by softmax.
+///
+/// `forward` expects inputs that already went through `preprocessor`; `predict` takes raw
+/// pixels and applies it.
+#[derive(Clone)]
 pub struct InferrableModel {
-  pub w1: Array2<f32>,
-  pub b1: Array2<f32>,
-  pub w2: Array2<f32>,
-  pub b2: Array2<f32>,
+  pub preprocessor: Preprocessor,
+  /// Applied to the preprocessed image, in order, before `layers`.
+  pub conv_layers: Vec<ConvLayer>,
+  pub layers: Vec<DenseLayer>,
+  /// Name of each output class, in output order.
+  pub class_labels: Vec<String>,
 }
 
 impl InferrableModel {
-  pub fn new() -> Self {
-    return InferrableModel {
-      // --- Init weights ---
-      // 128 hidden layers
-      w1: Array2::<f32>::random((128, 784), Uniform::new(-0.5, 0.5)),
-      b1: Array2::<f32>::zeros((128, 1)),
-      // output layer (10 neurons, 10 digits)
-      w2: Array2::<f32>::random((10, 128), Uniform::new(-0.5, 0.5)),
-      b2: Array2::<f32>::zeros((10, 1)),
+  /// Create a randomly initialized model. `layer_sizes` lists the width of every layer
+  /// including the input and output, e.g. `[784, 256, 128, 10]`, and
+  /// `hidden_activations` holds one activation per hidden layer. Weights are drawn from `rng`.
+  /// The model starts without preprocessing and with the digits as class labels.
+  pub fn new<R: Rng>(
+    layer_sizes: &[usize],
+    hidden_activations: &[Activation],
+    rng: &mut R,
+  ) -> Self {
+    assert!(
+      layer_sizes.len() >= 2,
+      "a model needs at least an input and an output layer"
+    );
+    assert_eq!(
+      hidden_activations.len(),
+      layer_sizes.len() - 2,
+      "expected one activation per hidden layer"
+    );
+
+    let activations = hidden_activations
+      .iter()
+      .cloned()
+      .chain(std::iter::once(Activation::Identity));
+
+    InferrableModel {
+      preprocessor: Preprocessor::default(),
+      conv_layers: Vec::new(),
+      layers: layer_sizes
+        .windows(2)
+        .zip(activations)
+        .map(|(pair, activation)| DenseLayer::new(pair[0], pair[1], activation, rng))
+        .collect(),
+      class_labels: digit_labels(),
+    }
+  }
+
+  /// Load a model from a safetensors file written by `SerializableModel::save_to_safetensors`,
+  /// or from an ONNX file if the name ends in `.onnx`.
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ModelError> {
+    if path.as_ref().extension().is_some_and(|ext| ext == "onnx") {
+      return load_onnx(path);
+    }
+    Self::from_serializable_model(&SerializableModel::load_from_safetensors(path)?)
+  }
+
+  pub fn from_serializable_model(model: &SerializableModel) -> Result<Self, ModelError> {
+    let array = |name: String, shape: (usize, usize), values: &[f32]| {
+      Array2::from_shape_vec(shape, values.to_vec()).map_err(|_| ModelError::ShapeMismatch {
+        name,
+        expected: vec![shape.0, shape.1],
+        actual: vec![values.len()],
+      })
     };
+
+    let mut layers = Vec::with_capacity(model.layers.len());
+    for (i, layer) in model.layers.iter().enumerate() {
+      layers.push(DenseLayer {
+        w: array(format!("w{}", i + 1), layer.w_shape, &layer.w)?,
+        b: array(format!("b{}", i + 1), layer.b_shape, &layer.b)?,
+        activation: layer.activation,
+        quantized: layer.quantized.clone(),
+        norm: layer.norm.clone(),
+      });
+    }
+
+    Ok(InferrableModel {
+      preprocessor: model.preprocessor.clone(),
+      conv_layers: model.conv_layers.clone(),
+      layers,
+      class_labels: model.class_labels.clone(),
+    })
   }
 
-  pub fn from_serializable_model(model: &SerializableModel) -> Self {
-    let w1 =
-      Array2::from_shape_vec((model.w1_shape.0, model.w1_shape.1), model.w1.clone()).unwrap();
-    let b1 =
-      Array2::from_shape_vec((model.b1_shape.0, model.b1_shape.1), model.b1.clone()).unwrap();
-    let w2 =
-      Array2::from_shape_vec((model.w2_shape.0, model.w2_shape.1), model.w2.clone()).unwrap();
-    let b2 =
-      Array2::from_shape_vec((model.b2_shape.0, model.b2_shape.1), model.b2.clone()).unwrap();
+  /// Width of every layer, including the input, e.g. `[784, 128, 10]`.
+  pub fn layer_sizes(&self) -> Vec<usize> {
+    let mut sizes = vec![self.layers[0].inputs()];
+    sizes.extend(self.layers.iter().map(|layer| layer.outputs()));
+    sizes
+  }
 
-    InferrableModel { w1, b1, w2, b2 }
+  /// Put randomly initialized convolutional layers in front of the dense layers, with
+  /// weights drawn from `rng`. The first dense layer must take their flattened output.
+  pub fn add_conv_layers<R: Rng>(&mut self, configs: &[ConvLayerConfig], rng: &mut R) {
+    let mut channels = Self::image_shape().0;
+    self.conv_layers = configs
+      .iter()
+      .map(|config| {
+        let layer = config.build(channels, rng);
+        if let Some(conv) = layer.as_conv2d() {
+          channels = conv.out_channels();
+        }
+        layer
+      })
+      .collect();
   }
 
-  pub fn to_serializable_model(&self) -> SerializableModel {
-    let (w1_r, w1_c) = self.w1.dim();
-    let (b1_r, b1_c) = self.b1.dim();
-    let (w2_r, w2_c) = self.w2.dim();
-    let (b2_r, b2_c) = self.b2.dim();
+  /// Shape of the images the convolutional layers read.
+  fn image_shape() -> Shape {
+    shape_of(&mnist_input_shape()).unwrap()
+  }
+
+  /// Shape of the feature maps the convolutional layers produce.
+  pub fn conv_output_shape(&self) -> Shape {
+    self
+      .conv_layers
+      .iter()
+      .fold(Self::image_shape(), |shape, layer| {
+        layer
+          .output_shape(shape)
+          .expect("convolutional layers do not fit the image")
+      })
+  }
+
+  /// Give each hidden layer the normalization in `kinds`, one per hidden layer.
+  pub fn add_norm_layers(&mut self, kinds: &[NormKind]) {
+    for (layer, &kind) in self.layers.iter_mut().zip(kinds) {
+      layer.norm = NormLayer::new(kind, layer.outputs());
+    }
+  }
 
+  /// Every trainable parameter: the weights and biases of the convolutional layers,
+  /// then w1, b1, (gamma1, beta1,) w2, b2, ... of the dense layers, matching the
+  /// gradients returned by `backward` once flattened with `Gradients::tensors`.
+  pub fn parameters_mut(&mut self) -> Vec<&mut Array2<f32>> {
+    let conv = self
+      .conv_layers
+      .iter_mut()
+      .filter_map(ConvLayer::as_conv2d_mut)
+      .flat_map(|conv| [&mut conv.w, &mut conv.b]);
+    let dense = self.layers.iter_mut().flat_map(|layer| {
+      let DenseLayer { w, b, norm, .. } = layer;
+      let mut parameters = vec![w, b];
+      if let Some(norm) = norm {
+        parameters.extend([&mut norm.gamma, &mut norm.beta]);
+      }
+      parameters
+    });
+    conv.chain(dense).collect()
+  }
+
+  /// The weights of every convolutional, then every dense layer, without biases or
+  /// normalizations.
+  pub fn weights(&self) -> impl Iterator<Item = &Array2<f32>> {
+    let conv = self
+      .conv_layers
+      .iter()
+      .filter_map(ConvLayer::as_conv2d)
+      .map(|conv| &conv.w);
+    conv.chain(self.layers.iter().map(|layer| &layer.w))
+  }
+
+  /// `weights`, mutably.
+  pub fn weights_mut(&mut self) -> impl Iterator<Item = &mut Array2<f32>> {
+    let conv = self
+      .conv_layers
+      .iter_mut()
+      .filter_map(ConvLayer::as_conv2d_mut)
+      .map(|conv| &mut conv.w);
+    conv.chain(self.layers.iter_mut().map(|layer| &mut layer.w))
+  }
+
+  /// Every weight tensor, including normalization parameters and running statistics,
+  /// under the name it has in a model file.
+  pub fn named_tensors_mut(&mut self) -> Vec<(String, &mut Array2<f32>)> {
+    let mut tensors = Vec::new();
+    for (i, layer) in self.conv_layers.iter_mut().enumerate() {
+      if let Some(conv) = layer.as_conv2d_mut() {
+        tensors.push((format!("conv{}.weight", i + 1), &mut conv.w));
+        tensors.push((format!("conv{}.bias", i + 1), &mut conv.b));
+      }
+    }
+    for (i, layer) in self.layers.iter_mut().enumerate() {
+      let n = i + 1;
+      tensors.push((format!("w{}", n), &mut layer.w));
+      tensors.push((format!("b{}", n), &mut layer.b));
+      if let Some(norm) = &mut layer.norm {
+        tensors.push((format!("norm{}.gamma", n), &mut norm.gamma));
+        tensors.push((format!("norm{}.beta", n), &mut norm.beta));
+        if let Some(running) = &mut norm.running {
+          tensors.push((format!("norm{}.running_mean", n), &mut running.mean));
+          tensors.push((format!("norm{}.running_var", n), &mut running.var));
+        }
+      }
+    }
+    tensors
+  }
+
+  /// Fold the batch statistics of a training forward pass into each batch norm's
+  /// running statistics.
+  pub fn update_running_stats(&mut self, pass: &ForwardPass) {
+    let samples = pass.probabilities.ncols();
+    for (layer, vars) in self.layers.iter_mut().zip(&pass.dense_vars) {
+      if let (Some(norm), Some((mean, var))) =
+        (&mut layer.norm, vars.norm.and_then(|norm| norm.batch_stats))
+      {
+        norm.update_running_stats(&pass.tape.value(mean), &pass.tape.value(var), samples);
+      }
+    }
+  }
+
+  /// Run `input` (features x batch) through the convolutional layers and flatten their
+  /// output. Without convolutional layers the input goes to the dense layers as it is.
+  fn conv_forward(&self, input: &Array2<f32>) -> Array2<f32> {
+    if self.conv_layers.is_empty() {
+      return input.clone();
+    }
+    let mut x = unflatten(input, Self::image_shape());
+    for layer in &self.conv_layers {
+      x = layer.apply(&x);
+    }
+    flatten(&x)
+  }
+
+  /// Forward pass recorded on a tape, keeping every intermediate value for backprop.
+  /// `input` is features x batch, i.e. 784xB for a batch of B images.
+  pub fn forward_pass(&self, input: &Array2<f32>, mut mode: Mode) -> ForwardPass {
+    let tape = Tape::new();
+    let training = matches!(mode, Mode::Train { .. });
+
+    let mut x = tape.constant(input.clone());
+    let mut shape = Self::image_shape();
+    let mut conv_vars = Vec::with_capacity(self.conv_layers.len());
+    for layer in &self.conv_layers {
+      let (y, vars) = layer.record(&tape, x, shape);
+      shape = layer
+        .output_shape(shape)
+        .expect("convolutional layers do not fit the image");
+      conv_vars.push(vars);
+      x = y;
+    }
+
+    let mut dense_vars = Vec::with_capacity(self.layers.len());
+    let mut logits = x;
+    for (i, layer) in self.layers.iter().enumerate() {
+      let input = x;
+      let w = tape.parameter(layer.w.clone());
+      let b = tape.parameter(layer.b.clone());
+      let mut z = tape.add(tape.matmul(w, input), b);
+      let mut norm_vars = None;
+      if let Some(norm) = &layer.norm {
+        let (normalized, vars) = norm.record(&tape, z, training);
+        z = normalized;
+        norm_vars = Some(vars);
+      }
+
+      let mut mask = None;
+      if i == self.layers.len() - 1 {
+        logits = z;
+      } else {
+        x = tape.activation(z, layer.activation);
+        if let Mode::Train { dropout, rng } = &mut mode
+          && let Some(&rate) = dropout.get(i)
+          && rate > 0.0
+        {
+          let m = dropout_mask(rate, tape.value(x).dim(), *rng);
+          let m = tape.constant(m);
+          x = tape.mul(x, m);
+          mask = Some(m);
+        }
+      }
+      dense_vars.push(DenseVars {
+        input,
+        w,
+        b,
+        norm: norm_vars,
+        mask,
+      });
+    }
+
+    // redistribute so each sample's values sum up to 1
+    let probabilities = softmax_columns(&tape.value(logits));
+    ForwardPass {
+      tape,
+      conv_vars,
+      dense_vars,
+      logits,
+      probabilities,
+    }
+  }
+
+  /// Whether any layer holds int8 weights.
+  pub fn is_quantized(&self) -> bool {
+    self.layers.iter().any(|layer| layer.quantized.is_some())
+  }
+
+  /// Forward pass returning only the class probabilities. Eval mode skips recording a
+  /// tape and runs quantized layers in integer arithmetic; train mode records the float
+  /// pass, where quantized layers use their dequantized weights.
+  pub fn forward(&self, input: &Array2<f32>, mode: Mode) -> Array2<f32> {
+    match mode {
+      Mode::Eval => self.forward_eval(input),
+      Mode::Train { .. } => self.forward_pass(input, mode).probabilities,
+    }
+  }
+
+  /// Eval-mode forward pass, without a tape: each quantized layer quantizes its input to
+  /// int8 and multiplies it with its int8 weights in integer arithmetic, other layers run
+  /// in f32. Activations and the final softmax are applied in f32, as are any
+  /// convolutional layers.
+  pub fn forward_eval(&self, input: &Array2<f32>) -> Array2<f32> {
+    let mut a = self.conv_forward(input);
+    for (i, layer) in self.layers.iter().enumerate() {
+      let mut z = layer.linear_quantized(&a);
+      if let Some(norm) = &layer.norm {
+        z = norm.apply(&z);
+      }
+      a = if i == self.layers.len() - 1 {
+        softmax_columns(&z)
+      } else {
+        layer.activation.apply(&z)
+      };
+    }
+    a
+  }
+
+  /// Back-propagate the mean cross-entropy loss for the one-hot targets `y` (classes x
+  /// batch) through the operations recorded by `forward_pass`. Returns one set of
+  /// gradients per layer, in layer order. The result equals the average of the per-sample
+  /// gradients.
+  pub fn backward(&self, pass: &ForwardPass, y: &Array2<f32>) -> Gradients {
+    let tape = &pass.tape;
+    let loss = tape.cross_entropy(tape.log_softmax(pass.logits), y);
+    let mut grads = tape.backward(loss);
+    // the tape leaves out parameters the loss doesn't depend on; they don't affect it
+    let mut take = |var| {
+      grads
+        .take(var)
+        .unwrap_or_else(|| Array2::zeros(tape.value(var).dim()))
+    };
+
+    let conv = pass
+      .conv_vars
+      .iter()
+      .map(|vars| {
+        vars.map(|(w, b)| ConvGradients {
+          dw: take(w),
+          db: take(b),
+        })
+      })
+      .collect();
+    let dense = pass
+      .dense_vars
+      .iter()
+      .map(|vars| DenseGradients {
+        dw: take(vars.w),
+        db: take(vars.b),
+        norm: vars.norm.map(|norm| NormGradients {
+          dgamma: take(norm.gamma),
+          dbeta: take(norm.beta),
+        }),
+      })
+      .collect();
+    Gradients { conv, dense }
+  }
+
+  /// Run a 28x28 grayscale image through the network in eval mode and return the 10x1
+  /// class probabilities.
+  pub fn predict(&self, image_data: &Array2<u8>) -> Array2<f32> {
+    let image = flatten_2d_to_1d(image_data)
+      .mapv(|x| x as f32)
+      .insert_axis(Axis(1));
+
+    self.forward(&self.preprocessor.apply(&image), Mode::Eval)
+  }
+
+  pub fn to_serializable_model(&self) -> SerializableModel {
     SerializableModel {
-      w1: self.w1.iter().cloned().collect(),
-      w1_shape: (w1_r, w1_c),
-      b1: self.b1.iter().cloned().collect(),
-      b1_shape: (b1_r, b1_c),
-      w2: self.w2.iter().cloned().collect(),
-      w2_shape: (w2_r, w2_c),
-      b2: self.b2.iter().cloned().collect(),
-      b2_shape: (b2_r, b2_c),
+      conv_layers: self.conv_layers.clone(),
+      layers: self
+        .layers
+        .iter()
+        .map(|layer| SerializableLayer {
+          w: layer.w.iter().cloned().collect(),
+          w_shape: layer.w.dim(),
+          b: layer.b.iter().cloned().collect(),
+          b_shape: layer.b.dim(),
+          activation: layer.activation,
+          quantized: layer.quantized.clone(),
+          norm: layer.norm.clone(),
+        })
+        .collect(),
+      preprocessor: self.preprocessor.clone(),
+      input_shape: mnist_input_shape(),
+      class_labels: self.class_labels.clone(),
+      training: None,
     }
   }
 }
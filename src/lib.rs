@@ -1,6 +1,24 @@
+pub mod activation;
+pub mod autograd;
+pub mod checkpoint;
+pub mod config;
+pub mod conv;
+pub mod early_stopping;
+pub mod error;
+pub mod evaluation;
 pub mod gui;
+pub mod image_input;
+pub mod infer;
 pub mod inferrable_model;
 pub mod math;
+pub mod misclassified;
+pub mod norm;
+pub mod onnx;
+pub mod optimizer;
+pub mod preprocessing;
+pub mod quantization;
+pub mod regularization;
+pub mod schedule;
 pub mod serializable_model;
 pub mod serialization;
 pub mod stats;
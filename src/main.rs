@@ -1,5 +1,12 @@
 use clap::{Parser, Subcommand};
+use neural_net::checkpoint::{CheckpointPolicy, load_checkpoint};
+use neural_net::config::TrainArgs;
+use neural_net::infer::OutputFormat;
+use neural_net::onnx::export::ExportFormat;
+use neural_net::quantization::Granularity;
+use neural_net::serialization::TensorDtype;
 use neural_net::training::run_train;
+use std::path::PathBuf;
 
 // sigmoid "clamps" values (in a fairly scaled way) to 0..1
 // Training logic moved to `training.rs`
@@ -9,22 +16,107 @@ fn main() {
   let cli = Cli::parse();
 
   match &cli.command {
-    Commands::Train { out } => {
-      run_train(out);
+    Commands::Train {
+      out,
+      save_dtype,
+      checkpoint,
+      checkpoint_every,
+      checkpoint_every_steps,
+      resume,
+      args,
+    } => {
+      let resume = match resume {
+        Some(path) => match load_checkpoint(path) {
+          Ok(checkpoint) => Some(checkpoint),
+          Err(e) => {
+            eprintln!("Failed to load checkpoint {}: {}", path, e);
+            std::process::exit(1);
+          }
+        },
+        None => None,
+      };
+
+      // a resumed run continues with the checkpoint's config; flags can still override
+      // whatever doesn't change the model's shape, the order the data is visited in or the
+      // random draws
+      let base_config = resume.as_ref().map(|c| c.metadata.config.clone());
+      let config = match args.resolve(base_config) {
+        Ok(config) => config,
+        Err(e) => {
+          eprintln!("Invalid training configuration: {}", e);
+          std::process::exit(1);
+        }
+      };
+      if let Some(checkpoint) = &resume
+        && let Err(e) = config.check_resumable(&checkpoint.metadata.config)
+      {
+        eprintln!("Invalid training configuration: {}", e);
+        std::process::exit(1);
+      }
+
+      let checkpoints = CheckpointPolicy {
+        path: checkpoint
+          .as_ref()
+          .map(PathBuf::from)
+          .unwrap_or_else(|| CheckpointPolicy::default_path(out)),
+        every_epochs: *checkpoint_every,
+        every_steps: *checkpoint_every_steps,
+      };
+
+      run_train(out, *save_dtype, &config, &checkpoints, resume);
     }
 
-    Commands::Infer { .. } => {
-      // Not implemented yet
-      unimplemented!("infer is not implemented yet");
+    Commands::Infer {
+      model,
+      input,
+      top_k,
+      format,
+      no_invert,
+    } => {
+      neural_net::infer::infer(model, input, *top_k, *format, !no_invert);
     }
 
-    Commands::GUI { model } => {
+    Commands::Gui { model } => {
       // Create a GUI window
       neural_net::gui::window::create_window(model);
     }
 
-    Commands::Validate { model } => {
-      neural_net::validate::validate(model);
+    Commands::Validate {
+      model,
+      baseline,
+      report,
+      dump_errors,
+      contact_sheet_size,
+    } => {
+      neural_net::validate::validate(
+        model,
+        baseline.as_deref(),
+        report.as_deref(),
+        dump_errors.as_deref(),
+        *contact_sheet_size,
+      );
+    }
+
+    Commands::Quantize {
+      model,
+      out,
+      granularity,
+      calibration_size,
+      seed,
+    } => {
+      neural_net::quantization::run_quantize(model, out, *granularity, *calibration_size, *seed);
+    }
+
+    Commands::Export { model, out, format } => {
+      neural_net::onnx::export::run_export(model, out, *format);
+    }
+
+    Commands::Import {
+      model,
+      out,
+      scale_pixels,
+    } => {
+      neural_net::onnx::import::run_import(model, out, *scale_pixels);
     }
   }
 }
@@ -43,17 +135,61 @@ enum Commands {
     /// Output file to write model weights to
     #[arg(short, long, default_value = "model.safetensors")]
     out: String,
+
+    /// Precision to store the model's weights in; loading converts them back to f32
+    #[arg(long, value_enum, default_value_t = TensorDtype::F32)]
+    save_dtype: TensorDtype,
+
+    /// Where to write checkpoints [default: next to --out, e.g. model.checkpoint.safetensors]
+    #[arg(long)]
+    checkpoint: Option<String>,
+
+    /// Write a checkpoint every N epochs
+    #[arg(long, value_name = "N")]
+    checkpoint_every: Option<usize>,
+
+    /// Write a checkpoint every N optimizer steps
+    #[arg(long, value_name = "N")]
+    checkpoint_every_steps: Option<usize>,
+
+    /// Continue training from a checkpoint, using the config stored in it. Flags that
+    /// change the model, optimizer or data order are rejected
+    #[arg(long, conflicts_with = "config")]
+    resume: Option<String>,
+
+    #[command(flatten)]
+    args: Box<TrainArgs>,
   },
 
-  /// Run inference (not implemented yet)
+  /// Classify image files or raw MNIST (idx3-ubyte) files
   Infer {
-    /// Input image or model path (placeholder)
-    #[arg(short, long)]
-    input: Option<String>,
+    #[arg(short, long, default_value = "model.safetensors")]
+    model: String,
+
+    /// Image files, directories or glob patterns (e.g. "digits/*.png") to classify
+    #[arg(short, long, required = true, num_args = 1..)]
+    input: Vec<String>,
+
+    /// Number of most likely classes to print per input
+    #[arg(
+      short = 'k',
+      long,
+      default_value_t = 3,
+      value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    top_k: usize,
+
+    /// Output format
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    format: OutputFormat,
+
+    /// Use image pixels as-is instead of inverting them; use for white digits on a black background
+    #[arg(long)]
+    no_invert: bool,
   },
 
   /// Create a GUI window
-  GUI {
+  Gui {
     #[arg(short, long, default_value = "model.safetensors")]
     model: String,
   },
@@ -62,5 +198,75 @@ enum Commands {
   Validate {
     #[arg(short, long, default_value = "model.safetensors")]
     model: String,
+
+    /// Also validate this model and report the accuracy difference, e.g. the float model
+    /// a quantized model was made from
+    #[arg(long)]
+    baseline: Option<String>,
+
+    /// Also write the evaluation report to this file, as JSON or CSV depending on its
+    /// extension
+    #[arg(long)]
+    report: Option<String>,
+
+    /// Write each misclassified test image to this directory as an upscaled PNG, plus a
+    /// contact sheet of the most confidently wrong ones
+    #[arg(long)]
+    dump_errors: Option<String>,
+
+    /// Number of images on the --dump-errors contact sheet
+    #[arg(long, default_value_t = 100)]
+    contact_sheet_size: usize,
+  },
+
+  /// Quantize a trained model to int8 weights
+  Quantize {
+    #[arg(short, long, default_value = "model.safetensors")]
+    model: String,
+
+    /// Output file for the quantized model
+    #[arg(short, long, default_value = "model.int8.safetensors")]
+    out: String,
+
+    /// Whether each layer gets one weight scale or one per output neuron
+    #[arg(long, value_enum, default_value_t = Granularity::PerChannel)]
+    granularity: Granularity,
+
+    /// Number of training images to calibrate activation ranges on
+    #[arg(long, default_value_t = 1000)]
+    calibration_size: usize,
+
+    /// Seed for picking the calibration images
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+  },
+
+  /// Export a model for use in other runtimes
+  Export {
+    #[arg(short, long, default_value = "model.safetensors")]
+    model: String,
+
+    /// Output file
+    #[arg(short, long, default_value = "model.onnx")]
+    out: String,
+
+    #[arg(short, long, value_enum, default_value_t = ExportFormat::Onnx)]
+    format: ExportFormat,
+  },
+
+  /// Convert an ONNX model into a model file. ONNX files can also be used directly
+  /// wherever a model is expected
+  Import {
+    /// ONNX file holding a chain of Gemm/MatMul/Add, activation and Softmax nodes
+    #[arg(short, long)]
+    model: String,
+
+    /// Output file to write model weights to
+    #[arg(short, long, default_value = "model.safetensors")]
+    out: String,
+
+    /// The graph expects pixels scaled to [0, 1] instead of raw 0-255 values
+    #[arg(long)]
+    scale_pixels: bool,
   },
 }
@@ -1,17 +1,35 @@
 use ndarray::Array1;
 use ndarray::Array2;
+use ndarray::ArrayView1;
 
 // sigmoid "clamps" values (in a fairly scaled way) to 0..1
 pub fn sigmoid(x: &Array2<f32>) -> Array2<f32> {
-  return x.mapv(|v| 1.0 / (1.0 + (-v).exp()));
+  x.mapv(|v| 1.0 / (1.0 + (-v).exp()))
 }
 
-pub fn softmax(z: &Array2<f32>) -> Array2<f32> {
-  // Stable softmax: subtract max to avoid large exponents
-  let max = z.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
-  let exps = z.mapv(|v| (v - max).exp());
-  let sum = exps.sum();
-  exps.mapv(|e| e / sum)
+/// Index of the largest value, the first one on ties.
+pub fn argmax(values: ArrayView1<f32>) -> usize {
+  values
+    .iter()
+    .enumerate()
+    .fold((0, f32::NEG_INFINITY), |(best, max), (i, &v)| {
+      if v > max { (i, v) } else { (best, max) }
+    })
+    .0
+}
+
+/// Column-wise softmax for a batch laid out as one sample per column (classes x batch).
+/// Each column is normalized on its own so one sample's logits can't affect another's.
+pub fn softmax_columns(z: &Array2<f32>) -> Array2<f32> {
+  let mut out = z.clone();
+  for mut column in out.columns_mut() {
+    // Stable softmax: subtract the column max to avoid large exponents
+    let max = column.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+    column.mapv_inplace(|v| (v - max).exp());
+    let sum = column.sum();
+    column.mapv_inplace(|e| e / sum);
+  }
+  out
 }
 
 pub fn sigmoid_derivative(a: &Array2<f32>) -> Array2<f32> {
@@ -0,0 +1,127 @@
+use image::{GrayImage, Luma};
+use ndarray::{Array2, ArrayView1};
+use std::path::Path;
+
+use crate::image_input::IMAGE_SIZE;
+use crate::math::argmax;
+
+/// Dumped digits are scaled up by this factor so they are easy to look at.
+pub const UPSCALE: u32 = 4;
+/// Tiles per row in the contact sheet.
+const SHEET_COLUMNS: u32 = 10;
+/// Pixels of grey between contact sheet tiles.
+const SHEET_GAP: u32 = 2;
+const SHEET_BACKGROUND: u8 = 128;
+pub const CONTACT_SHEET_NAME: &str = "contact_sheet.png";
+
+/// A test image the model got wrong.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Misclassification {
+  /// Row of the image in the test set.
+  pub index: usize,
+  pub label: usize,
+  pub predicted: usize,
+  /// The probability the model gave `predicted`.
+  pub confidence: f32,
+}
+
+/// Every sample whose most probable class is not its label, most confidently wrong first.
+/// `probabilities` is classes x samples.
+pub fn misclassifications(probabilities: &Array2<f32>, labels: &[u8]) -> Vec<Misclassification> {
+  let mut wrong: Vec<Misclassification> = probabilities
+    .columns()
+    .into_iter()
+    .zip(labels)
+    .enumerate()
+    .filter_map(|(index, (column, &label))| {
+      let predicted = argmax(column);
+      (predicted != label as usize).then_some(Misclassification {
+        index,
+        label: label as usize,
+        predicted,
+        confidence: column[predicted],
+      })
+    })
+    .collect();
+  wrong.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+  wrong
+}
+
+/// Keep file names portable whatever the class labels are.
+fn file_safe(label: &str) -> String {
+  label
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .collect()
+}
+
+/// e.g. `00042_true-7_pred-1_0.9812.png`. Classes without a label, such as a dataset
+/// class the model has no output for, are named by their number.
+pub fn file_name(wrong: &Misclassification, class_labels: &[String]) -> String {
+  let label = |class: usize| match class_labels.get(class) {
+    Some(label) => file_safe(label),
+    None => class.to_string(),
+  };
+  format!(
+    "{:05}_true-{}_pred-{}_{:.4}.png",
+    wrong.index,
+    label(wrong.label),
+    label(wrong.predicted),
+    wrong.confidence
+  )
+}
+
+/// A 28x28 image of raw 0-255 `pixels`, each pixel scaled up to a `scale` x `scale` block.
+fn digit_image(pixels: ArrayView1<f32>, scale: u32) -> GrayImage {
+  let size = IMAGE_SIZE as u32;
+  GrayImage::from_fn(size * scale, size * scale, |x, y| {
+    let pixel = pixels[(y / scale) as usize * IMAGE_SIZE + (x / scale) as usize];
+    Luma([pixel.clamp(0.0, 255.0) as u8])
+  })
+}
+
+/// The digits of `wrong`, in order, left to right and top to bottom.
+pub fn contact_sheet(images: &Array2<f32>, wrong: &[Misclassification]) -> GrayImage {
+  let tile = IMAGE_SIZE as u32 * UPSCALE;
+  let columns = SHEET_COLUMNS.min(wrong.len() as u32).max(1);
+  let rows = (wrong.len() as u32).div_ceil(columns).max(1);
+  let mut sheet = GrayImage::from_pixel(
+    columns * (tile + SHEET_GAP) + SHEET_GAP,
+    rows * (tile + SHEET_GAP) + SHEET_GAP,
+    Luma([SHEET_BACKGROUND]),
+  );
+  for (i, wrong) in wrong.iter().enumerate() {
+    let (column, row) = (i as u32 % columns, i as u32 / columns);
+    let digit = digit_image(images.row(wrong.index), UPSCALE);
+    image::imageops::replace(
+      &mut sheet,
+      &digit,
+      (SHEET_GAP + column * (tile + SHEET_GAP)) as i64,
+      (SHEET_GAP + row * (tile + SHEET_GAP)) as i64,
+    );
+  }
+  sheet
+}
+
+/// Write every misclassified image (one per row of `images`) to `dir` as an upscaled
+/// PNG, plus a contact sheet of the `sheet_size` most confidently wrong ones. Returns
+/// the misclassifications, most confident first.
+pub fn dump_errors<P: AsRef<Path>>(
+  dir: P,
+  images: &Array2<f32>,
+  labels: &[u8],
+  probabilities: &Array2<f32>,
+  class_labels: &[String],
+  sheet_size: usize,
+) -> Result<Vec<Misclassification>, image::ImageError> {
+  let dir = dir.as_ref();
+  std::fs::create_dir_all(dir)?;
+
+  let wrong = misclassifications(probabilities, labels);
+  for wrong in &wrong {
+    digit_image(images.row(wrong.index), UPSCALE).save(dir.join(file_name(wrong, class_labels)))?;
+  }
+  let worst = &wrong[..sheet_size.min(wrong.len())];
+  contact_sheet(images, worst).save(dir.join(CONTACT_SHEET_NAME))?;
+  Ok(wrong)
+}
@@ -0,0 +1,170 @@
+use clap::ValueEnum;
+use ndarray::{Array2, Axis};
+use serde::{Deserialize, Serialize};
+
+use crate::autograd::{Tape, Var};
+
+/// Added to the variance before taking its square root, so constant features don't
+/// divide by zero.
+pub const NORM_EPSILON: f32 = 1e-5;
+/// Weight of each new batch in batch norm's running mean and variance.
+pub const RUNNING_MOMENTUM: f32 = 0.1;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum NormKind {
+  None,
+  /// Normalize each feature over the batch (BatchNorm1d), using running statistics in
+  /// eval mode
+  BatchNorm,
+  /// Normalize each sample over its features
+  LayerNorm,
+}
+
+/// Mean and variance of each feature (`features x 1`) seen while training, which batch
+/// norm normalizes with in eval mode.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunningStats {
+  pub mean: Array2<f32>,
+  pub var: Array2<f32>,
+}
+
+/// Normalization of a dense layer's `w . x + b`, applied before its activation:
+/// `gamma * (z - mean) / sqrt(var + eps) + beta`. Batch norm takes the mean and variance
+/// of each feature over the batch, layer norm those of each sample over its features.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NormLayer {
+  pub kind: NormKind,
+  /// Learned scale of each feature, `features x 1`.
+  pub gamma: Array2<f32>,
+  /// Learned shift of each feature, `features x 1`.
+  pub beta: Array2<f32>,
+  /// Batch norm only.
+  pub running: Option<RunningStats>,
+}
+
+/// The tape variables of a recorded normalization.
+#[derive(Copy, Clone)]
+pub struct NormVars {
+  pub gamma: Var,
+  pub beta: Var,
+  /// Normalized values, before `gamma` and `beta` are applied.
+  pub x_hat: Var,
+  /// Mean and variance of the batch, when batch norm normalized with them rather than
+  /// its running statistics.
+  pub batch_stats: Option<(Var, Var)>,
+}
+
+/// Gradients for a normalization's `gamma` and `beta`, summed over the batch.
+pub struct NormGradients {
+  pub dgamma: Array2<f32>,
+  pub dbeta: Array2<f32>,
+}
+
+impl NormLayer {
+  /// An identity normalization (`gamma` 1, `beta` 0) of `features` values, or `None` for
+  /// `NormKind::None`.
+  pub fn new(kind: NormKind, features: usize) -> Option<Self> {
+    let running = match kind {
+      NormKind::None => return None,
+      NormKind::BatchNorm => Some(RunningStats {
+        mean: Array2::zeros((features, 1)),
+        var: Array2::ones((features, 1)),
+      }),
+      NormKind::LayerNorm => None,
+    };
+    Some(NormLayer {
+      kind,
+      gamma: Array2::ones((features, 1)),
+      beta: Array2::zeros((features, 1)),
+      running,
+    })
+  }
+
+  pub fn features(&self) -> usize {
+    self.gamma.nrows()
+  }
+
+  /// The axis statistics are taken over: the samples (columns) for batch norm, the
+  /// features (rows) for layer norm.
+  fn axis(&self) -> Axis {
+    match self.kind {
+      NormKind::BatchNorm => Axis(1),
+      _ => Axis(0),
+    }
+  }
+
+  /// Normalize `z` (features x batch) in eval mode: batch norm with its running
+  /// statistics, layer norm with each sample's own.
+  pub fn apply(&self, z: &Array2<f32>) -> Array2<f32> {
+    let stats;
+    let (mean, var) = match &self.running {
+      Some(running) => (&running.mean, &running.var),
+      None => {
+        let axis = self.axis();
+        let mean = z.mean_axis(axis).unwrap().insert_axis(axis);
+        let var = (z - &mean)
+          .mapv(|v| v * v)
+          .mean_axis(axis)
+          .unwrap()
+          .insert_axis(axis);
+        stats = (mean, var);
+        (&stats.0, &stats.1)
+      }
+    };
+    (z - mean) / var.mapv(|v| (v + NORM_EPSILON).sqrt()) * &self.gamma + &self.beta
+  }
+
+  /// Record the normalization of `z` on `tape`. Batch norm uses the batch's statistics
+  /// when `training` and its running statistics otherwise. Returns the output and the
+  /// variables `update_running_stats` reads.
+  pub fn record(&self, tape: &Tape, z: Var, training: bool) -> (Var, NormVars) {
+    let (mean, var, centered) = match (&self.running, training) {
+      (Some(running), false) => {
+        let mean = tape.constant(running.mean.clone());
+        let var = tape.constant(running.var.clone());
+        (mean, var, tape.sub(z, mean))
+      }
+      _ => {
+        let axis = self.axis();
+        let mean = tape.mean_axis(z, axis);
+        let centered = tape.sub(z, mean);
+        let var = tape.mean_axis(tape.mul(centered, centered), axis);
+        (mean, var, centered)
+      }
+    };
+
+    let epsilon = tape.constant(Array2::from_elem((1, 1), NORM_EPSILON));
+    let inv_std = tape.powf(tape.add(var, epsilon), -0.5);
+    let x_hat = tape.mul(centered, inv_std);
+    let gamma = tape.parameter(self.gamma.clone());
+    let beta = tape.parameter(self.beta.clone());
+    let y = tape.add(tape.mul(x_hat, gamma), beta);
+    let vars = NormVars {
+      gamma,
+      beta,
+      x_hat,
+      batch_stats: (self.running.is_some() && training).then_some((mean, var)),
+    };
+    (y, vars)
+  }
+
+  /// Fold the `mean` and `var` of a training batch of `samples` into the running
+  /// statistics. The running variance is the unbiased estimate, so a batch of one sample
+  /// is skipped.
+  pub fn update_running_stats(&mut self, mean: &Array2<f32>, var: &Array2<f32>, samples: usize) {
+    let Some(running) = &mut self.running else {
+      return;
+    };
+    let n = samples as f32;
+    if n < 2.0 {
+      return;
+    }
+    running.mean *= 1.0 - RUNNING_MOMENTUM;
+    running.mean.scaled_add(RUNNING_MOMENTUM, mean);
+    running.var *= 1.0 - RUNNING_MOMENTUM;
+    running
+      .var
+      .scaled_add(RUNNING_MOMENTUM * n / (n - 1.0), var);
+  }
+}
@@ -0,0 +1,399 @@
+use clap::ValueEnum;
+use ndarray::Array2;
+use std::path::Path;
+
+use super::proto::{
+  Attribute, AttributeValue, DATA_TYPE_FLOAT, DATA_TYPE_INT64, Dimension, GraphProto, IR_VERSION,
+  ModelProto, NodeProto, OPSET_VERSION, OperatorSetId, TensorProto, ValueInfo,
+};
+use super::{CLASS_LABELS_KEY, INPUT_NAME, INPUT_SHAPE_KEY, OUTPUT_NAME};
+use crate::activation::{Activation, ELU_ALPHA, GELU_COEFF, GELU_SQRT_2_OVER_PI, LEAKY_RELU_SLOPE};
+use crate::conv::{ConvLayer, PoolKind};
+use crate::inferrable_model::{DenseLayer, InferrableModel};
+use crate::norm::{NORM_EPSILON, NormLayer};
+use crate::preprocessing::PreprocessStep;
+use crate::serializable_model::mnist_input_shape;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+  Onnx,
+}
+
+/// Builds a graph one node at a time, each node reading the previous node's output.
+struct GraphBuilder {
+  graph: GraphProto,
+  /// Name of the value the next node reads.
+  current: String,
+  constants: usize,
+}
+
+impl GraphBuilder {
+  fn initializer(&mut self, name: String, dims: &[usize], values: Vec<f32>) -> String {
+    self.graph.initializer.push(TensorProto {
+      name: name.clone(),
+      dims: dims.iter().map(|&d| d as i64).collect(),
+      data_type: DATA_TYPE_FLOAT,
+      float_data: values,
+      int64_data: Vec::new(),
+    });
+    name
+  }
+
+  /// An int64 vector initializer, as shapes are given.
+  fn int64_initializer(&mut self, name: String, values: Vec<i64>) -> String {
+    self.graph.initializer.push(TensorProto {
+      name: name.clone(),
+      dims: vec![values.len() as i64],
+      data_type: DATA_TYPE_INT64,
+      float_data: Vec::new(),
+      int64_data: values,
+    });
+    name
+  }
+
+  /// A scalar initializer.
+  fn constant(&mut self, value: f32) -> String {
+    self.constants += 1;
+    self.initializer(format!("constant{}", self.constants), &[], vec![value])
+  }
+
+  /// Add a node reading `inputs` and return the name of its output.
+  fn node(&mut self, op_type: &str, inputs: &[&str], attribute: Vec<Attribute>) -> String {
+    let name = format!("{}_{}", op_type, self.graph.node.len() + 1);
+    self.graph.node.push(NodeProto {
+      name: name.clone(),
+      op_type: op_type.to_string(),
+      domain: String::new(),
+      input: inputs.iter().map(|input| input.to_string()).collect(),
+      output: vec![name.clone()],
+      attribute,
+    });
+    name
+  }
+
+  /// Add a node reading the current value (first) and `others`; its output becomes the
+  /// current value.
+  fn then(&mut self, op_type: &str, others: &[&str], attribute: Vec<Attribute>) -> String {
+    let current = self.current.clone();
+    let inputs: Vec<&str> = std::iter::once(current.as_str())
+      .chain(others.iter().copied())
+      .collect();
+    self.current = self.node(op_type, &inputs, attribute);
+    self.current.clone()
+  }
+
+  fn activation(&mut self, activation: Activation) {
+    let alpha = |value| {
+      vec![Attribute {
+        name: "alpha".to_string(),
+        value: AttributeValue::Float(value),
+      }]
+    };
+    match activation {
+      Activation::Sigmoid => _ = self.then("Sigmoid", &[], vec![]),
+      Activation::Relu => _ = self.then("Relu", &[], vec![]),
+      Activation::LeakyRelu => _ = self.then("LeakyRelu", &[], alpha(LEAKY_RELU_SLOPE)),
+      Activation::Tanh => _ = self.then("Tanh", &[], vec![]),
+      Activation::Elu => _ = self.then("Elu", &[], alpha(ELU_ALPHA)),
+      Activation::Silu => {
+        // x * sigmoid(x)
+        let x = self.current.clone();
+        let sigmoid = self.node("Sigmoid", &[&x], vec![]);
+        self.then("Mul", &[&sigmoid], vec![]);
+      }
+      Activation::Gelu => {
+        // opset 13 has no Gelu: 0.5 * x * (1 + tanh(sqrt(2/pi) * (x + 0.044715 * x^3)))
+        let x = self.current.clone();
+        let (coeff, sqrt_2_over_pi) = (
+          self.constant(GELU_COEFF),
+          self.constant(GELU_SQRT_2_OVER_PI),
+        );
+        let (one, half) = (self.constant(1.0), self.constant(0.5));
+        let square = self.node("Mul", &[&x, &x], vec![]);
+        let cube = self.node("Mul", &[&square, &x], vec![]);
+        let scaled_cube = self.node("Mul", &[&cube, &coeff], vec![]);
+        let inner = self.node("Add", &[&x, &scaled_cube], vec![]);
+        let inner = self.node("Mul", &[&inner, &sqrt_2_over_pi], vec![]);
+        let tanh = self.node("Tanh", &[&inner], vec![]);
+        let gate = self.node("Add", &[&tanh, &one], vec![]);
+        let gate = self.node("Mul", &[&gate, &half], vec![]);
+        self.then("Mul", &[&gate], vec![]);
+      }
+      Activation::Identity => {}
+    }
+  }
+
+  /// A convolutional or pooling layer, with a convolution's tensors named after `layer`.
+  fn conv_layer(&mut self, layer: usize, conv_layer: &ConvLayer) {
+    let ints = |name: &str, value: usize, count: usize| Attribute {
+      name: name.to_string(),
+      value: AttributeValue::Ints(vec![value as i64; count]),
+    };
+    match conv_layer {
+      ConvLayer::Conv2d(conv) => {
+        let k = conv.kernel_size;
+        let weight = self.initializer(
+          format!("conv{}.weight", layer),
+          &[conv.out_channels(), conv.in_channels, k, k],
+          row_major(&conv.w),
+        );
+        let bias = self.initializer(
+          format!("conv{}.bias", layer),
+          &[conv.out_channels()],
+          row_major(&conv.b),
+        );
+        self.then(
+          "Conv",
+          &[&weight, &bias],
+          vec![
+            ints("kernel_shape", k, 2),
+            ints("strides", conv.stride, 2),
+            ints("pads", conv.padding, 4),
+          ],
+        );
+        self.activation(conv.activation);
+      }
+      ConvLayer::Pool2d(pool) => {
+        let op_type = match pool.kind {
+          PoolKind::Max => "MaxPool",
+          PoolKind::Avg => "AveragePool",
+        };
+        self.then(
+          op_type,
+          &[],
+          vec![
+            ints("kernel_shape", pool.size, 2),
+            ints("strides", pool.stride, 2),
+          ],
+        );
+      }
+    }
+  }
+
+  /// Layer norm over the features of each sample, with `gamma` and `beta` named after
+  /// `layer`. Opset 13 has no LayerNormalization, so it is spelled out.
+  fn layer_norm(&mut self, layer: usize, norm: &NormLayer) {
+    let mean_over_features = || {
+      vec![Attribute {
+        name: "axes".to_string(),
+        value: AttributeValue::Ints(vec![1]),
+      }]
+    };
+    let gamma = self.initializer(
+      format!("norm{}.gamma", layer),
+      &[norm.features()],
+      row_major(&norm.gamma),
+    );
+    let beta = self.initializer(
+      format!("norm{}.beta", layer),
+      &[norm.features()],
+      row_major(&norm.beta),
+    );
+    let epsilon = self.constant(NORM_EPSILON);
+
+    let x = self.current.clone();
+    let mean = self.node("ReduceMean", &[&x], mean_over_features());
+    let centered = self.node("Sub", &[&x, &mean], vec![]);
+    let square = self.node("Mul", &[&centered, &centered], vec![]);
+    let var = self.node("ReduceMean", &[&square], mean_over_features());
+    let var = self.node("Add", &[&var, &epsilon], vec![]);
+    let std = self.node("Sqrt", &[&var], vec![]);
+    self.current = self.node("Div", &[&centered, &std], vec![]);
+    self.then("Mul", &[&gamma], vec![]);
+    self.then("Add", &[&beta], vec![]);
+  }
+}
+
+/// The layer's weights and bias, with a batch norm (a fixed scale and shift of each
+/// output in eval mode) folded into them.
+fn folded_weights(layer: &DenseLayer) -> (Array2<f32>, Array2<f32>) {
+  match layer
+    .norm
+    .as_ref()
+    .and_then(|norm| Some((norm, norm.running.as_ref()?)))
+  {
+    Some((norm, running)) => {
+      let scale = &norm.gamma * &running.var.mapv(|v| 1.0 / (v + NORM_EPSILON).sqrt());
+      let w = &layer.w * &scale;
+      let b = (&layer.b - &running.mean) * &scale + &norm.beta;
+      (w, b)
+    }
+    None => (layer.w.clone(), layer.b.clone()),
+  }
+}
+
+fn row_major(arr: &Array2<f32>) -> Vec<f32> {
+  arr.iter().cloned().collect()
+}
+
+/// Build an ONNX graph computing what `model.predict` does, for a batch of images at a
+/// time: the input is raw 0-255 pixels, `batch x 784`, and the preprocessing steps come
+/// first as Mul, Sub/Div and Sub/MatMul nodes. Convolutional layers reshape the input to
+/// `batch x channels x height x width` and run Conv and MaxPool/AveragePool nodes, and a
+/// Flatten hands their output to the dense layers. Each dense layer is a Gemm followed
+/// by its activation, and a Softmax produces the class probabilities. Batch norms are folded
+/// into the Gemm before them, and layer norms follow it. Quantized models are exported
+/// with their dequantized weights.
+pub fn to_onnx(model: &InferrableModel) -> ModelProto {
+  let input_shape = mnist_input_shape();
+  let input_size: usize = input_shape.iter().product();
+  let mut builder = GraphBuilder {
+    graph: GraphProto {
+      name: "neural-net".to_string(),
+      ..Default::default()
+    },
+    current: INPUT_NAME.to_string(),
+    constants: 0,
+  };
+
+  for (i, step) in model.preprocessor.steps.iter().enumerate() {
+    let name = |tensor: &str| format!("preprocess{}.{}", i + 1, tensor);
+    match step {
+      PreprocessStep::Scale { factor } => {
+        let factor = builder.initializer(name("factor"), &[], vec![*factor]);
+        builder.then("Mul", &[&factor], vec![]);
+      }
+      PreprocessStep::Standardize { mean, std } => {
+        let mean = builder.initializer(name("mean"), &[mean.nrows()], row_major(mean));
+        let std = builder.initializer(name("std"), &[std.nrows()], row_major(std));
+        builder.then("Sub", &[&mean], vec![]);
+        builder.then("Div", &[&std], vec![]);
+      }
+      PreprocessStep::Whiten { mean, projection } => {
+        let mean = builder.initializer(name("mean"), &[mean.nrows()], row_major(mean));
+        // rows are samples here, so x . projection^T
+        let (components, features) = projection.dim();
+        let projection = builder.initializer(
+          name("projection"),
+          &[features, components],
+          row_major(&projection.t().to_owned()),
+        );
+        builder.then("Sub", &[&mean], vec![]);
+        builder.then("MatMul", &[&projection], vec![]);
+      }
+    }
+  }
+
+  if !model.conv_layers.is_empty() {
+    // -1 keeps the batch size
+    let shape = std::iter::once(-1)
+      .chain(input_shape.iter().map(|&d| d as i64))
+      .collect();
+    let shape = builder.int64_initializer("image_shape".to_string(), shape);
+    builder.then("Reshape", &[&shape], vec![]);
+    for (i, layer) in model.conv_layers.iter().enumerate() {
+      builder.conv_layer(i + 1, layer);
+    }
+    builder.then(
+      "Flatten",
+      &[],
+      vec![Attribute {
+        name: "axis".to_string(),
+        value: AttributeValue::Int(1),
+      }],
+    );
+  }
+
+  let last = model.layers.len() - 1;
+  for (i, layer) in model.layers.iter().enumerate() {
+    let (w, b) = folded_weights(layer);
+    let weight = builder.initializer(
+      format!("w{}", i + 1),
+      &[layer.outputs(), layer.inputs()],
+      row_major(&w),
+    );
+    let bias = builder.initializer(format!("b{}", i + 1), &[layer.outputs()], row_major(&b));
+    // Gemm computes x . w^T + b with transB set
+    builder.then(
+      "Gemm",
+      &[&weight, &bias],
+      vec![Attribute {
+        name: "transB".to_string(),
+        value: AttributeValue::Int(1),
+      }],
+    );
+    if let Some(norm) = &layer.norm
+      && norm.running.is_none()
+    {
+      builder.layer_norm(i + 1, norm);
+    }
+    if i < last {
+      builder.activation(layer.activation);
+    }
+  }
+  builder.then(
+    "Softmax",
+    &[],
+    vec![Attribute {
+      name: "axis".to_string(),
+      value: AttributeValue::Int(1),
+    }],
+  );
+
+  let mut graph = builder.graph;
+  graph.node.last_mut().unwrap().output = vec![OUTPUT_NAME.to_string()];
+  let batch = || Dimension::Param("batch".to_string());
+  graph.input.push(ValueInfo {
+    name: INPUT_NAME.to_string(),
+    elem_type: DATA_TYPE_FLOAT,
+    shape: vec![batch(), Dimension::Value(input_size as i64)],
+  });
+  graph.output.push(ValueInfo {
+    name: OUTPUT_NAME.to_string(),
+    elem_type: DATA_TYPE_FLOAT,
+    shape: vec![batch(), Dimension::Value(model.class_labels.len() as i64)],
+  });
+
+  ModelProto {
+    ir_version: IR_VERSION,
+    opset_import: vec![OperatorSetId {
+      domain: String::new(),
+      version: OPSET_VERSION,
+    }],
+    producer_name: env!("CARGO_PKG_NAME").to_string(),
+    producer_version: env!("CARGO_PKG_VERSION").to_string(),
+    graph,
+    metadata_props: vec![
+      (
+        CLASS_LABELS_KEY.to_string(),
+        serde_json::to_string(&model.class_labels).unwrap(),
+      ),
+      (
+        INPUT_SHAPE_KEY.to_string(),
+        serde_json::to_string(&input_shape).unwrap(),
+      ),
+    ],
+  }
+}
+
+pub fn export_onnx<P: AsRef<Path>>(model: &InferrableModel, path: P) -> std::io::Result<()> {
+  std::fs::write(path, to_onnx(model).encode())
+}
+
+/// Export the model at `model_path` to `out_path` in `format`.
+pub fn run_export(model_path: &str, out_path: &str, format: ExportFormat) {
+  let model = match InferrableModel::load(model_path) {
+    Ok(model) => model,
+    Err(e) => {
+      eprintln!("Failed to load model from {}: {}", model_path, e);
+      std::process::exit(1);
+    }
+  };
+  if model.is_quantized() {
+    println!(
+      "Note: {} is quantized; exporting its dequantized weights",
+      model_path
+    );
+  }
+
+  let result = match format {
+    ExportFormat::Onnx => export_onnx(&model, out_path),
+  };
+  match result {
+    Ok(()) => println!("Model exported to {}", out_path),
+    Err(e) => {
+      eprintln!("Failed to write {}: {}", out_path, e);
+      std::process::exit(1);
+    }
+  }
+}
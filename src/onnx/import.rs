@@ -0,0 +1,555 @@
+use ndarray::Array2;
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::CLASS_LABELS_KEY;
+use super::proto::{
+  AttributeValue, DATA_TYPE_FLOAT, Dimension, ModelProto, NodeProto, TensorProto,
+};
+use crate::activation::{Activation, ELU_ALPHA, LEAKY_RELU_SLOPE};
+use crate::error::ModelError;
+use crate::inferrable_model::{DenseLayer, InferrableModel};
+use crate::preprocessing::{PreprocessStep, Preprocessor};
+use crate::serializable_model::{digit_labels, mnist_input_shape};
+use crate::serialization::TensorDtype;
+
+/// Operators `from_onnx` understands.
+pub const SUPPORTED_OPS: &str = "Gemm, MatMul, Add, Sub, Mul, Div, Relu, LeakyRelu, Sigmoid, Tanh, \
+  Elu, Softmax, Flatten, Identity, Dropout";
+
+fn unsupported(message: impl Into<String>) -> ModelError {
+  ModelError::UnsupportedGraph(message.into())
+}
+
+/// A float initializer as a matrix. Scalars and vectors become a single row.
+fn matrix(tensor: &TensorProto) -> Result<Array2<f32>, ModelError> {
+  if tensor.data_type != DATA_TYPE_FLOAT {
+    return Err(unsupported(format!(
+      "initializer {} has data type {}, only float is supported",
+      tensor.name, tensor.data_type
+    )));
+  }
+  let shape = match tensor.dims[..] {
+    [] => (1, 1),
+    [n] => (1, n as usize),
+    [rows, cols] => (rows as usize, cols as usize),
+    _ => {
+      return Err(unsupported(format!(
+        "initializer {} has {} dimensions, at most 2 are supported",
+        tensor.name,
+        tensor.dims.len()
+      )));
+    }
+  };
+  Array2::from_shape_vec(shape, tensor.float_data.clone()).map_err(|_| ModelError::ShapeMismatch {
+    name: tensor.name.clone(),
+    expected: tensor.dims.iter().map(|&d| d as usize).collect(),
+    actual: vec![tensor.float_data.len()],
+  })
+}
+
+/// A scalar, vector or single-row initializer as an `n x 1` column.
+fn column(tensor: &TensorProto) -> Result<Array2<f32>, ModelError> {
+  let values = matrix(tensor)?;
+  if values.nrows() != 1 {
+    return Err(unsupported(format!(
+      "{} has shape {:?}, expected a vector",
+      tensor.name, tensor.dims
+    )));
+  }
+  Ok(values.reversed_axes())
+}
+
+fn float_attribute(node: &NodeProto, name: &str, default: f32) -> Result<f32, ModelError> {
+  match node.attribute(name) {
+    None => Ok(default),
+    Some(AttributeValue::Float(value)) => Ok(*value),
+    Some(_) => Err(unsupported(format!(
+      "{}: attribute {} is not a float",
+      node.name, name
+    ))),
+  }
+}
+
+fn int_attribute(node: &NodeProto, name: &str, default: i64) -> Result<i64, ModelError> {
+  match node.attribute(name) {
+    None => Ok(default),
+    Some(AttributeValue::Int(value)) => Ok(*value),
+    Some(_) => Err(unsupported(format!(
+      "{}: attribute {} is not an int",
+      node.name, name
+    ))),
+  }
+}
+
+/// Walks the chain of nodes, turning leading Mul/Sub/Div/MatMul nodes into preprocessing
+/// steps and the rest into dense layers.
+struct Importer<'a> {
+  initializers: HashMap<&'a str, &'a TensorProto>,
+  /// The value the next node must read.
+  current: String,
+  /// Width of `current`.
+  width: usize,
+  steps: Vec<PreprocessStep>,
+  layers: Vec<DenseLayer>,
+  /// The last step is a Sub, which a Div turns into a standardization and a MatMul into
+  /// a whitening.
+  after_sub: bool,
+  /// The last layer came from a MatMul, so a following Add is its bias.
+  after_matmul: bool,
+  softmax: bool,
+}
+
+impl<'a> Importer<'a> {
+  /// The initializer inputs of `node`, after checking that its one other input is the
+  /// current value.
+  fn constants(&self, node: &NodeProto) -> Result<Vec<&'a TensorProto>, ModelError> {
+    let mut constants = Vec::new();
+    let mut reads_current = false;
+    for (i, input) in node.input.iter().enumerate() {
+      if let Some(tensor) = self.initializers.get(input.as_str()) {
+        constants.push(*tensor);
+      } else if *input == self.current && !reads_current {
+        // matrix products and Sub/Div need the data as their first operand
+        if i != 0 && matches!(node.op_type.as_str(), "Gemm" | "MatMul" | "Sub" | "Div") {
+          return Err(unsupported(format!(
+            "{} ({}) reads {} as its second operand",
+            node.name, node.op_type, input
+          )));
+        }
+        reads_current = true;
+      } else if !input.is_empty() {
+        return Err(unsupported(format!(
+          "{} ({}) reads {}, which is neither the previous node's output nor an initializer; \
+           only chains of nodes are supported",
+          node.name, node.op_type, input
+        )));
+      }
+    }
+    if !reads_current {
+      return Err(unsupported(format!(
+        "{} ({}) does not read the previous node's output {}",
+        node.name, node.op_type, self.current
+      )));
+    }
+    Ok(constants)
+  }
+
+  fn one_constant(&self, node: &NodeProto) -> Result<&'a TensorProto, ModelError> {
+    match self.constants(node)?[..] {
+      [tensor] => Ok(tensor),
+      _ => Err(unsupported(format!(
+        "{} ({}) should have exactly one initializer input",
+        node.name, node.op_type
+      ))),
+    }
+  }
+
+  /// `values` as an `n x 1` column of the current width; a scalar is repeated.
+  fn broadcast(&self, tensor: &TensorProto, rows: usize) -> Result<Array2<f32>, ModelError> {
+    let values = column(tensor)?;
+    if values.len() == 1 {
+      return Ok(Array2::from_elem((rows, 1), values[[0, 0]]));
+    }
+    if values.nrows() != rows {
+      return Err(ModelError::ShapeMismatch {
+        name: tensor.name.clone(),
+        expected: vec![rows],
+        actual: tensor.dims.iter().map(|&d| d as usize).collect(),
+      });
+    }
+    Ok(values)
+  }
+
+  fn add_layer(&mut self, w: Array2<f32>, b: Array2<f32>) -> Result<(), ModelError> {
+    if w.ncols() != self.width {
+      return Err(ModelError::LayerMismatch {
+        layer: self.layers.len() + 1,
+        inputs: w.ncols(),
+        received: self.width,
+      });
+    }
+    self.width = w.nrows();
+    self.layers.push(DenseLayer {
+      w,
+      b,
+      activation: Activation::Identity,
+      quantized: None,
+      norm: None,
+    });
+    Ok(())
+  }
+
+  fn set_activation(&mut self, node: &NodeProto, activation: Activation) -> Result<(), ModelError> {
+    self.constants(node)?;
+    match self.layers.last_mut() {
+      Some(layer) if layer.activation == Activation::Identity => {
+        layer.activation = activation;
+        Ok(())
+      }
+      Some(_) => Err(unsupported(format!(
+        "{} ({}) follows another activation",
+        node.name, node.op_type
+      ))),
+      None => Err(unsupported(format!(
+        "{} ({}) comes before the first layer",
+        node.name, node.op_type
+      ))),
+    }
+  }
+
+  fn node(&mut self, node: &NodeProto) -> Result<(), ModelError> {
+    if !node.domain.is_empty() && node.domain != "ai.onnx" {
+      return Err(ModelError::UnsupportedOp {
+        op: format!("{}.{}", node.domain, node.op_type),
+        node: node.name.clone(),
+      });
+    }
+    if self.softmax {
+      return Err(unsupported(format!(
+        "{} ({}) follows the Softmax, which must come last",
+        node.name, node.op_type
+      )));
+    }
+    let preprocessing = self.layers.is_empty();
+    let after_sub = std::mem::take(&mut self.after_sub);
+    let after_matmul = std::mem::take(&mut self.after_matmul);
+
+    match node.op_type.as_str() {
+      "Gemm" => {
+        let (weight, bias) = match self.constants(node)?[..] {
+          [weight] => (weight, None),
+          [weight, bias] => (weight, Some(bias)),
+          _ => {
+            return Err(unsupported(format!(
+              "{} (Gemm) should have a weight and an optional bias initializer",
+              node.name
+            )));
+          }
+        };
+        if int_attribute(node, "transA", 0)? != 0 {
+          return Err(unsupported(format!("{} (Gemm) has transA set", node.name)));
+        }
+        let alpha = float_attribute(node, "alpha", 1.0)?;
+        let beta = float_attribute(node, "beta", 1.0)?;
+        let mut w = matrix(weight)? * alpha;
+        // without transB the weight is inputs x outputs
+        if int_attribute(node, "transB", 0)? == 0 {
+          w = w.reversed_axes().as_standard_layout().to_owned();
+        }
+        let b = match bias {
+          Some(bias) => self.broadcast(bias, w.nrows())? * beta,
+          None => Array2::zeros((w.nrows(), 1)),
+        };
+        self.add_layer(w, b)?;
+      }
+      "MatMul" => {
+        let weight = self.one_constant(node)?;
+        // rows are samples, so the weight is inputs x outputs
+        let w = matrix(weight)?
+          .reversed_axes()
+          .as_standard_layout()
+          .to_owned();
+        if preprocessing && after_sub {
+          let Some(PreprocessStep::Standardize { mean, .. }) = self.steps.pop() else {
+            unreachable!("a Sub always adds a standardization");
+          };
+          if w.ncols() != self.width {
+            return Err(unsupported(format!(
+              "{} projects {} values, but its input has {}",
+              weight.name,
+              w.ncols(),
+              self.width
+            )));
+          }
+          self.width = w.nrows();
+          self.steps.push(PreprocessStep::Whiten {
+            mean,
+            projection: w,
+          });
+        } else {
+          let outputs = w.nrows();
+          self.add_layer(w, Array2::zeros((outputs, 1)))?;
+          self.after_matmul = true;
+        }
+      }
+      "Add" if after_matmul => {
+        let bias = self.one_constant(node)?;
+        let layer = self.layers.last().unwrap();
+        let b = self.broadcast(bias, layer.outputs())?;
+        self.layers.last_mut().unwrap().b = b;
+      }
+      "Sub" if preprocessing => {
+        let mean = self.broadcast(self.one_constant(node)?, self.width)?;
+        self.steps.push(PreprocessStep::Standardize {
+          std: Array2::ones(mean.dim()),
+          mean,
+        });
+        self.after_sub = true;
+      }
+      "Div" if preprocessing && after_sub => {
+        let std = self.broadcast(self.one_constant(node)?, self.width)?;
+        let Some(PreprocessStep::Standardize { std: unit, .. }) = self.steps.last_mut() else {
+          unreachable!("a Sub always adds a standardization");
+        };
+        *unit = std;
+      }
+      "Mul" | "Div" if preprocessing => {
+        let tensor = self.one_constant(node)?;
+        let values = matrix(tensor)?;
+        if values.len() != 1 {
+          return Err(unsupported(format!(
+            "{} ({}) must use a scalar, or a vector right after a Sub, before the first layer",
+            node.name, node.op_type
+          )));
+        }
+        let value = values[[0, 0]];
+        let factor = if node.op_type == "Mul" {
+          value
+        } else {
+          1.0 / value
+        };
+        // a Div by zero gives an infinite scale
+        if !factor.is_finite() {
+          return Err(ModelError::NonFinite {
+            name: tensor.name.clone(),
+            count: 1,
+          });
+        }
+        self.steps.push(PreprocessStep::Scale { factor });
+      }
+      "Relu" => self.set_activation(node, Activation::Relu)?,
+      "Sigmoid" => self.set_activation(node, Activation::Sigmoid)?,
+      "Tanh" => self.set_activation(node, Activation::Tanh)?,
+      "LeakyRelu" | "Elu" => {
+        let (activation, supported) = if node.op_type == "Elu" {
+          (Activation::Elu, ELU_ALPHA)
+        } else {
+          (Activation::LeakyRelu, LEAKY_RELU_SLOPE)
+        };
+        // the ONNX defaults are the same values: 0.01 for LeakyRelu, 1.0 for Elu
+        let alpha = float_attribute(node, "alpha", supported)?;
+        if (alpha - supported).abs() > 1e-6 {
+          return Err(unsupported(format!(
+            "{} ({}) has alpha {}, only {} is supported",
+            node.name, node.op_type, alpha, supported
+          )));
+        }
+        self.set_activation(node, activation)?;
+      }
+      "Softmax" => {
+        self.constants(node)?;
+        let axis = int_attribute(node, "axis", 1)?;
+        if axis != 1 && axis != -1 {
+          return Err(unsupported(format!(
+            "{} (Softmax) uses axis {}",
+            node.name, axis
+          )));
+        }
+        self.softmax = true;
+      }
+      // no-ops on a batch x features input at inference time
+      "Flatten" | "Identity" | "Dropout" => {
+        self.constants(node)?;
+        if node.op_type == "Flatten" && int_attribute(node, "axis", 1)? != 1 {
+          return Err(unsupported(format!(
+            "{} (Flatten) uses an axis other than 1",
+            node.name
+          )));
+        }
+      }
+      "Add" | "Sub" | "Mul" | "Div" => {
+        return Err(unsupported(format!(
+          "{} ({}) is only supported as preprocessing before the first layer, or as the bias \
+           after a MatMul",
+          node.name, node.op_type
+        )));
+      }
+      op => {
+        return Err(ModelError::UnsupportedOp {
+          op: op.to_string(),
+          node: node.name.clone(),
+        });
+      }
+    }
+    self.current = node.output.first().cloned().unwrap_or_default();
+    Ok(())
+  }
+}
+
+/// Number of values per sample of a graph input shaped `batch x ...`.
+fn input_width(shape: &[Dimension], name: &str) -> Result<usize, ModelError> {
+  let mut width = 1;
+  for dim in shape.iter().skip(1) {
+    match dim {
+      Dimension::Value(n) if *n > 0 => width *= *n as usize,
+      _ => {
+        return Err(unsupported(format!(
+          "input {} has shape {:?}; only the batch dimension may vary",
+          name, shape
+        )));
+      }
+    }
+  }
+  Ok(width)
+}
+
+/// Build a model from an ONNX graph that is a chain of nodes: optional preprocessing
+/// (Mul/Div by a scalar, Sub followed by Div or MatMul), then Gemm or MatMul(+Add)
+/// layers each optionally followed by an activation, and an optional final Softmax.
+/// The graph input must be the raw 0-255 pixels of a 28x28 image, as in graphs written
+/// by `export`.
+pub fn from_onnx(onnx: &ModelProto) -> Result<InferrableModel, ModelError> {
+  let graph = &onnx.graph;
+  let initializers: HashMap<&str, &TensorProto> = graph
+    .initializer
+    .iter()
+    .map(|tensor| (tensor.name.as_str(), tensor))
+    .collect();
+
+  // files before IR version 4 list initializers as inputs too
+  let inputs: Vec<_> = graph
+    .input
+    .iter()
+    .filter(|input| !initializers.contains_key(input.name.as_str()))
+    .collect();
+  let [input] = inputs[..] else {
+    return Err(unsupported(format!(
+      "graph has {} inputs, expected one",
+      inputs.len()
+    )));
+  };
+  let [output] = &graph.output[..] else {
+    return Err(unsupported(format!(
+      "graph has {} outputs, expected one",
+      graph.output.len()
+    )));
+  };
+
+  let input_size: usize = mnist_input_shape().iter().product();
+  let width = input_width(&input.shape, &input.name)?;
+  if width != input_size {
+    return Err(unsupported(format!(
+      "input {} has {} values per sample, expected {} (28x28 pixels)",
+      input.name, width, input_size
+    )));
+  }
+
+  let mut importer = Importer {
+    initializers,
+    current: input.name.clone(),
+    width,
+    steps: Vec::new(),
+    layers: Vec::new(),
+    after_sub: false,
+    after_matmul: false,
+    softmax: false,
+  };
+  for node in &graph.node {
+    importer.node(node)?;
+  }
+
+  if importer.current != output.name {
+    return Err(unsupported(format!(
+      "the chain of nodes ends in {}, not the graph output {}",
+      importer.current, output.name
+    )));
+  }
+  let Some(last) = importer.layers.last() else {
+    return Err(ModelError::NoLayers);
+  };
+  if last.activation != Activation::Identity {
+    return Err(unsupported(format!(
+      "the output layer is followed by {:?}; only a Softmax may follow it",
+      last.activation
+    )));
+  }
+
+  let class_labels = match onnx
+    .metadata_props
+    .iter()
+    .find(|(key, _)| key == CLASS_LABELS_KEY)
+  {
+    Some((key, labels)) => {
+      serde_json::from_str(labels).map_err(|e| ModelError::InvalidMetadata {
+        key: key.clone(),
+        message: e.to_string(),
+      })?
+    }
+    None if last.outputs() == 10 => digit_labels(),
+    None => (0..last.outputs()).map(|class| class.to_string()).collect(),
+  };
+  if class_labels.len() != last.outputs() {
+    return Err(ModelError::LabelMismatch {
+      outputs: last.outputs(),
+      labels: class_labels.len(),
+    });
+  }
+
+  let preprocessor = Preprocessor {
+    steps: importer.steps,
+  };
+  let (preprocessing, _) = preprocessor.to_tensors();
+  let layers = importer.layers.iter().enumerate().flat_map(|(i, layer)| {
+    [
+      (format!("w{}", i + 1), &layer.w),
+      (format!("b{}", i + 1), &layer.b),
+    ]
+  });
+  for (name, values) in preprocessing
+    .iter()
+    .map(|(name, values)| (name.clone(), values))
+    .chain(layers)
+  {
+    let count = values.iter().filter(|v| !v.is_finite()).count();
+    if count > 0 {
+      return Err(ModelError::NonFinite { name, count });
+    }
+  }
+
+  Ok(InferrableModel {
+    preprocessor,
+    conv_layers: Vec::new(),
+    layers: importer.layers,
+    class_labels,
+  })
+}
+
+/// Load a model from an ONNX file. See `from_onnx` for the graphs that are supported.
+pub fn load_onnx<P: AsRef<Path>>(path: P) -> Result<InferrableModel, ModelError> {
+  let bytes = std::fs::read(path)?;
+  let onnx = ModelProto::decode(&bytes).map_err(|e| ModelError::InvalidOnnx(e.to_string()))?;
+  from_onnx(&onnx)
+}
+
+/// Convert the ONNX model at `model_path` into a model file at `out_path`. Set
+/// `scale_pixels` for graphs that expect pixels scaled to [0, 1] rather than 0-255.
+pub fn run_import(model_path: &str, out_path: &str, scale_pixels: bool) {
+  let mut model = match load_onnx(model_path) {
+    Ok(model) => model,
+    Err(e) => {
+      eprintln!("Failed to import {}: {}", model_path, e);
+      std::process::exit(1);
+    }
+  };
+  if scale_pixels {
+    model.preprocessor.steps.insert(
+      0,
+      PreprocessStep::Scale {
+        factor: 1.0 / 255.0,
+      },
+    );
+  }
+  println!("Imported model layers: {:?}", model.layer_sizes());
+
+  match model
+    .to_serializable_model()
+    .save_to_safetensors(out_path, TensorDtype::F32)
+  {
+    Ok(()) => println!("Model saved to {}", out_path),
+    Err(e) => {
+      eprintln!("Failed to save model: {}", e);
+      std::process::exit(1);
+    }
+  }
+}
@@ -0,0 +1,12 @@
+pub mod export;
+pub mod import;
+pub mod proto;
+
+/// Name of the graph input: a batch of raw 0-255 pixels, `batch x pixels`.
+pub const INPUT_NAME: &str = "pixels";
+/// Name of the graph output: class probabilities, `batch x classes`.
+pub const OUTPUT_NAME: &str = "probabilities";
+/// `metadata_props` entry holding the class labels as a JSON list.
+pub const CLASS_LABELS_KEY: &str = "class_labels";
+/// `metadata_props` entry holding the shape of one input image as a JSON list.
+pub const INPUT_SHAPE_KEY: &str = "input_shape";
@@ -0,0 +1,378 @@
+use clap::ValueEnum;
+use ndarray::{Array2, Zip};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+const ADAM_BETA1: f32 = 0.9;
+const ADAM_BETA2: f32 = 0.999;
+const RMSPROP_DECAY: f32 = 0.9;
+const EPSILON: f32 = 1e-8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OptimizerKind {
+  Sgd,
+  Momentum,
+  Nesterov,
+  Rmsprop,
+  Adam,
+  Adamw,
+}
+
+/// Everything needed to build an optimizer. Checkpoints record it with the rest of the
+/// training config, so a resumed run can check it continues with the same optimizer.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OptimizerConfig {
+  pub kind: OptimizerKind,
+  pub lr: f32,
+  /// Momentum coefficient for momentum and Nesterov.
+  pub momentum: f32,
+  /// Decoupled weight decay for AdamW.
+  pub weight_decay: f32,
+}
+
+impl Default for OptimizerConfig {
+  fn default() -> Self {
+    OptimizerConfig {
+      kind: OptimizerKind::Sgd,
+      lr: 0.001,
+      momentum: 0.9,
+      weight_decay: 0.01,
+    }
+  }
+}
+
+impl OptimizerConfig {
+  pub fn validate(&self) -> Result<(), String> {
+    if !(self.lr.is_finite() && self.lr > 0.0) {
+      return Err("learning rate must be greater than 0".into());
+    }
+    if !(0.0..1.0).contains(&self.momentum) {
+      return Err("momentum must be at least 0 and less than 1".into());
+    }
+    if !(self.weight_decay.is_finite() && self.weight_decay >= 0.0) {
+      return Err("weight decay must not be negative".into());
+    }
+    Ok(())
+  }
+
+  pub fn build(&self) -> Box<dyn Optimizer> {
+    match self.kind {
+      OptimizerKind::Sgd => Box::new(Sgd { lr: self.lr }),
+      OptimizerKind::Momentum => Box::new(Momentum::new(self.lr, self.momentum, false)),
+      OptimizerKind::Nesterov => Box::new(Momentum::new(self.lr, self.momentum, true)),
+      OptimizerKind::Rmsprop => Box::new(RmsProp::new(self.lr)),
+      OptimizerKind::Adam => Box::new(Adam::new(self.lr, 0.0)),
+      OptimizerKind::Adamw => Box::new(Adam::new(self.lr, self.weight_decay)),
+    }
+  }
+}
+
+/// Per-parameter state of an optimizer, e.g. Adam's first and second moments.
+///
+/// Tensors are named `{slot}.{index}` where `index` is the position of the parameter in
+/// the list passed to `Optimizer::step`.
+#[derive(Default)]
+pub struct OptimizerState {
+  pub step: u64,
+  pub tensors: HashMap<String, Array2<f32>>,
+}
+
+impl OptimizerState {
+  fn insert_slot(&mut self, slot: &str, values: &[Array2<f32>]) {
+    for (i, v) in values.iter().enumerate() {
+      self.tensors.insert(format!("{}.{}", slot, i), v.clone());
+    }
+  }
+
+  /// Remove the tensors of `slot`, one per parameter of `shapes`. A slot with no tensors
+  /// at all comes from an optimizer that never stepped and is returned empty.
+  fn take_slot(
+    &mut self,
+    slot: &str,
+    shapes: &[(usize, usize)],
+  ) -> Result<Vec<Array2<f32>>, String> {
+    let name = |i: usize| format!("{}.{}", slot, i);
+    let prefix = format!("{}.", slot);
+    if !self.tensors.keys().any(|key| key.starts_with(&prefix)) {
+      return Ok(Vec::new());
+    }
+    if self.tensors.contains_key(&name(shapes.len())) {
+      return Err(format!(
+        "optimizer state has more {} tensors than the model has parameters ({})",
+        slot,
+        shapes.len()
+      ));
+    }
+    shapes
+      .iter()
+      .enumerate()
+      .map(|(i, &shape)| match self.tensors.remove(&name(i)) {
+        Some(tensor) if tensor.dim() == shape => Ok(tensor),
+        Some(tensor) => Err(format!(
+          "optimizer state tensor {} is {:?}, the parameter is {:?}",
+          name(i),
+          tensor.dim(),
+          shape
+        )),
+        None => Err(format!("optimizer state is missing tensor {}", name(i))),
+      })
+      .collect()
+  }
+}
+
+/// Updates parameters in place from their gradients.
+///
+/// `params` and `grads` must list the same parameters in the same order on every call;
+/// optimizers keep per-parameter state by position. `load_state` takes the shapes of
+/// those parameters and fails if the state doesn't match them.
+pub trait Optimizer {
+  fn step(&mut self, params: &mut [&mut Array2<f32>], grads: &[&Array2<f32>]);
+
+  fn learning_rate(&self) -> f32;
+
+  fn set_learning_rate(&mut self, lr: f32);
+
+  fn state(&self) -> OptimizerState;
+
+  fn load_state(&mut self, state: OptimizerState, shapes: &[(usize, usize)]) -> Result<(), String>;
+}
+
+/// Allocate zeroed state matching the parameter shapes on the first step.
+fn init_slot(slot: &mut Vec<Array2<f32>>, params: &[&mut Array2<f32>]) {
+  if slot.is_empty() {
+    *slot = params.iter().map(|p| Array2::zeros(p.dim())).collect();
+  }
+}
+
+/// Plain stochastic gradient descent: `p -= lr * g`.
+pub struct Sgd {
+  lr: f32,
+}
+
+impl Optimizer for Sgd {
+  fn step(&mut self, params: &mut [&mut Array2<f32>], grads: &[&Array2<f32>]) {
+    for (p, g) in params.iter_mut().zip(grads) {
+      p.scaled_add(-self.lr, g);
+    }
+  }
+
+  fn learning_rate(&self) -> f32 {
+    self.lr
+  }
+
+  fn set_learning_rate(&mut self, lr: f32) {
+    self.lr = lr;
+  }
+
+  fn state(&self) -> OptimizerState {
+    OptimizerState::default()
+  }
+
+  fn load_state(
+    &mut self,
+    _state: OptimizerState,
+    _shapes: &[(usize, usize)],
+  ) -> Result<(), String> {
+    Ok(())
+  }
+}
+
+/// SGD with (optionally Nesterov) momentum.
+pub struct Momentum {
+  lr: f32,
+  momentum: f32,
+  nesterov: bool,
+  velocity: Vec<Array2<f32>>,
+}
+
+impl Momentum {
+  pub fn new(lr: f32, momentum: f32, nesterov: bool) -> Self {
+    Momentum {
+      lr,
+      momentum,
+      nesterov,
+      velocity: Vec::new(),
+    }
+  }
+}
+
+impl Optimizer for Momentum {
+  fn step(&mut self, params: &mut [&mut Array2<f32>], grads: &[&Array2<f32>]) {
+    init_slot(&mut self.velocity, params);
+
+    for ((p, g), v) in params.iter_mut().zip(grads).zip(self.velocity.iter_mut()) {
+      // v = momentum * v + g
+      v.zip_mut_with(g, |v, &g| *v = self.momentum * *v + g);
+      if self.nesterov {
+        // look ahead along the velocity: p -= lr * (g + momentum * v)
+        Zip::from(&mut **p)
+          .and(*g)
+          .and(&*v)
+          .for_each(|p, &g, &v| *p -= self.lr * (g + self.momentum * v));
+      } else {
+        p.scaled_add(-self.lr, v);
+      }
+    }
+  }
+
+  fn learning_rate(&self) -> f32 {
+    self.lr
+  }
+
+  fn set_learning_rate(&mut self, lr: f32) {
+    self.lr = lr;
+  }
+
+  fn state(&self) -> OptimizerState {
+    let mut state = OptimizerState::default();
+    state.insert_slot("velocity", &self.velocity);
+    state
+  }
+
+  fn load_state(
+    &mut self,
+    mut state: OptimizerState,
+    shapes: &[(usize, usize)],
+  ) -> Result<(), String> {
+    self.velocity = state.take_slot("velocity", shapes)?;
+    Ok(())
+  }
+}
+
+/// RMSProp: scale each step by a running average of the squared gradients.
+pub struct RmsProp {
+  lr: f32,
+  mean_square: Vec<Array2<f32>>,
+}
+
+impl RmsProp {
+  pub fn new(lr: f32) -> Self {
+    RmsProp {
+      lr,
+      mean_square: Vec::new(),
+    }
+  }
+}
+
+impl Optimizer for RmsProp {
+  fn step(&mut self, params: &mut [&mut Array2<f32>], grads: &[&Array2<f32>]) {
+    init_slot(&mut self.mean_square, params);
+
+    for ((p, g), s) in params
+      .iter_mut()
+      .zip(grads)
+      .zip(self.mean_square.iter_mut())
+    {
+      Zip::from(&mut **p).and(s).and(*g).for_each(|p, s, &g| {
+        *s = RMSPROP_DECAY * *s + (1.0 - RMSPROP_DECAY) * g * g;
+        *p -= self.lr * g / (s.sqrt() + EPSILON);
+      });
+    }
+  }
+
+  fn learning_rate(&self) -> f32 {
+    self.lr
+  }
+
+  fn set_learning_rate(&mut self, lr: f32) {
+    self.lr = lr;
+  }
+
+  fn state(&self) -> OptimizerState {
+    let mut state = OptimizerState::default();
+    state.insert_slot("mean_square", &self.mean_square);
+    state
+  }
+
+  fn load_state(
+    &mut self,
+    mut state: OptimizerState,
+    shapes: &[(usize, usize)],
+  ) -> Result<(), String> {
+    self.mean_square = state.take_slot("mean_square", shapes)?;
+    Ok(())
+  }
+}
+
+/// Adam with bias-corrected moments. A non-zero `weight_decay` makes it AdamW, which
+/// shrinks the weights directly instead of adding the decay to the gradient.
+pub struct Adam {
+  lr: f32,
+  weight_decay: f32,
+  step: u64,
+  m: Vec<Array2<f32>>,
+  v: Vec<Array2<f32>>,
+}
+
+impl Adam {
+  pub fn new(lr: f32, weight_decay: f32) -> Self {
+    Adam {
+      lr,
+      weight_decay,
+      step: 0,
+      m: Vec::new(),
+      v: Vec::new(),
+    }
+  }
+}
+
+impl Optimizer for Adam {
+  fn step(&mut self, params: &mut [&mut Array2<f32>], grads: &[&Array2<f32>]) {
+    init_slot(&mut self.m, params);
+    init_slot(&mut self.v, params);
+    self.step += 1;
+
+    let bias_correction1 = 1.0 - ADAM_BETA1.powi(self.step as i32);
+    let bias_correction2 = 1.0 - ADAM_BETA2.powi(self.step as i32);
+
+    for (((p, g), m), v) in params
+      .iter_mut()
+      .zip(grads)
+      .zip(self.m.iter_mut())
+      .zip(self.v.iter_mut())
+    {
+      Zip::from(&mut **p)
+        .and(m)
+        .and(v)
+        .and(*g)
+        .for_each(|p, m, v, &g| {
+          *m = ADAM_BETA1 * *m + (1.0 - ADAM_BETA1) * g;
+          *v = ADAM_BETA2 * *v + (1.0 - ADAM_BETA2) * g * g;
+          let m_hat = *m / bias_correction1;
+          let v_hat = *v / bias_correction2;
+          *p -= self.lr * (m_hat / (v_hat.sqrt() + EPSILON) + self.weight_decay * *p);
+        });
+    }
+  }
+
+  fn learning_rate(&self) -> f32 {
+    self.lr
+  }
+
+  fn set_learning_rate(&mut self, lr: f32) {
+    self.lr = lr;
+  }
+
+  fn state(&self) -> OptimizerState {
+    let mut state = OptimizerState {
+      step: self.step,
+      ..Default::default()
+    };
+    state.insert_slot("m", &self.m);
+    state.insert_slot("v", &self.v);
+    state
+  }
+
+  fn load_state(
+    &mut self,
+    mut state: OptimizerState,
+    shapes: &[(usize, usize)],
+  ) -> Result<(), String> {
+    self.step = state.step;
+    self.m = state.take_slot("m", shapes)?;
+    self.v = state.take_slot("v", shapes)?;
+    Ok(())
+  }
+}
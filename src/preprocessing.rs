@@ -0,0 +1,294 @@
+use clap::ValueEnum;
+use ndarray::{Array1, Array2, Axis};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ModelError;
+use crate::serialization::NamedTensors;
+
+// per-pixel standard deviations are floored at one grey level, so pixels that never vary
+// in the training set (the borders) don't blow up on images where they do
+const MIN_PIXEL_STD: f32 = 1.0;
+// added to each principal component's variance before whitening
+const WHITEN_EPSILON: f32 = 1e-5;
+const PCA_ITERATIONS: usize = 100;
+const JACOBI_SWEEPS: usize = 50;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Normalization {
+  /// Feed raw 0-255 pixel values
+  Raw,
+  /// Scale pixels to [0, 1]
+  Scale,
+  /// Subtract each pixel's training set mean and divide by its standard deviation
+  Standardize,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PreprocessingConfig {
+  pub normalization: Normalization,
+  /// Project the normalized pixels onto this many principal components of the training
+  /// set, rescaled to unit variance
+  pub pca_components: Option<usize>,
+}
+
+impl Default for PreprocessingConfig {
+  fn default() -> Self {
+    PreprocessingConfig {
+      normalization: Normalization::Scale,
+      pca_components: None,
+    }
+  }
+}
+
+/// One stage of a `Preprocessor`. Vectors are `pixels x 1` columns.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum PreprocessStep {
+  Scale {
+    factor: f32,
+  },
+  Standardize {
+    mean: Array2<f32>,
+    std: Array2<f32>,
+  },
+  /// `projection . (x - mean)`, with one whitened principal component per row.
+  Whiten {
+    mean: Array2<f32>,
+    projection: Array2<f32>,
+  },
+}
+
+/// How a step is described in a model file. Its tensors are stored as
+/// `preprocess{n}.{name}`.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StepSpec {
+  Scale { factor: f32 },
+  Standardize,
+  Whiten,
+}
+
+/// Transforms raw 0-255 pixels into the inputs a model was trained on. Fitted on the
+/// training set and saved with the model, so every consumer preprocesses identically.
+/// An empty pipeline passes raw pixels through, as older models expect.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Preprocessor {
+  pub steps: Vec<PreprocessStep>,
+}
+
+impl Preprocessor {
+  /// Fit a pipeline to `images`, which holds one raw image per row.
+  pub fn fit(config: &PreprocessingConfig, images: &Array2<f32>) -> Self {
+    let mut steps = Vec::new();
+    match config.normalization {
+      Normalization::Raw => {}
+      Normalization::Scale => steps.push(PreprocessStep::Scale {
+        factor: 1.0 / 255.0,
+      }),
+      Normalization::Standardize => {
+        let mean = images.mean_axis(Axis(0)).unwrap();
+        let std = images.std_axis(Axis(0), 0.0).mapv(|s| s.max(MIN_PIXEL_STD));
+        steps.push(PreprocessStep::Standardize {
+          mean: mean.insert_axis(Axis(1)),
+          std: std.insert_axis(Axis(1)),
+        });
+      }
+    }
+
+    if let Some(components) = config.pca_components {
+      let normalized = Preprocessor {
+        steps: steps.clone(),
+      }
+      .apply(&images.t().to_owned());
+      let (mean, projection) = fit_whitening(&normalized, components);
+      steps.push(PreprocessStep::Whiten { mean, projection });
+    }
+
+    Preprocessor { steps }
+  }
+
+  /// Number of values each image is turned into.
+  pub fn output_size(&self, input_size: usize) -> usize {
+    match self.steps.last() {
+      Some(PreprocessStep::Whiten { projection, .. }) => projection.nrows(),
+      _ => input_size,
+    }
+  }
+
+  /// Preprocess `x`, which holds one image per column.
+  pub fn apply(&self, x: &Array2<f32>) -> Array2<f32> {
+    let mut x = x.clone();
+    for step in &self.steps {
+      x = match step {
+        PreprocessStep::Scale { factor } => x * *factor,
+        PreprocessStep::Standardize { mean, std } => (x - mean) / std,
+        PreprocessStep::Whiten { mean, projection } => projection.dot(&(x - mean)),
+      };
+    }
+    x
+  }
+
+  /// The tensors and step descriptions this pipeline is stored as.
+  pub fn to_tensors(&self) -> (NamedTensors, Vec<StepSpec>) {
+    let mut tensors = Vec::new();
+    let mut specs = Vec::new();
+    for (i, step) in self.steps.iter().enumerate() {
+      let name = |tensor: &str| format!("preprocess{}.{}", i + 1, tensor);
+      match step {
+        PreprocessStep::Scale { factor } => specs.push(StepSpec::Scale { factor: *factor }),
+        PreprocessStep::Standardize { mean, std } => {
+          tensors.push((name("mean"), mean.clone()));
+          tensors.push((name("std"), std.clone()));
+          specs.push(StepSpec::Standardize);
+        }
+        PreprocessStep::Whiten { mean, projection } => {
+          tensors.push((name("mean"), mean.clone()));
+          tensors.push((name("projection"), projection.clone()));
+          specs.push(StepSpec::Whiten);
+        }
+      }
+    }
+    (tensors, specs)
+  }
+
+  /// Rebuild a pipeline for inputs of `input_size` values from its step descriptions,
+  /// reading each step's tensors with `read_tensor`.
+  pub fn from_tensors(
+    specs: Vec<StepSpec>,
+    input_size: usize,
+    read_tensor: impl Fn(&str) -> Result<Array2<f32>, ModelError>,
+  ) -> Result<Self, ModelError> {
+    let mut steps = Vec::with_capacity(specs.len());
+    let mut width = input_size;
+    for (i, spec) in specs.into_iter().enumerate() {
+      // `rows` is None where any number of rows is fine, as for the projection which has
+      // one per component it keeps
+      let tensor = |tensor: &str, rows: Option<usize>, cols: usize| {
+        let name = format!("preprocess{}.{}", i + 1, tensor);
+        let arr = read_tensor(&name)?;
+        let expected = (rows.unwrap_or(arr.nrows()), cols);
+        if arr.dim() != expected {
+          return Err(ModelError::ShapeMismatch {
+            name,
+            expected: vec![expected.0, expected.1],
+            actual: arr.shape().to_vec(),
+          });
+        }
+        Ok(arr)
+      };
+      steps.push(match spec {
+        StepSpec::Scale { factor } => PreprocessStep::Scale { factor },
+        StepSpec::Standardize => PreprocessStep::Standardize {
+          mean: tensor("mean", Some(width), 1)?,
+          std: tensor("std", Some(width), 1)?,
+        },
+        StepSpec::Whiten => {
+          let mean = tensor("mean", Some(width), 1)?;
+          let projection = tensor("projection", None, width)?;
+          width = projection.nrows();
+          PreprocessStep::Whiten { mean, projection }
+        }
+      });
+    }
+    Ok(Preprocessor { steps })
+  }
+}
+
+/// PCA whitening of `x` (one sample per column): returns the mean and a
+/// `components x features` matrix projecting onto the top principal components, each
+/// scaled to unit variance.
+fn fit_whitening(x: &Array2<f32>, components: usize) -> (Array2<f32>, Array2<f32>) {
+  let samples = x.ncols();
+  let mean = x.mean_axis(Axis(1)).unwrap().insert_axis(Axis(1));
+  let centered = x - &mean;
+  let covariance = centered.dot(&centered.t()) / (samples.max(2) - 1) as f32;
+
+  // orthogonal iteration converges to the span of the top eigenvectors...
+  let mut rng = ChaCha8Rng::seed_from_u64(0);
+  let mut basis = Array2::from_shape_simple_fn((covariance.nrows(), components), || {
+    rng.random_range(-1.0..1.0)
+  });
+  orthonormalize_columns(&mut basis);
+  for _ in 0..PCA_ITERATIONS {
+    basis = covariance.dot(&basis);
+    orthonormalize_columns(&mut basis);
+  }
+
+  // ...and diagonalizing the covariance within that span gives the eigenvectors themselves
+  let reduced = basis.t().dot(&covariance).dot(&basis);
+  let (eigenvalues, rotation) = symmetric_eigen(reduced);
+  let eigenvectors = basis.dot(&rotation);
+
+  let mut order: Vec<usize> = (0..components).collect();
+  order.sort_by(|&a, &b| eigenvalues[b].total_cmp(&eigenvalues[a]));
+
+  let mut projection = Array2::zeros((components, covariance.nrows()));
+  for (row, &component) in order.iter().enumerate() {
+    let scale = 1.0 / (eigenvalues[component].max(0.0) + WHITEN_EPSILON).sqrt();
+    projection
+      .row_mut(row)
+      .assign(&(&eigenvectors.column(component) * scale));
+  }
+  (mean, projection)
+}
+
+/// Modified Gram-Schmidt.
+fn orthonormalize_columns(a: &mut Array2<f32>) {
+  for j in 0..a.ncols() {
+    for k in 0..j {
+      let dot = a.column(j).dot(&a.column(k));
+      let previous = a.column(k).to_owned();
+      a.column_mut(j).scaled_add(-dot, &previous);
+    }
+    let norm = a.column(j).dot(&a.column(j)).sqrt().max(f32::EPSILON);
+    a.column_mut(j).mapv_inplace(|v| v / norm);
+  }
+}
+
+/// Eigenvalues and eigenvectors (as columns) of a small symmetric matrix, by cyclic
+/// Jacobi rotations.
+fn symmetric_eigen(mut a: Array2<f32>) -> (Array1<f32>, Array2<f32>) {
+  let n = a.nrows();
+  let mut v = Array2::eye(n);
+  for _ in 0..JACOBI_SWEEPS {
+    let off_diagonal: f32 = (0..n)
+      .flat_map(|p| (p + 1..n).map(move |q| (p, q)))
+      .map(|(p, q)| a[[p, q]] * a[[p, q]])
+      .sum();
+    if off_diagonal < 1e-12 {
+      break;
+    }
+
+    for p in 0..n {
+      for q in p + 1..n {
+        if a[[p, q]].abs() < f32::MIN_POSITIVE {
+          continue;
+        }
+        // rotate rows/columns p and q so that a[p][q] becomes zero
+        let theta = (a[[q, q]] - a[[p, p]]) / (2.0 * a[[p, q]]);
+        let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+        let c = 1.0 / (t * t + 1.0).sqrt();
+        let s = t * c;
+        for k in 0..n {
+          let (akp, akq) = (a[[k, p]], a[[k, q]]);
+          a[[k, p]] = c * akp - s * akq;
+          a[[k, q]] = s * akp + c * akq;
+        }
+        for k in 0..n {
+          let (apk, aqk) = (a[[p, k]], a[[q, k]]);
+          a[[p, k]] = c * apk - s * aqk;
+          a[[q, k]] = s * apk + c * aqk;
+        }
+        for k in 0..n {
+          let (vkp, vkq) = (v[[k, p]], v[[k, q]]);
+          v[[k, p]] = c * vkp - s * vkq;
+          v[[k, q]] = s * vkp + c * vkq;
+        }
+      }
+    }
+  }
+  (a.diag().to_owned(), v)
+}
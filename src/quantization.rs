@@ -0,0 +1,211 @@
+use clap::ValueEnum;
+use ndarray::{Array2, Axis};
+use rand::SeedableRng;
+use rand::seq::index::sample;
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::inferrable_model::{InferrableModel, Mode};
+use crate::math::argmax;
+use crate::serializable_model::SerializableModel;
+use crate::serialization::TensorDtype;
+use crate::training::{TRAINING_SIZE, TrainingData};
+
+// symmetric quantization uses -127..=127, leaving -128 unused so zero sits in the middle
+const INT8_MAX: f32 = 127.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Granularity {
+  /// One scale for all of a layer's weights
+  PerTensor,
+  /// One scale per output neuron (row of the weight matrix)
+  PerChannel,
+}
+
+/// Symmetric int8 weights of a dense layer, `w ≈ weights * scales`, along with the
+/// scale its inputs are quantized with, `x ≈ x_q * input_scale`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QuantizedWeights {
+  pub weights: Array2<i8>,
+  /// `outputs x 1` for per-channel scales, `1 x 1` for a per-tensor scale.
+  pub scales: Array2<f32>,
+  pub input_scale: f32,
+}
+
+impl QuantizedWeights {
+  /// Quantize `w` for inputs whose absolute values reach up to `input_range`.
+  pub fn new(w: &Array2<f32>, granularity: Granularity, input_range: f32) -> Self {
+    let scales = match granularity {
+      Granularity::PerTensor => Array2::from_elem((1, 1), scale_for(max_abs(w.iter()))),
+      Granularity::PerChannel => w
+        .map_axis(Axis(1), |row| scale_for(max_abs(row.iter())))
+        .insert_axis(Axis(1)),
+    };
+    QuantizedWeights {
+      weights: quantize(&(w / &scales)),
+      scales,
+      input_scale: scale_for(input_range),
+    }
+  }
+
+  /// The weights as f32 values.
+  pub fn dequantize(&self) -> Array2<f32> {
+    self.weights.mapv(|q| q as f32) * &self.scales
+  }
+
+  /// Pre-activation values `w . x + b` computed with integer arithmetic: int8 weights
+  /// times int8 inputs `x_q`, plus the bias rounded to the same scale, accumulated in
+  /// i32 and scaled back to f32 once at the end.
+  pub fn linear(&self, x_q: &Array2<i8>, b: &Array2<f32>) -> Array2<f32> {
+    let accumulator_scale = &self.scales * self.input_scale;
+    let bias = (b / &accumulator_scale).mapv(|v| v.round() as i32);
+    let accumulator = self.weights.mapv(i32::from).dot(&x_q.mapv(i32::from)) + bias;
+    accumulator.mapv(|v| v as f32) * &accumulator_scale
+  }
+
+  /// Quantize layer inputs `x` (one sample per column) with `input_scale`.
+  pub fn quantize_input(&self, x: &Array2<f32>) -> Array2<i8> {
+    quantize(&(x / self.input_scale))
+  }
+}
+
+fn max_abs<'a>(values: impl Iterator<Item = &'a f32>) -> f32 {
+  values.fold(0.0, |m, v| m.max(v.abs()))
+}
+
+/// The scale mapping values up to `range` onto -127..=127.
+fn scale_for(range: f32) -> f32 {
+  if range > 0.0 { range / INT8_MAX } else { 1.0 }
+}
+
+fn quantize(x: &Array2<f32>) -> Array2<i8> {
+  x.mapv(|v| v.round().clamp(-INT8_MAX, INT8_MAX) as i8)
+}
+
+/// Largest absolute value seen at the input of each layer when running the float model
+/// on `inputs` (preprocessed, one sample per column).
+pub fn calibrate(model: &InferrableModel, inputs: &Array2<f32>) -> Vec<f32> {
+  let pass = model.forward_pass(inputs, Mode::Eval);
+  (0..model.layers.len())
+    .map(|layer| max_abs(pass.input(layer).iter()))
+    .collect()
+}
+
+/// Quantize every layer of a float model, calibrating activation ranges on `images`
+/// (raw pixels, one image per row).
+pub fn quantize_model(
+  model: &InferrableModel,
+  images: &Array2<f32>,
+  granularity: Granularity,
+) -> InferrableModel {
+  let inputs = model.preprocessor.apply(&images.t().to_owned());
+  let ranges = calibrate(model, &inputs);
+
+  let mut quantized = model.clone();
+  for (layer, range) in quantized.layers.iter_mut().zip(ranges) {
+    let weights = QuantizedWeights::new(&layer.w, granularity, range);
+    layer.w = weights.dequantize();
+    layer.quantized = Some(weights);
+  }
+  quantized
+}
+
+/// Fraction of `images` (one per row) both models assign the same class to.
+pub fn agreement(
+  float: &InferrableModel,
+  quantized: &InferrableModel,
+  images: &Array2<f32>,
+) -> f32 {
+  let inputs = float.preprocessor.apply(&images.t().to_owned());
+  let classes = |probabilities: Array2<f32>| -> Vec<usize> {
+    probabilities.axis_iter(Axis(1)).map(argmax).collect()
+  };
+  let expected = classes(float.forward(&inputs, Mode::Eval));
+  let actual = classes(quantized.forward(&inputs, Mode::Eval));
+  let same = expected.iter().zip(&actual).filter(|(a, b)| a == b).count();
+  same as f32 / expected.len() as f32
+}
+
+/// Quantize the float model at `model_path` to int8 and save it to `out_path`, calibrating
+/// on `calibration_size` training images drawn with `seed`.
+pub fn run_quantize(
+  model_path: &str,
+  out_path: &str,
+  granularity: Granularity,
+  calibration_size: usize,
+  seed: u64,
+) {
+  let serializable = match SerializableModel::load_from_safetensors(model_path) {
+    Ok(model) => model,
+    Err(e) => {
+      eprintln!("Failed to load model from {}: {}", model_path, e);
+      std::process::exit(1);
+    }
+  };
+  if serializable
+    .layers
+    .iter()
+    .any(|layer| layer.quantized.is_some())
+  {
+    eprintln!("{} is already quantized", model_path);
+    std::process::exit(1);
+  }
+  let model = match InferrableModel::from_serializable_model(&serializable) {
+    Ok(model) => model,
+    Err(e) => {
+      eprintln!("Failed to load model from {}: {}", model_path, e);
+      std::process::exit(1);
+    }
+  };
+
+  let data = TrainingData::load_mnist(TRAINING_SIZE);
+  let mut rng = ChaCha8Rng::seed_from_u64(seed);
+  let indices = sample(&mut rng, data.len(), calibration_size.clamp(1, data.len())).into_vec();
+  let calibration = data.images.select(Axis(0), &indices);
+  println!(
+    "Calibrating {:?} int8 quantization on {} training images",
+    granularity,
+    calibration.nrows()
+  );
+
+  let quantized = quantize_model(&model, &calibration, granularity);
+  for (i, layer) in quantized.layers.iter().enumerate() {
+    let weights = layer.quantized.as_ref().unwrap();
+    let (min_scale, max_scale) = weights
+      .scales
+      .fold((f32::INFINITY, 0.0f32), |(lo, hi), &s| {
+        (lo.min(s), hi.max(s))
+      });
+    println!(
+      "Layer {}: {} -> {}, input range ±{:.4}, weight scale {:.6}..{:.6}",
+      i + 1,
+      layer.inputs(),
+      layer.outputs(),
+      weights.input_scale * INT8_MAX,
+      min_scale,
+      max_scale
+    );
+  }
+  println!(
+    "int8 and float predictions agree on {:.2}% of the calibration images",
+    agreement(&model, &quantized, &calibration) * 100.0
+  );
+
+  let mut out = quantized.to_serializable_model();
+  out.input_shape = serializable.input_shape;
+  out.training = serializable.training;
+  match out.save_to_safetensors(out_path, TensorDtype::F32) {
+    Ok(()) => {
+      println!("Quantized model saved to {}", out_path);
+      println!(
+        "Compare test accuracy with: neural-net validate --model {} --baseline {}",
+        out_path, model_path
+      );
+    }
+    Err(e) => {
+      eprintln!("Failed to save quantized model: {}", e);
+      std::process::exit(1);
+    }
+  }
+}
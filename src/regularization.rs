@@ -0,0 +1,99 @@
+use ndarray::Axis;
+use serde::{Deserialize, Serialize};
+
+use crate::inferrable_model::{Gradients, InferrableModel};
+
+/// Penalties on the size of the weights, applied the same way whatever the optimizer:
+/// the L1 and L2 terms add their gradients to the weight gradients before the optimizer
+/// step, and the max-norm constraint rescales weights after it. Convolution kernels count
+/// as weights; biases are never regularized.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RegularizationConfig {
+  /// Coefficient of the L1 penalty `l1 * sum |w|`
+  pub l1: f32,
+  /// Coefficient of the L2 penalty `l2 / 2 * sum w^2`, i.e. weight decay of `l2 * w`.
+  /// AdamW's decoupled weight decay still applies on top of it.
+  pub l2: f32,
+  /// Largest L2 norm allowed for each neuron's incoming weights; unconstrained if unset
+  pub max_norm: Option<f32>,
+}
+
+impl Default for RegularizationConfig {
+  fn default() -> Self {
+    RegularizationConfig {
+      l1: 0.0,
+      l2: 0.0,
+      max_norm: None,
+    }
+  }
+}
+
+impl RegularizationConfig {
+  pub fn validate(&self) -> Result<(), String> {
+    let valid = |coefficient: f32| coefficient.is_finite() && coefficient >= 0.0;
+    if !valid(self.l1) || !valid(self.l2) {
+      return Err("L1 and L2 coefficients must not be negative".into());
+    }
+    if self
+      .max_norm
+      .is_some_and(|max_norm| !(max_norm.is_finite() && max_norm > 0.0))
+    {
+      return Err("max norm must be greater than 0".into());
+    }
+    Ok(())
+  }
+
+  /// Whether there is any penalty to add to the loss.
+  pub fn has_penalty(&self) -> bool {
+    self.l1 > 0.0 || self.l2 > 0.0
+  }
+
+  /// The penalty term for the model's current weights, added to the data loss.
+  pub fn penalty(&self, model: &InferrableModel) -> f32 {
+    if !self.has_penalty() {
+      return 0.0;
+    }
+    model
+      .weights()
+      .flat_map(|w| w.iter())
+      .map(|&w| self.l1 * w.abs() + 0.5 * self.l2 * w * w)
+      .sum()
+  }
+
+  /// Add the gradient of the penalty to each layer's weight gradients.
+  pub fn add_gradients(&self, model: &InferrableModel, gradients: &mut Gradients) {
+    if !self.has_penalty() {
+      return;
+    }
+    for (weights, dw) in model.weights().zip(gradients.weights_mut()) {
+      // 0 is the subgradient of |w| at w = 0, so weights that are exactly zero stay put
+      dw.zip_mut_with(weights, |dw, &w| {
+        let sign = if w > 0.0 {
+          1.0
+        } else if w < 0.0 {
+          -1.0
+        } else {
+          0.0
+        };
+        *dw += self.l1 * sign + self.l2 * w;
+      });
+    }
+  }
+
+  /// Scale down the incoming weights (a row of `w`, or a convolution's kernel) of every
+  /// neuron whose L2 norm is above `max_norm`, so the norm is exactly `max_norm`.
+  pub fn apply_max_norm(&self, model: &mut InferrableModel) {
+    let Some(max_norm) = self.max_norm else {
+      return;
+    };
+    for w in model.weights_mut() {
+      for mut row in w.axis_iter_mut(Axis(0)) {
+        let norm = row.dot(&row).sqrt();
+        if norm > max_norm {
+          row *= max_norm / norm;
+        }
+      }
+    }
+  }
+}
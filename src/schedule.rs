@@ -0,0 +1,208 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::f32::consts::PI;
+
+// one-cycle starts at max_lr / ONE_CYCLE_DIV and finishes at start / ONE_CYCLE_FINAL_DIV
+const ONE_CYCLE_DIV: f32 = 25.0;
+const ONE_CYCLE_FINAL_DIV: f32 = 1e4;
+const ONE_CYCLE_WARMUP_FRACTION: f32 = 0.3;
+// relative improvement needed for reduce-on-plateau to count an epoch as better
+const PLATEAU_THRESHOLD: f32 = 1e-4;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScheduleKind {
+  /// Keep the learning rate fixed
+  Constant,
+  /// Multiply by `--lr-gamma` every `--lr-step-size` epochs
+  Step,
+  /// Multiply by `--lr-gamma` every epoch
+  Exponential,
+  /// Cosine annealing to `--lr-min` with warm restarts
+  Cosine,
+  /// Ramp up to the learning rate for the first 30% of training, then anneal far below it
+  OneCycle,
+  /// Multiply by `--lr-gamma` when the loss stops improving for `--lr-patience` epochs
+  Plateau,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScheduleConfig {
+  pub kind: ScheduleKind,
+  /// Linearly ramp the learning rate up from zero over this many steps
+  pub warmup_steps: usize,
+  /// Epochs between decays (step schedule)
+  pub step_size: usize,
+  /// Decay factor (step, exponential and plateau schedules)
+  pub gamma: f32,
+  /// Length in epochs of the first cosine cycle
+  pub cycle_epochs: f32,
+  /// Each cosine cycle is this many times longer than the previous one
+  pub cycle_mult: f32,
+  /// Lower bound for the cosine and plateau schedules
+  pub min_lr: f32,
+  /// Epochs without improvement before the plateau schedule decays
+  pub patience: usize,
+}
+
+impl Default for ScheduleConfig {
+  fn default() -> Self {
+    ScheduleConfig {
+      kind: ScheduleKind::Constant,
+      warmup_steps: 0,
+      step_size: 5,
+      gamma: 0.5,
+      cycle_epochs: 5.0,
+      cycle_mult: 1.0,
+      min_lr: 0.0,
+      patience: 2,
+    }
+  }
+}
+
+impl ScheduleConfig {
+  pub fn validate(&self) -> Result<(), String> {
+    if !(self.gamma.is_finite() && self.gamma > 0.0) {
+      return Err("learning rate gamma must be greater than 0".into());
+    }
+    if !(self.min_lr.is_finite() && self.min_lr >= 0.0) {
+      return Err("minimum learning rate must not be negative".into());
+    }
+    if self.step_size == 0 {
+      return Err("learning rate step size must be at least 1".into());
+    }
+    if !(self.cycle_epochs.is_finite() && self.cycle_epochs > 0.0) {
+      return Err("cosine cycle length must be greater than 0".into());
+    }
+    if !(self.cycle_mult.is_finite() && self.cycle_mult >= 1.0) {
+      return Err("cosine cycle multiplier must be at least 1".into());
+    }
+    Ok(())
+  }
+}
+
+/// Computes the learning rate for every optimizer step from a `ScheduleConfig`.
+///
+/// Serializable so checkpoints can restore the reduce-on-plateau progress.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LrScheduler {
+  config: ScheduleConfig,
+  base_lr: f32,
+  steps_per_epoch: usize,
+  total_epochs: usize,
+  // reduce-on-plateau state
+  best_loss: Option<f32>,
+  bad_epochs: usize,
+  plateau_scale: f32,
+}
+
+impl LrScheduler {
+  pub fn new(
+    config: &ScheduleConfig,
+    base_lr: f32,
+    steps_per_epoch: usize,
+    total_epochs: usize,
+  ) -> Self {
+    LrScheduler {
+      config: config.clone(),
+      base_lr,
+      steps_per_epoch,
+      total_epochs,
+      best_loss: None,
+      bad_epochs: 0,
+      plateau_scale: 1.0,
+    }
+  }
+
+  /// Switch to the schedule, learning rate and run length of a resumed run, keeping the
+  /// reduce-on-plateau progress. The steps per epoch can't change on resume.
+  pub fn reconfigure(&mut self, config: &ScheduleConfig, base_lr: f32, total_epochs: usize) {
+    self.config = config.clone();
+    self.base_lr = base_lr;
+    self.total_epochs = total_epochs;
+  }
+
+  /// Learning rate for the (0-based) global optimizer step `step`.
+  pub fn lr(&self, step: usize) -> f32 {
+    let epoch = step as f32 / self.steps_per_epoch as f32;
+
+    let lr = match self.config.kind {
+      ScheduleKind::Constant => self.base_lr,
+      ScheduleKind::Step => {
+        let decays = (epoch as usize / self.config.step_size) as i32;
+        self.base_lr * self.config.gamma.powi(decays)
+      }
+      ScheduleKind::Exponential => self.base_lr * self.config.gamma.powi(epoch as i32),
+      ScheduleKind::Cosine => {
+        // find our position within the current restart cycle: cycle i lasts
+        // `first * mult^i` epochs, so cycles 0..n take `first * (mult^n - 1) / (mult - 1)`
+        let (first, mult) = (self.config.cycle_epochs, self.config.cycle_mult);
+        let (position, cycle) = if mult == 1.0 {
+          (epoch % first, first)
+        } else {
+          let start = |n: f32| first * (mult.powf(n) - 1.0) / (mult - 1.0);
+          let mut n = ((1.0 + epoch * (mult - 1.0) / first).ln() / mult.ln()).floor();
+          // the logarithm can round across a restart
+          if epoch >= start(n + 1.0) {
+            n += 1.0;
+          } else if epoch < start(n) {
+            n -= 1.0;
+          }
+          (epoch - start(n), first * mult.powf(n))
+        };
+        let min_lr = self.config.min_lr;
+        min_lr + 0.5 * (self.base_lr - min_lr) * (1.0 + (PI * position / cycle).cos())
+      }
+      ScheduleKind::OneCycle => {
+        let total_steps = (self.steps_per_epoch * self.total_epochs).max(1) as f32;
+        let up_steps = (total_steps * ONE_CYCLE_WARMUP_FRACTION).max(1.0);
+        let initial_lr = self.base_lr / ONE_CYCLE_DIV;
+        let final_lr = initial_lr / ONE_CYCLE_FINAL_DIV;
+        let step = step as f32;
+        if step < up_steps {
+          cosine_interpolate(initial_lr, self.base_lr, step / up_steps)
+        } else {
+          let progress = (step - up_steps) / (total_steps - up_steps).max(1.0);
+          cosine_interpolate(self.base_lr, final_lr, progress.min(1.0))
+        }
+      }
+      ScheduleKind::Plateau => (self.base_lr * self.plateau_scale).max(self.config.min_lr),
+    };
+
+    if step < self.config.warmup_steps {
+      lr * (step + 1) as f32 / self.config.warmup_steps as f32
+    } else {
+      lr
+    }
+  }
+
+  /// Report the monitored loss at the end of an epoch. Only reduce-on-plateau uses it.
+  pub fn end_epoch(&mut self, loss: f32) {
+    if self.config.kind != ScheduleKind::Plateau {
+      return;
+    }
+
+    let best_loss = self.best_loss.unwrap_or(f32::INFINITY);
+    if loss < best_loss * (1.0 - PLATEAU_THRESHOLD) {
+      self.best_loss = Some(loss);
+      self.bad_epochs = 0;
+    } else {
+      self.bad_epochs += 1;
+      if self.bad_epochs > self.config.patience {
+        self.plateau_scale *= self.config.gamma;
+        self.bad_epochs = 0;
+        println!(
+          "Loss plateaued at {:.4}, reducing learning rate to {:.2e}",
+          best_loss,
+          (self.base_lr * self.plateau_scale).max(self.config.min_lr)
+        );
+      }
+    }
+  }
+}
+
+/// Cosine-shaped interpolation from `start` (t = 0) to `end` (t = 1).
+fn cosine_interpolate(start: f32, end: f32, t: f32) -> f32 {
+  end + 0.5 * (start - end) * (1.0 + (PI * t).cos())
+}
@@ -1,26 +1,277 @@
 use serde::{Deserialize, Serialize};
 
+use crate::activation::Activation;
+use crate::config::TrainingConfig;
+use crate::conv::{Conv2d, ConvLayer, Pool2d, PoolKind, shape_of};
+use crate::norm::{NormKind, NormLayer, RunningStats};
+use crate::preprocessing::{Preprocessor, StepSpec};
+use crate::quantization::QuantizedWeights;
+
+#[derive(Serialize, Deserialize)]
+pub struct SerializableLayer {
+  pub w: Vec<f32>,
+  pub w_shape: (usize, usize),
+  pub b: Vec<f32>,
+  pub b_shape: (usize, usize),
+  pub activation: Activation,
+  /// Int8 weights of a quantized layer; `w` holds their dequantized values.
+  pub quantized: Option<QuantizedWeights>,
+  pub norm: Option<NormLayer>,
+}
+
+impl SerializableLayer {
+  pub fn new(w: &Array2<f32>, b: &Array2<f32>, activation: Activation) -> Self {
+    SerializableLayer {
+      w: w.iter().cloned().collect(),
+      w_shape: w.dim(),
+      b: b.iter().cloned().collect(),
+      b_shape: b.dim(),
+      activation,
+      quantized: None,
+      norm: None,
+    }
+  }
+}
+
+/// Where a model came from: the config it was trained with and the commit of the build
+/// that trained it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrainingInfo {
+  /// The `TrainingConfig`, kept as plain JSON so models trained by other versions still load.
+  pub config: serde_json::Value,
+  pub git_hash: Option<String>,
+}
+
+impl TrainingInfo {
+  pub fn new(config: &TrainingConfig) -> Result<Self, serde_json::Error> {
+    Ok(TrainingInfo {
+      config: serde_json::to_value(config)?,
+      git_hash: option_env!("GIT_HASH").map(String::from),
+    })
+  }
+}
+
+/// Flat representation of a model.
+///
+/// Files describe themselves with a versioned JSON `Architecture` stored under the
+/// `architecture` key of the safetensors `__metadata__`, naming the tensors each layer
+/// and preprocessing step reads. Files without it (format version 0) are the original
+/// layout: layer `n` (1-based) stored as `w{n}` and `b{n}`, sigmoid hidden layers unless
+/// an `activations` list is present, raw pixel input unless `preprocessing` is present.
 #[derive(Serialize, Deserialize)]
 pub struct SerializableModel {
-  pub w1: Vec<f32>,
-  pub w1_shape: (usize, usize),
-  pub b1: Vec<f32>,
-  pub b1_shape: (usize, usize),
-  pub w2: Vec<f32>,
-  pub w2_shape: (usize, usize),
-  pub b2: Vec<f32>,
-  pub b2_shape: (usize, usize),
+  /// Applied to the preprocessed image before `layers`, which read their flattened output.
+  pub conv_layers: Vec<ConvLayer>,
+  pub layers: Vec<SerializableLayer>,
+  pub preprocessor: Preprocessor,
+  /// Shape of one input image: channels, height, width.
+  pub input_shape: Vec<usize>,
+  /// Name of each output class, in output order.
+  pub class_labels: Vec<String>,
+  pub training: Option<TrainingInfo>,
 }
 
+use crate::error::ModelError;
+use crate::serialization::{
+  NamedTensors, TensorDtype, read_i8_tensor, read_tensor, save_safetensors_with_i8,
+};
+use ndarray::Array2;
 use safetensors::SafeTensors;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
 
+/// Newest version of the `Architecture` description this build reads and writes. Files
+/// with any version outside `MIN_FORMAT_VERSION..=FORMAT_VERSION` are rejected rather
+/// than guessed at.
+pub const FORMAT_VERSION: u32 = QUANTIZED_FORMAT_VERSION;
+/// Oldest version this build reads. Models are written with the oldest version that
+/// describes them, so builds that predate a layer kind still load models without it.
+pub const MIN_FORMAT_VERSION: u32 = 1;
+/// Version that added normalization layers.
+const NORM_FORMAT_VERSION: u32 = 2;
+/// Version that added convolutional layers.
+const CONV_FORMAT_VERSION: u32 = 3;
+/// Version that added int8 quantized layers.
+const QUANTIZED_FORMAT_VERSION: u32 = 4;
+
+const ARCHITECTURE_KEY: &str = "architecture";
+
+/// The `architecture` metadata entry of a model file.
+#[derive(Serialize, Deserialize)]
+struct Architecture {
+  format_version: u32,
+  input_shape: Vec<usize>,
+  class_labels: Vec<String>,
+  /// Applied to the input before the first layer; tensors are named `preprocess{n}.*`.
+  preprocessing: Vec<StepSpec>,
+  /// Any convolutional and pooling layers, then a flatten, then the dense layers.
+  layers: Vec<LayerSpec>,
+  training: Option<TrainingInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum LayerSpec {
+  /// `activation(conv2d(x, weight) + bias)` over square kernels. `weight` is
+  /// `out_channels x (in_channels * kernel_size * kernel_size)`, each row a kernel laid
+  /// out channel by channel, then row by row.
+  Conv2d {
+    weight: String,
+    bias: String,
+    in_channels: usize,
+    out_channels: usize,
+    kernel_size: usize,
+    stride: usize,
+    padding: usize,
+    activation: Activation,
+  },
+  MaxPool2d {
+    size: usize,
+    stride: usize,
+  },
+  AvgPool2d {
+    size: usize,
+    stride: usize,
+  },
+  /// Each sample's `channels x height x width` feature maps as one vector, in that order.
+  Flatten,
+  /// `activation(weight . x + bias)`
+  Dense {
+    weight: String,
+    bias: String,
+    inputs: usize,
+    outputs: usize,
+    activation: Activation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    norm: Option<NormSpec>,
+  },
+  /// `activation(weight * weight_scale . x + bias)` with int8 `weight`. The input is
+  /// quantized to int8 with `input_scale`. `weight_scale` is `outputs x 1` (per channel)
+  /// or `1 x 1` (per tensor).
+  QuantizedDense {
+    weight: String,
+    weight_scale: String,
+    bias: String,
+    input_scale: f32,
+    inputs: usize,
+    outputs: usize,
+    activation: Activation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    norm: Option<NormSpec>,
+  },
+}
+
+/// Normalization of a layer's `weight . x + bias`, applied before its activation. Every
+/// tensor is `outputs x 1`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum NormSpec {
+  BatchNorm {
+    gamma: String,
+    beta: String,
+    running_mean: String,
+    running_var: String,
+  },
+  LayerNorm {
+    gamma: String,
+    beta: String,
+  },
+}
+
+impl NormSpec {
+  /// Describe `norm`, adding its tensors named `norm{n}.*` to `arrays`.
+  fn new(n: usize, norm: &NormLayer, arrays: &mut NamedTensors) -> Self {
+    let mut tensor = |name: &str, arr: &Array2<f32>| {
+      let name = format!("norm{}.{}", n, name);
+      arrays.push((name.clone(), arr.clone()));
+      name
+    };
+    let (gamma, beta) = (tensor("gamma", &norm.gamma), tensor("beta", &norm.beta));
+    match &norm.running {
+      Some(running) => NormSpec::BatchNorm {
+        gamma,
+        beta,
+        running_mean: tensor("running_mean", &running.mean),
+        running_var: tensor("running_var", &running.var),
+      },
+      None => NormSpec::LayerNorm { gamma, beta },
+    }
+  }
+
+  fn load<F>(&self, features: usize, tensor: F) -> Result<NormLayer, ModelError>
+  where
+    F: Fn(&str) -> Result<Array2<f32>, ModelError>,
+  {
+    let read = |name: &str| {
+      let arr = tensor(name)?;
+      expect_shape(name, &arr, (features, 1))?;
+      Ok::<_, ModelError>(arr)
+    };
+    Ok(match self {
+      NormSpec::BatchNorm {
+        gamma,
+        beta,
+        running_mean,
+        running_var,
+      } => {
+        let (gamma, beta, mean) = (read(gamma)?, read(beta)?, read(running_mean)?);
+        let var = read(running_var)?;
+        let negative = var.iter().filter(|&&v| v < 0.0).count();
+        if negative > 0 {
+          return Err(ModelError::NegativeVariance {
+            name: running_var.clone(),
+            count: negative,
+          });
+        }
+        NormLayer {
+          kind: NormKind::BatchNorm,
+          gamma,
+          beta,
+          running: Some(RunningStats { mean, var }),
+        }
+      }
+      NormSpec::LayerNorm { gamma, beta } => NormLayer {
+        kind: NormKind::LayerNorm,
+        gamma: read(gamma)?,
+        beta: read(beta)?,
+        running: None,
+      },
+    })
+  }
+}
+
+fn invalid_metadata(key: &str, e: serde_json::Error) -> ModelError {
+  ModelError::InvalidMetadata {
+    key: key.to_string(),
+    message: e.to_string(),
+  }
+}
+
+fn expect_shape(name: &str, arr: &Array2<f32>, expected: (usize, usize)) -> Result<(), ModelError> {
+  if arr.dim() != expected {
+    return Err(ModelError::ShapeMismatch {
+      name: name.to_string(),
+      expected: vec![expected.0, expected.1],
+      actual: arr.shape().to_vec(),
+    });
+  }
+  Ok(())
+}
+
+/// The MNIST input: one 28x28 grayscale channel.
+pub fn mnist_input_shape() -> Vec<usize> {
+  vec![1, 28, 28]
+}
+
+/// Labels of the ten digit classes, "0" to "9".
+pub fn digit_labels() -> Vec<String> {
+  (0..10).map(|digit: u8| digit.to_string()).collect()
+}
+
 impl SerializableModel {
-  pub fn load_from_safetensors<P: AsRef<Path>>(
-    path: P,
-  ) -> Result<Self, Box<dyn std::error::Error>> {
+  pub fn load_from_safetensors<P: AsRef<Path>>(path: P) -> Result<Self, ModelError> {
     // Read the safetensors file
     let mut file = File::open(path)?;
     let mut buffer = Vec::new();
@@ -28,48 +279,421 @@ impl SerializableModel {
 
     // Parse the safetensors
     let tensors = SafeTensors::deserialize(&buffer)?;
+    let (_, header) = SafeTensors::read_metadata(&buffer)?;
+    let metadata = header.metadata().clone().unwrap_or_default();
 
-    // Helper function to convert bytes to f32 vector
-    let bytes_to_f32_vec = |data: &[u8]| -> Vec<f32> {
-      data
-        .chunks_exact(4)
-        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
-        .collect()
+    let tensor = |name: &str| -> Result<Array2<f32>, ModelError> {
+      let arr = read_tensor(&tensors, name)?;
+      let non_finite = arr.iter().filter(|x| !x.is_finite()).count();
+      if non_finite > 0 {
+        return Err(ModelError::NonFinite {
+          name: name.to_string(),
+          count: non_finite,
+        });
+      }
+      Ok(arr)
+    };
+    let parse_metadata = |key: &str| -> Result<Option<serde_json::Value>, ModelError> {
+      match metadata.get(key) {
+        Some(json) => serde_json::from_str(json)
+          .map(Some)
+          .map_err(|e| invalid_metadata(key, e)),
+        None => Ok(None),
+      }
     };
 
-    // Extract w1
-    let w1_view = tensors.tensor("w1")?;
-    let w1_data = bytes_to_f32_vec(w1_view.data());
-    let w1_shape = w1_view.shape();
-    let w1_shape = (w1_shape[0], w1_shape[1]);
-
-    // Extract b1
-    let b1_view = tensors.tensor("b1")?;
-    let b1_data = bytes_to_f32_vec(b1_view.data());
-    let b1_shape = b1_view.shape();
-    let b1_shape = (b1_shape[0], b1_shape[1]);
-
-    // Extract w2
-    let w2_view = tensors.tensor("w2")?;
-    let w2_data = bytes_to_f32_vec(w2_view.data());
-    let w2_shape = w2_view.shape();
-    let w2_shape = (w2_shape[0], w2_shape[1]);
-
-    // Extract b2
-    let b2_view = tensors.tensor("b2")?;
-    let b2_data = bytes_to_f32_vec(b2_view.data());
-    let b2_shape = b2_view.shape();
-    let b2_shape = (b2_shape[0], b2_shape[1]);
-
-    Ok(SerializableModel {
-      w1: w1_data,
-      w1_shape,
-      b1: b1_data,
-      b1_shape,
-      w2: w2_data,
-      w2_shape,
-      b2: b2_data,
-      b2_shape,
-    })
+    let model = match parse_metadata(ARCHITECTURE_KEY)? {
+      Some(architecture) => {
+        // check the version before anything else: other versions may not parse at all
+        let found = architecture.get("format_version").and_then(|v| v.as_u64());
+        let supported = MIN_FORMAT_VERSION..=FORMAT_VERSION;
+        if !found.is_some_and(|found| supported.contains(&(found as u32))) {
+          return Err(ModelError::UnsupportedVersion { found, supported });
+        }
+        let architecture: Architecture = serde_json::from_value(architecture)
+          .map_err(|e| invalid_metadata(ARCHITECTURE_KEY, e))?;
+
+        let mut conv_layers = Vec::new();
+        let mut layers = Vec::with_capacity(architecture.layers.len());
+        let mut flattened = false;
+        for spec in architecture.layers {
+          let misplaced = |message: &str| ModelError::InvalidMetadata {
+            key: ARCHITECTURE_KEY.to_string(),
+            message: message.to_string(),
+          };
+          match &spec {
+            LayerSpec::Conv2d { .. }
+            | LayerSpec::MaxPool2d { .. }
+            | LayerSpec::AvgPool2d { .. }
+              if flattened || !layers.is_empty() =>
+            {
+              return Err(misplaced(
+                "convolutional layers must come before the flatten and the dense layers",
+              ));
+            }
+            LayerSpec::Flatten if flattened || !layers.is_empty() => {
+              return Err(misplaced("flatten must come before the dense layers, once"));
+            }
+            LayerSpec::Dense { .. } | LayerSpec::QuantizedDense { .. }
+              if !conv_layers.is_empty() && !flattened =>
+            {
+              return Err(misplaced(
+                "convolutional layers must be flattened before the dense layers",
+              ));
+            }
+            _ => {}
+          }
+
+          match spec {
+            LayerSpec::Conv2d {
+              weight,
+              bias,
+              in_channels,
+              out_channels,
+              kernel_size,
+              stride,
+              padding,
+              activation,
+            } => {
+              let w = tensor(&weight)?;
+              let b = tensor(&bias)?;
+              expect_shape(
+                &weight,
+                &w,
+                (out_channels, in_channels * kernel_size * kernel_size),
+              )?;
+              expect_shape(&bias, &b, (out_channels, 1))?;
+              conv_layers.push(ConvLayer::Conv2d(Conv2d {
+                w,
+                b,
+                in_channels,
+                kernel_size,
+                stride,
+                padding,
+                activation,
+              }));
+            }
+            LayerSpec::MaxPool2d { size, stride } => conv_layers.push(ConvLayer::Pool2d(Pool2d {
+              kind: PoolKind::Max,
+              size,
+              stride,
+            })),
+            LayerSpec::AvgPool2d { size, stride } => conv_layers.push(ConvLayer::Pool2d(Pool2d {
+              kind: PoolKind::Avg,
+              size,
+              stride,
+            })),
+            LayerSpec::Flatten => flattened = true,
+            LayerSpec::Dense {
+              weight,
+              bias,
+              inputs,
+              outputs,
+              activation,
+              norm,
+            } => {
+              let w = tensor(&weight)?;
+              let b = tensor(&bias)?;
+              expect_shape(&weight, &w, (outputs, inputs))?;
+              expect_shape(&bias, &b, (outputs, 1))?;
+              let mut layer = SerializableLayer::new(&w, &b, activation);
+              layer.norm = norm.map(|norm| norm.load(outputs, tensor)).transpose()?;
+              layers.push(layer);
+            }
+            LayerSpec::QuantizedDense {
+              weight,
+              weight_scale,
+              bias,
+              input_scale,
+              inputs,
+              outputs,
+              activation,
+              norm,
+            } => {
+              let weights = read_i8_tensor(&tensors, &weight)?;
+              let scales = tensor(&weight_scale)?;
+              let b = tensor(&bias)?;
+              if weights.dim() != (outputs, inputs) {
+                return Err(ModelError::ShapeMismatch {
+                  name: weight,
+                  expected: vec![outputs, inputs],
+                  actual: weights.shape().to_vec(),
+                });
+              }
+              if scales.dim() != (1, 1) {
+                expect_shape(&weight_scale, &scales, (outputs, 1))?;
+              }
+              expect_shape(&bias, &b, (outputs, 1))?;
+              if !(input_scale.is_finite() && input_scale > 0.0) {
+                return Err(ModelError::InvalidMetadata {
+                  key: ARCHITECTURE_KEY.to_string(),
+                  message: format!("{} has input scale {}", weight, input_scale),
+                });
+              }
+
+              let quantized = QuantizedWeights {
+                weights,
+                scales,
+                input_scale,
+              };
+              let mut layer = SerializableLayer::new(&quantized.dequantize(), &b, activation);
+              layer.quantized = Some(quantized);
+              layer.norm = norm.map(|norm| norm.load(outputs, tensor)).transpose()?;
+              layers.push(layer);
+            }
+          }
+        }
+
+        if layers.is_empty() {
+          return Err(ModelError::NoLayers);
+        }
+
+        let input_size = architecture.input_shape.iter().product();
+        SerializableModel {
+          conv_layers,
+          layers,
+          preprocessor: Preprocessor::from_tensors(architecture.preprocessing, input_size, tensor)?,
+          input_shape: architecture.input_shape,
+          class_labels: architecture.class_labels,
+          training: architecture.training,
+        }
+      }
+      None => {
+        let mut layers = Vec::new();
+
+        // Extract w1/b1, w2/b2, ... until we run out of layers
+        for n in 1.. {
+          let w_name = format!("w{}", n);
+          if !tensors.names().iter().any(|name| **name == w_name) {
+            break;
+          }
+
+          let w = tensor(&w_name)?;
+          let b_name = format!("b{}", n);
+          let b = tensor(&b_name)?;
+          expect_shape(&b_name, &b, (w.nrows(), 1))?;
+          layers.push(SerializableLayer::new(&w, &b, Activation::Sigmoid));
+        }
+
+        if layers.is_empty() {
+          return Err(ModelError::NoLayers);
+        }
+
+        // files without an architecture: sigmoid hidden layers feeding softmax
+        layers.last_mut().unwrap().activation = Activation::Identity;
+
+        SerializableModel {
+          conv_layers: Vec::new(),
+          layers,
+          preprocessor: Preprocessor::default(),
+          input_shape: mnist_input_shape(),
+          class_labels: digit_labels(),
+          training: None,
+        }
+      }
+    };
+
+    model.check_layers_connect()?;
+    Ok(model)
+  }
+
+  /// Check that each layer's input width matches the output of whatever feeds it.
+  fn check_layers_connect(&self) -> Result<(), ModelError> {
+    let input_size = self.input_shape.iter().product();
+    let mut width = self.preprocessor.output_size(input_size);
+    if !self.conv_layers.is_empty() {
+      let mismatch = |layer: usize, message: String| ModelError::ConvMismatch { layer, message };
+      let mut shape = shape_of(&self.input_shape).ok_or_else(|| {
+        mismatch(
+          1,
+          format!(
+            "needs a channels x height x width input, not {:?}",
+            self.input_shape
+          ),
+        )
+      })?;
+      if width != input_size {
+        return Err(mismatch(
+          1,
+          format!(
+            "needs the whole image, but preprocessing reduces it to {} values",
+            width
+          ),
+        ));
+      }
+      for (i, layer) in self.conv_layers.iter().enumerate() {
+        shape = layer.output_shape(shape).map_err(|e| mismatch(i + 1, e))?;
+      }
+      width = shape.0 * shape.1 * shape.2;
+    }
+
+    for (i, layer) in self.layers.iter().enumerate() {
+      if layer.w_shape.1 != width {
+        return Err(ModelError::LayerMismatch {
+          layer: i + 1,
+          inputs: layer.w_shape.1,
+          received: width,
+        });
+      }
+      width = layer.w_shape.0;
+    }
+    if width != self.class_labels.len() {
+      return Err(ModelError::LabelMismatch {
+        outputs: width,
+        labels: self.class_labels.len(),
+      });
+    }
+    Ok(())
+  }
+
+  /// The tensors and `__metadata__` entries this model is stored as.
+  pub fn to_tensors(
+    &self,
+  ) -> Result<(NamedTensors, HashMap<String, String>), Box<dyn std::error::Error>> {
+    let mut arrays = Vec::with_capacity(self.layers.len() * 2);
+    let mut layers = Vec::with_capacity(self.conv_layers.len() + 1 + self.layers.len());
+    for (i, layer) in self.conv_layers.iter().enumerate() {
+      layers.push(match layer {
+        ConvLayer::Conv2d(conv) => {
+          let (weight, bias) = (
+            format!("conv{}.weight", i + 1),
+            format!("conv{}.bias", i + 1),
+          );
+          arrays.push((weight.clone(), conv.w.clone()));
+          arrays.push((bias.clone(), conv.b.clone()));
+          LayerSpec::Conv2d {
+            weight,
+            bias,
+            in_channels: conv.in_channels,
+            out_channels: conv.out_channels(),
+            kernel_size: conv.kernel_size,
+            stride: conv.stride,
+            padding: conv.padding,
+            activation: conv.activation,
+          }
+        }
+        ConvLayer::Pool2d(pool) => match pool.kind {
+          PoolKind::Max => LayerSpec::MaxPool2d {
+            size: pool.size,
+            stride: pool.stride,
+          },
+          PoolKind::Avg => LayerSpec::AvgPool2d {
+            size: pool.size,
+            stride: pool.stride,
+          },
+        },
+      });
+    }
+    if !self.conv_layers.is_empty() {
+      layers.push(LayerSpec::Flatten);
+    }
+    for (i, layer) in self.layers.iter().enumerate() {
+      let n = i + 1;
+      let (weight, bias) = (format!("w{}", n), format!("b{}", n));
+      arrays.push((
+        bias.clone(),
+        Array2::from_shape_vec(layer.b_shape, layer.b.clone())?,
+      ));
+      let norm = layer
+        .norm
+        .as_ref()
+        .map(|norm| NormSpec::new(n, norm, &mut arrays));
+      match &layer.quantized {
+        // the int8 weights themselves come from `i8_tensors`
+        Some(quantized) => {
+          let weight_scale = format!("{}.scale", weight);
+          arrays.push((weight_scale.clone(), quantized.scales.clone()));
+          layers.push(LayerSpec::QuantizedDense {
+            weight,
+            weight_scale,
+            bias,
+            input_scale: quantized.input_scale,
+            inputs: layer.w_shape.1,
+            outputs: layer.w_shape.0,
+            activation: layer.activation,
+            norm,
+          });
+        }
+        None => {
+          arrays.push((
+            weight.clone(),
+            Array2::from_shape_vec(layer.w_shape, layer.w.clone())?,
+          ));
+          layers.push(LayerSpec::Dense {
+            weight,
+            bias,
+            inputs: layer.w_shape.1,
+            outputs: layer.w_shape.0,
+            activation: layer.activation,
+            norm,
+          });
+        }
+      }
+    }
+
+    let (preprocess_tensors, preprocessing) = self.preprocessor.to_tensors();
+    arrays.extend(preprocess_tensors);
+
+    let architecture = Architecture {
+      format_version: self.format_version(),
+      input_shape: self.input_shape.clone(),
+      class_labels: self.class_labels.clone(),
+      preprocessing,
+      layers,
+      training: self.training.clone(),
+    };
+    let metadata = HashMap::from([(
+      ARCHITECTURE_KEY.to_string(),
+      serde_json::to_string(&architecture)?,
+    )]);
+
+    Ok((arrays, metadata))
+  }
+
+  /// Oldest format version that can describe this model.
+  fn format_version(&self) -> u32 {
+    if self.layers.iter().any(|layer| layer.quantized.is_some()) {
+      QUANTIZED_FORMAT_VERSION
+    } else if !self.conv_layers.is_empty() {
+      CONV_FORMAT_VERSION
+    } else if self.layers.iter().any(|layer| layer.norm.is_some()) {
+      NORM_FORMAT_VERSION
+    } else {
+      MIN_FORMAT_VERSION
+    }
+  }
+
+  /// The int8 weights of quantized layers, stored alongside `to_tensors`.
+  pub fn i8_tensors(&self) -> Vec<(String, Array2<i8>)> {
+    self
+      .layers
+      .iter()
+      .enumerate()
+      .filter_map(|(i, layer)| {
+        let quantized = layer.quantized.as_ref()?;
+        Some((format!("w{}", i + 1), quantized.weights.clone()))
+      })
+      .collect()
+  }
+
+  pub fn save_to_safetensors<P: AsRef<Path>>(
+    &self,
+    path: P,
+    dtype: TensorDtype,
+  ) -> Result<(), Box<dyn std::error::Error>> {
+    let (arrays, metadata) = self.to_tensors()?;
+    let named: Vec<(&str, &Array2<f32>)> = arrays
+      .iter()
+      .map(|(name, arr)| (name.as_str(), arr))
+      .collect();
+
+    let i8_tensors = self.i8_tensors();
+    let i8_named: Vec<(&str, &Array2<i8>)> = i8_tensors
+      .iter()
+      .map(|(name, arr)| (name.as_str(), arr))
+      .collect();
+
+    save_safetensors_with_i8(path, &named, &i8_named, Some(metadata), dtype)?;
+    Ok(())
   }
 }
@@ -1,15 +1,162 @@
+use clap::ValueEnum;
 use ndarray::Array2;
-use safetensors::{Dtype, SafeTensorError, View, serialize_to_file};
+use safetensors::tensor::TensorView;
+use safetensors::{Dtype, SafeTensors, View, serialize};
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
 use std::path::Path;
 
-/// Convert a Vec<f32> into little-endian bytes
-fn f32_vec_to_le_bytes(v: &[f32]) -> Vec<u8> {
-  let mut bytes = Vec::with_capacity(v.len() * 4);
-  for &f in v.iter() {
-    bytes.extend_from_slice(&f.to_bits().to_le_bytes());
+use crate::error::ModelError;
+
+/// Precision tensors are written in. Values are always f32 in memory; other dtypes are
+/// converted on save and converted back to f32 on load.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum TensorDtype {
+  /// IEEE half precision: half the size of f32, about 3 significant digits
+  F16,
+  /// bfloat16: half the size of f32 with its full range, about 2 significant digits
+  Bf16,
+  F32,
+  F64,
+}
+
+impl TensorDtype {
+  fn dtype(self) -> Dtype {
+    match self {
+      TensorDtype::F16 => Dtype::F16,
+      TensorDtype::Bf16 => Dtype::BF16,
+      TensorDtype::F32 => Dtype::F32,
+      TensorDtype::F64 => Dtype::F64,
+    }
+  }
+
+  /// Largest magnitude this dtype can hold without becoming infinite.
+  pub fn max_value(self) -> f32 {
+    match self {
+      TensorDtype::F16 => 65504.0,
+      TensorDtype::Bf16 => f32::from_bits(0x7f7f_0000),
+      TensorDtype::F32 | TensorDtype::F64 => f32::MAX,
+    }
+  }
+
+  /// Little-endian bytes of `values` in this dtype.
+  fn encode<'a>(self, values: impl Iterator<Item = &'a f32>) -> Vec<u8> {
+    match self {
+      TensorDtype::F16 => values
+        .flat_map(|&v| f32_to_f16_bits(v).to_le_bytes())
+        .collect(),
+      TensorDtype::Bf16 => values
+        .flat_map(|&v| f32_to_bf16_bits(v).to_le_bytes())
+        .collect(),
+      TensorDtype::F32 => values.flat_map(|&v| v.to_le_bytes()).collect(),
+      TensorDtype::F64 => values.flat_map(|&v| (v as f64).to_le_bytes()).collect(),
+    }
   }
-  bytes
+}
+
+/// Convert little-endian `data` of a supported dtype to f32. Returns None for other dtypes.
+fn decode_to_f32(dtype: Dtype, data: &[u8]) -> Option<Vec<f32>> {
+  let values = match dtype {
+    Dtype::F16 => data
+      .chunks_exact(2)
+      .map(|c| f16_bits_to_f32(u16::from_le_bytes([c[0], c[1]])))
+      .collect(),
+    Dtype::BF16 => data
+      .chunks_exact(2)
+      .map(|c| f32::from_bits((u16::from_le_bytes([c[0], c[1]]) as u32) << 16))
+      .collect(),
+    Dtype::F32 => data
+      .chunks_exact(4)
+      .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+      .collect(),
+    Dtype::F64 => data
+      .chunks_exact(8)
+      .map(|c| f64::from_le_bytes(c.try_into().unwrap()) as f32)
+      .collect(),
+    _ => return None,
+  };
+  Some(values)
+}
+
+/// Round an f32 to the nearest IEEE half precision value (ties to even).
+fn f32_to_f16_bits(value: f32) -> u16 {
+  let bits = value.to_bits();
+  let sign = ((bits >> 16) & 0x8000) as u16;
+  let exponent = ((bits >> 23) & 0xff) as i32;
+  let mantissa = bits & 0x7f_ffff;
+
+  if exponent == 0xff {
+    // infinity stays infinity, NaN stays NaN
+    return sign | 0x7c00 | if mantissa != 0 { 0x200 } else { 0 };
+  }
+
+  let half_exponent = exponent - 127 + 15;
+  if half_exponent >= 0x1f {
+    // too large for f16
+    return sign | 0x7c00;
+  }
+
+  // keep the top bits of the mantissa, rounding on the bits shifted out
+  let round = |mantissa: u32, shift: u32| {
+    let kept = mantissa >> shift;
+    let remainder = mantissa & ((1 << shift) - 1);
+    let halfway = 1 << (shift - 1);
+    if remainder > halfway || (remainder == halfway && kept & 1 == 1) {
+      kept + 1
+    } else {
+      kept
+    }
+  };
+
+  if half_exponent <= 0 {
+    // subnormal in f16, or too small and rounds to zero
+    if half_exponent < -10 {
+      return sign;
+    }
+    let shift = (14 - half_exponent) as u32;
+    return sign | round(mantissa | 0x80_0000, shift) as u16;
+  }
+
+  // a rounding carry out of the mantissa correctly bumps the exponent
+  sign | round(((half_exponent as u32) << 23) | mantissa, 13) as u16
+}
+
+fn f16_bits_to_f32(half: u16) -> f32 {
+  let sign = ((half & 0x8000) as u32) << 16;
+  let exponent = ((half >> 10) & 0x1f) as u32;
+  let mantissa = (half & 0x3ff) as u32;
+
+  let bits = match (exponent, mantissa) {
+    (0, 0) => sign,
+    (0, _) => {
+      // subnormal: shift the mantissa up until it has an implicit leading one
+      let mut exponent = 127 - 15 + 1;
+      let mut mantissa = mantissa;
+      while mantissa & 0x400 == 0 {
+        mantissa <<= 1;
+        exponent -= 1;
+      }
+      sign | (exponent << 23) | ((mantissa & 0x3ff) << 13)
+    }
+    (0x1f, _) => sign | 0x7f80_0000 | (mantissa << 13),
+    _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+  };
+  f32::from_bits(bits)
+}
+
+/// Round an f32 to the nearest bfloat16 value (ties to even).
+fn f32_to_bf16_bits(value: f32) -> u16 {
+  let bits = value.to_bits();
+  if value.is_nan() {
+    // keep it a NaN even if the payload is only in the low bits
+    return ((bits >> 16) | 0x40) as u16;
+  }
+  let rounding = 0x7fff + ((bits >> 16) & 1);
+  (bits.wrapping_add(rounding) >> 16) as u16
 }
 
 struct OwnedTensor {
@@ -36,27 +183,175 @@ impl View for OwnedTensor {
   }
 }
 
-/// Save a list of named Array2<f32> tensors into a safetensors file.
-/// `tensors` is a slice of (name, reference to array).
+/// Save a list of named Array2<f32> tensors into a safetensors file as F32.
+/// `tensors` is a slice of (name, reference to array); `metadata` is written to the
+/// file's `__metadata__` header.
+///
+/// The header is written with its keys sorted, so saving the same tensors and metadata
+/// always produces byte-identical files.
 pub fn save_safetensors<P: AsRef<Path>>(
   path: P,
   tensors: &[(&str, &Array2<f32>)],
-) -> Result<(), SafeTensorError> {
-  let mut owned: Vec<(&str, OwnedTensor)> = Vec::with_capacity(tensors.len());
+  metadata: Option<HashMap<String, String>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+  save_safetensors_as(path, tensors, metadata, TensorDtype::F32)
+}
+
+/// Like `save_safetensors`, converting every tensor to `dtype`.
+pub fn save_safetensors_as<P: AsRef<Path>>(
+  path: P,
+  tensors: &[(&str, &Array2<f32>)],
+  metadata: Option<HashMap<String, String>>,
+  dtype: TensorDtype,
+) -> Result<(), Box<dyn std::error::Error>> {
+  save_safetensors_with_i8(path, tensors, &[], metadata, dtype)
+}
+
+/// Like `save_safetensors_as`, also writing the I8 tensors `i8_tensors` as they are.
+pub fn save_safetensors_with_i8<P: AsRef<Path>>(
+  path: P,
+  tensors: &[(&str, &Array2<f32>)],
+  i8_tensors: &[(&str, &Array2<i8>)],
+  metadata: Option<HashMap<String, String>>,
+  dtype: TensorDtype,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let mut owned: Vec<(&str, OwnedTensor)> = Vec::with_capacity(tensors.len() + i8_tensors.len());
 
   for (name, arr) in tensors.iter() {
     let (r, c) = arr.dim();
-    let flat: Vec<f32> = arr.iter().cloned().collect();
-    let data = f32_vec_to_le_bytes(&flat);
 
     let ot = OwnedTensor {
-      dtype: Dtype::F32,
+      dtype: dtype.dtype(),
       shape: vec![r, c],
-      data,
+      data: dtype.encode(arr.iter()),
     };
 
-    owned.push((name.as_ref(), ot));
+    owned.push((name, ot));
+  }
+  for (name, arr) in i8_tensors.iter() {
+    let (r, c) = arr.dim();
+    owned.push((
+      name,
+      OwnedTensor {
+        dtype: Dtype::I8,
+        shape: vec![r, c],
+        data: arr.iter().map(|&v| v as u8).collect(),
+      },
+    ));
+  }
+
+  let bytes = canonicalize_header(&serialize(owned, metadata)?)?;
+  std::fs::write(path, bytes)?;
+  Ok(())
+}
+
+/// Rewrite the JSON header of a serialized safetensors buffer with sorted keys.
+/// `safetensors` writes `__metadata__` straight from a `HashMap`, whose iteration
+/// order changes from run to run.
+fn canonicalize_header(bytes: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+  let header_len = u64::from_le_bytes(bytes[..8].try_into()?) as usize;
+  let header_end = 8 + header_len;
+  // serde_json's Map is a BTreeMap, so re-serializing sorts every object's keys
+  let header: serde_json::Value = serde_json::from_slice(&bytes[8..header_end])?;
+  let mut header = serde_json::to_vec(&header)?;
+  // the data section must stay 8-byte aligned; safetensors pads with spaces
+  header.resize(header.len().next_multiple_of(8), b' ');
+
+  let mut out = Vec::with_capacity(8 + header.len() + bytes.len() - header_end);
+  out.extend_from_slice(&(header.len() as u64).to_le_bytes());
+  out.extend_from_slice(&header);
+  out.extend_from_slice(&bytes[header_end..]);
+  Ok(out)
+}
+
+/// Tensors in file order, paired with their names.
+pub type NamedTensors = Vec<(String, Array2<f32>)>;
+
+/// Load every tensor of a safetensors file written by `save_safetensors`, along with
+/// its `__metadata__` header (empty if the file has none).
+pub fn load_safetensors<P: AsRef<Path>>(
+  path: P,
+) -> Result<(NamedTensors, HashMap<String, String>), ModelError> {
+  let mut file = File::open(path)?;
+  let mut buffer = Vec::new();
+  file.read_to_end(&mut buffer)?;
+
+  let tensors = SafeTensors::deserialize(&buffer)?;
+  let (_, header) = SafeTensors::read_metadata(&buffer)?;
+
+  let mut arrays = Vec::with_capacity(tensors.len());
+  for name in tensors.names() {
+    arrays.push((name.to_string(), read_tensor(&tensors, name)?));
+  }
+  // names() comes in no particular order; sort so callers see a stable one
+  arrays.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+  Ok((arrays, header.metadata().clone().unwrap_or_default()))
+}
+
+/// Read the 2-dimensional tensor `name` from a parsed safetensors file, converting
+/// F16, BF16 and F64 data to f32.
+pub fn read_tensor(tensors: &SafeTensors, name: &str) -> Result<Array2<f32>, ModelError> {
+  let view = checked_view(
+    tensors,
+    name,
+    &[Dtype::F16, Dtype::BF16, Dtype::F32, Dtype::F64],
+  )?;
+  let values = decode_to_f32(view.dtype(), view.data()).expect("dtype checked above");
+  Ok(Array2::from_shape_vec(matrix_shape(&view), values).expect("length checked above"))
+}
+
+/// Read the 2-dimensional I8 tensor `name` from a parsed safetensors file.
+pub fn read_i8_tensor(tensors: &SafeTensors, name: &str) -> Result<Array2<i8>, ModelError> {
+  let view = checked_view(tensors, name, &[Dtype::I8])?;
+  let values = view.data().iter().map(|&byte| byte as i8).collect();
+  Ok(Array2::from_shape_vec(matrix_shape(&view), values).expect("length checked above"))
+}
+
+fn matrix_shape(view: &TensorView) -> (usize, usize) {
+  (view.shape()[0], view.shape()[1])
+}
+
+/// The 2-dimensional tensor `name`, checked to be stored as one of `dtypes` and to have
+/// data that fills its shape.
+fn checked_view<'a>(
+  tensors: &'a SafeTensors,
+  name: &str,
+  dtypes: &[Dtype],
+) -> Result<TensorView<'a>, ModelError> {
+  let view = tensors
+    .tensor(name)
+    .map_err(|_| ModelError::MissingTensor {
+      name: name.to_string(),
+    })?;
+
+  let dtype = view.dtype();
+  if !dtypes.contains(&dtype) {
+    return Err(ModelError::WrongDtype {
+      name: name.to_string(),
+      dtype: format!("{:?}", dtype),
+      expected: dtypes.iter().map(|dtype| format!("{:?}", dtype)).collect(),
+    });
+  }
+
+  let shape = view.shape();
+  if shape.len() != 2 {
+    return Err(ModelError::WrongRank {
+      name: name.to_string(),
+      expected: 2,
+      shape: shape.to_vec(),
+    });
+  }
+
+  let data = view.data();
+  let expected_bytes = shape[0] * shape[1] * dtype.bitsize() / 8;
+  if data.len() != expected_bytes {
+    return Err(ModelError::TruncatedData {
+      name: name.to_string(),
+      expected_bytes,
+      actual_bytes: data.len(),
+    });
   }
 
-  serialize_to_file(owned.into_iter(), None, path.as_ref())
+  Ok(view)
 }
@@ -1,10 +1,15 @@
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
+use std::fmt;
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct TrainingStats {
   pub total_loss: f32,
   pub total_correct: usize,
   pub total_samples: usize,
+  /// Regularization penalty summed over the samples, kept apart from the data loss.
+  #[serde(default)]
+  pub total_regularization: f32,
 }
 
 impl TrainingStats {
@@ -12,7 +17,7 @@ impl TrainingStats {
     Self::default()
   }
 
-  pub fn update(&mut self, loss: f32, correct: bool) -> () {
+  pub fn update(&mut self, loss: f32, correct: bool) {
     self.total_loss += loss;
     self.total_samples += 1;
     if correct {
@@ -20,11 +25,36 @@ impl TrainingStats {
     }
   }
 
-  pub fn to_string(&self) -> String {
-    format!(
-      "TrainingStats {{ mean loss: {}, accuracy: {}, samples: {} }}",
-      self.total_loss / self.total_samples as f32,
-      self.total_correct as f32 / self.total_samples as f32,
+  /// Record the regularization `penalty` of the weights a batch of `samples` was trained
+  /// with.
+  pub fn update_regularization(&mut self, penalty: f32, samples: usize) {
+    self.total_regularization += penalty * samples as f32;
+  }
+
+  /// Mean cross-entropy loss, without the regularization penalty.
+  pub fn mean_loss(&self) -> f32 {
+    self.total_loss / self.total_samples as f32
+  }
+
+  pub fn mean_regularization(&self) -> f32 {
+    self.total_regularization / self.total_samples as f32
+  }
+
+  pub fn accuracy(&self) -> f32 {
+    self.total_correct as f32 / self.total_samples as f32
+  }
+}
+
+impl fmt::Display for TrainingStats {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "TrainingStats {{ mean loss: {}, ", self.mean_loss())?;
+    if self.total_regularization > 0.0 {
+      write!(f, "regularization: {}, ", self.mean_regularization())?;
+    }
+    write!(
+      f,
+      "accuracy: {}, samples: {} }}",
+      self.accuracy(),
       self.total_samples
     )
   }
@@ -45,7 +75,7 @@ impl RollingMean {
     }
   }
 
-  pub fn push(&mut self, x: f32) -> () {
+  pub fn push(&mut self, x: f32) {
     self.buf.push_back(x);
     self.sum += x;
 
@@ -1,47 +1,197 @@
-use crate::inferrable_model::InferrableModel;
-use crate::serialization::save_safetensors;
+use crate::checkpoint::{
+  Checkpoint, CheckpointMetadata, CheckpointPolicy, RngState, save_checkpoint,
+};
+use crate::config::TrainingConfig;
+use crate::early_stopping::EarlyStopping;
+use crate::inferrable_model::{InferrableModel, Mode};
+use crate::optimizer::Optimizer;
+use crate::preprocessing::Preprocessor;
+use crate::schedule::LrScheduler;
+use crate::serializable_model::TrainingInfo;
+use crate::serialization::TensorDtype;
 use crate::stats::{RollingMean, TrainingStats};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use mnist::MnistBuilder;
-use ndarray::{Array2, Axis};
+use ndarray::{Array2, Axis, s};
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
 
-use crate::math::{sigmoid, sigmoid_derivative, softmax};
-
-const LR: f32 = 0.001;
 pub const TRAINING_SIZE: usize = 60_000 /* whole dataset */;
-const EPOCHS: usize = 15;
-const ROLLING_MEAN_SIZE: usize = 1000;
-
-pub fn run_train(model_path: &str) {
-  let mnist = MnistBuilder::new()
-    .label_format_digit()
-    .training_set_length(TRAINING_SIZE as u32)
-    .finalize();
-
-  let trn_img = Array2::from_shape_vec(
-    (TRAINING_SIZE, 28 * 28),
-    mnist
-      .trn_img
-      .clone()
-      .into_iter()
-      .map(|x| x as f32)
-      .collect(),
-  )
-  .unwrap();
-
-  let trn_lbl = mnist.trn_lbl.clone();
-
-  // --- Init weights ---
-  // 128 hidden layers
-  // let mut w1: ndarray::ArrayBase<ndarray::OwnedRepr<f32>, ndarray::Dim<[usize; 2]>> =
-  //   Array2::<f32>::random((128, 784), Uniform::new(-0.5, 0.5));
-  // -  let mut b1 = Array2::<f32>::zeros((128, 1));
-  // -
-  // -  // output layer (10 neurons, 10 digits)
-  // -  let mut w2 = Array2::<f32>::random((10, 128), Uniform::new(-0.5, 0.5));
-  // -  let mut b2 = Array2::<f32>::zeros((10, 1));
-
-  let mut model = InferrableModel::new();
+// images per forward pass when evaluating on the validation set
+const EVAL_BATCH_SIZE: usize = 1000;
+
+/// Training images, one per row with pixel values 0-255, and their digit labels.
+pub struct TrainingData {
+  pub images: Array2<f32>,
+  pub labels: Vec<u8>,
+}
+
+impl TrainingData {
+  /// The first `training_size` images of the MNIST training set in `data/`.
+  pub fn load_mnist(training_size: usize) -> Self {
+    let mnist = MnistBuilder::new()
+      .label_format_digit()
+      .training_set_length(training_size as u32)
+      .finalize();
+
+    let images = Array2::from_shape_vec(
+      (training_size, 28 * 28),
+      mnist.trn_img.into_iter().map(|x| x as f32).collect(),
+    )
+    .unwrap();
+
+    TrainingData {
+      images,
+      labels: mnist.trn_lbl,
+    }
+  }
+
+  /// Remove the last `count` images and return them as a separate set.
+  pub fn split_off(&mut self, count: usize) -> TrainingData {
+    let keep = self.len() - count;
+    let images = self.images.slice(s![keep.., ..]).to_owned();
+    self.images = self.images.slice(s![..keep, ..]).to_owned();
+    TrainingData {
+      images,
+      labels: self.labels.split_off(keep),
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.labels.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.labels.is_empty()
+  }
+}
+
+/// Train a model on MNIST and save it to `model_path` with weights stored as
+/// `save_dtype`. Pass a checkpoint in `resume` to continue an interrupted run exactly
+/// where it left off.
+pub fn run_train(
+  model_path: &str,
+  save_dtype: TensorDtype,
+  config: &TrainingConfig,
+  checkpoints: &CheckpointPolicy,
+  resume: Option<Checkpoint>,
+) {
+  println!(
+    "Training config:\n{}",
+    serde_json::to_string_pretty(config).expect("failed to format training config")
+  );
+
+  let mut data = TrainingData::load_mnist(config.training_size);
+  let validation = data.split_off(config.validation_size());
+  let validation = (!validation.is_empty()).then_some(&validation);
+  let model = train(config, &data, validation, checkpoints, resume);
+
+  println!("\nTraining finished, saving model to {}", model_path);
+  match save_trained_model(model_path, &model, config, save_dtype) {
+    Ok(()) => println!("Model saved to {} as safetensors", model_path),
+    Err(e) => eprintln!("Failed to save model: {}", e),
+  }
+}
+
+/// Train a model on `data` and return it. If `validation` is given, the model is
+/// evaluated on it after every epoch.
+///
+/// Every random choice (weight initialization, the order images are visited in each
+/// epoch and the dropout masks) is drawn from a single RNG seeded with `config.seed`, so
+/// the same config and data always produce the same weights.
+pub fn train(
+  config: &TrainingConfig,
+  data: &TrainingData,
+  validation: Option<&TrainingData>,
+  checkpoints: &CheckpointPolicy,
+  resume: Option<Checkpoint>,
+) -> InferrableModel {
+  let training_size = data.len();
+  let epochs = config.epochs;
+  let batch_size = config.batch_size;
+
+  let seed = config.seed.expect("training config has no seed");
+  let steps_per_epoch = training_size.div_ceil(batch_size);
+  let dropout = config.hidden_dropout();
+  let mut optimizer = config.optimizer.build();
+
+  // `epoch_rng` is the RNG as it was at the start of the epoch being resumed, so the
+  // epoch's shuffle can be replayed before continuing from `rng`
+  let mut epoch_stats = TrainingStats::new();
+  let mut epoch_rng = None;
+  let mut early_stopping = config
+    .early_stopping
+    .patience
+    .map(|_| EarlyStopping::new(&config.early_stopping));
+  let mut best_model = None;
+  let (
+    mut model,
+    mut rng,
+    mut scheduler,
+    start_epoch,
+    mut skip_steps,
+    mut global_step,
+    mut best_loss,
+  ) = match resume {
+    Some(checkpoint) => {
+      let progress = checkpoint.metadata;
+      println!(
+        "Resuming from epoch {} step {} (global step {})",
+        progress.epoch, progress.step_in_epoch, progress.global_step
+      );
+      // the moments and the plateau progress carry over, the hyperparameters and the
+      // schedule come from this run's flags
+      let mut model = checkpoint.model;
+      let shapes: Vec<(usize, usize)> = model.parameters_mut().iter().map(|p| p.dim()).collect();
+      optimizer
+        .load_state(checkpoint.optimizer.state(), &shapes)
+        .expect("checkpoint optimizer state does not fit its model");
+      let mut scheduler = progress.scheduler;
+      scheduler.reconfigure(&config.schedule, config.optimizer.lr, epochs);
+      epoch_stats = progress.epoch_stats;
+      epoch_rng = Some(progress.epoch_rng.restore());
+      // the checkpoint's controller keeps its best score, but the patience and minimum
+      // improvement come from this run's flags; without a patience early stopping is off
+      early_stopping = config
+        .early_stopping
+        .patience
+        .and(match progress.early_stopping {
+          Some(mut restored) => {
+            restored.reconfigure(&config.early_stopping);
+            Some(restored)
+          }
+          None => early_stopping,
+        });
+      best_model = checkpoint.best_model;
+      (
+        model,
+        progress.rng.restore(),
+        scheduler,
+        progress.epoch,
+        progress.step_in_epoch,
+        progress.global_step,
+        progress.best_loss,
+      )
+    }
+    None => {
+      let mut rng = ChaCha8Rng::seed_from_u64(seed);
+      let mut model = InferrableModel::new(&config.layers, &config.hidden_activations(), &mut rng);
+      model.add_conv_layers(&config.conv_layers, &mut rng);
+      model.add_norm_layers(&config.hidden_norm_layers());
+      model.preprocessor = Preprocessor::fit(&config.preprocessing, &data.images);
+      let scheduler = LrScheduler::new(
+        &config.schedule,
+        config.optimizer.lr,
+        steps_per_epoch,
+        epochs,
+      );
+      (model, rng, scheduler, 0, 0, 0, None)
+    }
+  };
+
+  // preprocess the whole training set up front; one image per column
+  let inputs = model.preprocessor.apply(&data.images.t().to_owned());
 
   // training loop
 
@@ -52,139 +202,281 @@ pub fn run_train(model_path: &str) {
       .unwrap()
       .progress_chars("##-");
 
-  for epoch in 0..EPOCHS {
+  for epoch in start_epoch..epochs {
     println!("Epoch: {}", epoch);
-    let rolling_entropy_loss = &mut RollingMean::new(ROLLING_MEAN_SIZE);
+    let rolling_entropy_loss = &mut RollingMean::new(config.rolling_mean_size);
 
-    let pb = m.add(ProgressBar::new(TRAINING_SIZE as u64));
+    let pb = m.add(ProgressBar::new(training_size as u64));
     pb.set_style(sty.clone());
-    pb.set_prefix(format!("Epoch {}/{}", epoch + 1, EPOCHS));
+    pb.set_prefix(format!("Epoch {}/{}", epoch + 1, epochs));
     pb.set_message(format!("loss={:.4}", rolling_entropy_loss.mean()));
 
-    let stats = &mut TrainingStats::new();
+    // visit the images in a new random order every epoch
+    let mut order: Vec<usize> = (0..training_size).collect();
+    let epoch_start_rng = match epoch_rng.take() {
+      Some(mut resumed_rng) => {
+        let start = RngState::new(seed, &resumed_rng);
+        order.shuffle(&mut resumed_rng);
+        start
+      }
+      None => {
+        let start = RngState::new(seed, &rng);
+        order.shuffle(&mut rng);
+        start
+      }
+    };
+
+    // when resuming mid-epoch, pick up the stats of the batches the checkpoint already
+    // trained on and skip those batches.
+    let stats = &mut std::mem::take(&mut epoch_stats);
+    let first_batch = std::mem::take(&mut skip_steps);
+    pb.inc((first_batch * batch_size).min(training_size) as u64);
+
+    for (step_in_epoch, start) in (0..training_size)
+      .step_by(batch_size)
+      .enumerate()
+      .skip(first_batch)
+    {
+      let end = (start + batch_size).min(training_size);
+      let batch = &order[start..end];
 
-    for (i, (image, &y)) in trn_img.outer_iter().zip(trn_lbl.iter()).enumerate() {
       // Forward
-      // 128x784 * 784x1 = 128x1 - hidden layer
-      let image = image.insert_axis(Axis(1)); // make it 1x784 ?
-      // println!("image shape {:?}", image.dim());
-      let z1 = &model.w1.dot(&image) + &model.b1;
-      // println!("w1 dot image dim {:?}", &w1.dot(&image).dim());
-      // println!("image dim {:?}", image.dim());
-      // println!("w1 dim {:?}", w1.dim());
-      // println!("b1 dim {:?}", b1.dim());
-      // clamp / normalize 128x1
-      let a1 = sigmoid(&z1);
-
-      // 10*128 * 128x1 = 10x1; 10x1 + 10x1
-      let z2 = &model.w2.dot(&a1) + &model.b2;
-
-      // redistribute so all values sum up to 1
-      let a2 = softmax(&z2);
-
-      let correct_probability = a2[[y as usize, 0]];
-      let entropy_loss = -((correct_probability + 1e-10).ln()); // add small value to avoid log(0)
-      let max_probability = a2.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
-      let is_correct = correct_probability == max_probability;
-
-      // One-hot target (the correct probabilities)
-      let mut y_vec = Array2::<f32>::zeros((10, 1));
-      y_vec[[y as usize, 0]] = 1.0;
-
-      // subtract the guesses by the actual answer; the more correct, the lower the values will be.
-      // 10x1 with values -0.5..0.5
-      let dz2 = &a2 - &y_vec;
-
-      // 10x1 * 1x128 (transposed a1) = 10x128
-      // how much did each of these hidden layers contribute to each neurons wrong guess?
-      let dw2 = dz2.dot(&a1.t());
-      // 10x128
-      let db2 = dz2.clone();
+      // 784xB, one image per column
+      let images = inputs.select(Axis(1), batch);
+      let pass = model.forward_pass(
+        &images,
+        Mode::Train {
+          dropout: &dropout,
+          rng: &mut rng,
+        },
+      );
+      model.update_running_stats(&pass);
+      let output = pass.output();
+
+      // One-hot targets (the correct probabilities), one column per image
+      let mut y_batch = Array2::<f32>::zeros(output.dim());
+      for (column, &i) in batch.iter().enumerate() {
+        let y = data.labels[i];
+        y_batch[[y as usize, column]] = 1.0;
+
+        let probabilities = output.column(column);
+        let correct_probability = probabilities[y as usize];
+        let entropy_loss = -((correct_probability + 1e-10).ln()); // add small value to avoid log(0)
+        let max_probability = probabilities
+          .iter()
+          .cloned()
+          .fold(f32::NEG_INFINITY, f32::max);
+        let is_correct = correct_probability == max_probability;
+
+        rolling_entropy_loss.push(entropy_loss);
+        stats.update(entropy_loss, is_correct);
+      }
 
       // Error
-
-      // 128x10 * 10x1 = 128x1 * 128x1 (how saturated are these hidden neurons? If saturated they've learned a feature and dz1_x will be close to 0.)
-      // derivative should be taken on the activated values (a1)
-      let dz1 = model.w2.t().dot(&dz2) * sigmoid_derivative(&a1);
-
-      // "flash" the image on to the hidden layer by multiplying it
-      // 128x1 * 1x784 = 128x784 = one "flashed" (multiplied) hidden layer neuron. We'll back-propagate by this the error amount, except if the neuron is saturated.
-      let dw1 = dz1.dot(&image.t());
-      let db1 = dz1.clone();
-
-      model.w2 = &model.w2 - &(LR * &dw2);
-      model.b2 = &model.b2 - &(LR * &db2);
-      model.w1 = &model.w1 - &(LR * &dw1);
-      model.b1 = &model.b1 - &(LR * &db1);
-
-      rolling_entropy_loss.push(entropy_loss);
-      stats.update(entropy_loss, is_correct);
+      let mut gradients = model.backward(&pass, &y_batch);
+      let regularization = &config.regularization;
+      stats.update_regularization(regularization.penalty(&model), batch.len());
+      regularization.add_gradients(&model, &mut gradients);
+
+      optimizer.set_learning_rate(scheduler.lr(global_step));
+      optimizer.step(&mut model.parameters_mut(), &gradients.tensors());
+      regularization.apply_max_norm(&mut model);
+      global_step += 1;
+
+      if checkpoints.after_step(global_step) {
+        let progress = CheckpointMetadata {
+          config: config.clone(),
+          epoch,
+          step_in_epoch: step_in_epoch + 1,
+          global_step,
+          rng: RngState::new(seed, &rng),
+          epoch_rng: epoch_start_rng.clone(),
+          scheduler: scheduler.clone(),
+          epoch_stats: stats.clone(),
+          best_loss,
+          early_stopping: early_stopping.clone(),
+        };
+        write_checkpoint(
+          checkpoints,
+          &progress,
+          &model,
+          best_model.as_ref(),
+          optimizer.as_ref(),
+        );
+      }
 
       // update the per-epoch progress bar: show rolling mean and iteration
-      pb.inc(1);
+      pb.inc((end - start) as u64);
       pb.set_message(format!(
-        "loss={:.4} it={}/{}",
+        "loss={:.4} lr={:.2e} it={}/{}",
         rolling_entropy_loss.mean(),
-        i + 1,
-        TRAINING_SIZE
+        optimizer.learning_rate(),
+        end,
+        training_size
       ));
     }
     println!("Training stats: {:?}", stats.to_string());
+    // reduce-on-plateau watches the validation loss, or the mean training loss of the
+    // epoch if nothing was held out
+    let val_stats = validation.map(|validation| evaluate(&model, validation));
+    if let Some(val_stats) = &val_stats {
+      println!("Validation stats: {:?}", val_stats.to_string());
+    }
+    let epoch_loss = val_stats.as_ref().unwrap_or(stats).mean_loss();
+    scheduler.end_epoch(epoch_loss);
+    if best_loss.is_none_or(|best| epoch_loss < best) {
+      best_loss = Some(epoch_loss);
+    }
 
     pb.finish_with_message("done");
+
+    if let Some(early_stopping) = &mut early_stopping {
+      if early_stopping.end_epoch(epoch + 1, stats, val_stats.as_ref()) {
+        best_model = Some(model.clone());
+      }
+      if early_stopping.should_stop() {
+        println!(
+          "Early stopping after epoch {}: {}",
+          epoch + 1,
+          early_stopping.reason()
+        );
+        break;
+      }
+    }
+
+    if checkpoints.after_epoch(epoch + 1) {
+      let progress = CheckpointMetadata {
+        config: config.clone(),
+        epoch: epoch + 1,
+        step_in_epoch: 0,
+        global_step,
+        rng: RngState::new(seed, &rng),
+        epoch_rng: RngState::new(seed, &rng),
+        scheduler: scheduler.clone(),
+        epoch_stats: TrainingStats::new(),
+        best_loss,
+        early_stopping: early_stopping.clone(),
+      };
+      write_checkpoint(
+        checkpoints,
+        &progress,
+        &model,
+        best_model.as_ref(),
+        optimizer.as_ref(),
+      );
+    }
   }
 
-  println!("\nTraining finished, saving model to {}", model_path);
+  // with early stopping on, keep the best weights rather than the last ones
+  if let (Some(early_stopping), Some(best_model)) = (&early_stopping, best_model) {
+    println!(
+      "Restoring the best weights, from epoch {}",
+      early_stopping.best_epoch()
+    );
+    model = best_model;
+  }
+
+  model
+}
 
-  let model = model.to_serializable_model();
+/// Mean cross-entropy loss and accuracy of `model` over `data`, which holds raw pixels.
+pub fn evaluate(model: &InferrableModel, data: &TrainingData) -> TrainingStats {
+  let mut stats = TrainingStats::new();
+  for start in (0..data.len()).step_by(EVAL_BATCH_SIZE) {
+    let end = (start + EVAL_BATCH_SIZE).min(data.len());
+    let images = data.images.slice(s![start..end, ..]).t().to_owned();
+    let output = model.forward(&model.preprocessor.apply(&images), Mode::Eval);
+
+    for (column, &y) in data.labels[start..end].iter().enumerate() {
+      let probabilities = output.column(column);
+      let correct_probability = probabilities[y as usize];
+      let max_probability = probabilities
+        .iter()
+        .cloned()
+        .fold(f32::NEG_INFINITY, f32::max);
+      stats.update(
+        -((correct_probability + 1e-10).ln()),
+        correct_probability == max_probability,
+      );
+    }
+  }
+  stats
+}
+
+/// Save a trained model along with the config that produced it, storing tensors as
+/// `dtype`. Refuses to write weights containing NaN or infinity, or too large for `dtype`.
+pub fn save_trained_model(
+  model_path: &str,
+  model: &InferrableModel,
+  config: &TrainingConfig,
+  dtype: TensorDtype,
+) -> Result<(), Box<dyn std::error::Error>> {
+  let mut model = model.to_serializable_model();
+  model.training = Some(TrainingInfo::new(config)?);
   // Check for non-finite values. serde_json serializes NaN/Inf to null,
   // which is why you were seeing nulls in the JSON file.
-  let check = |v: &Vec<f32>, name: &str| {
-    let non_finite = v.iter().filter(|x| !x.is_finite()).count();
+  fn check<'a>(values: impl IntoIterator<Item = &'a f32> + Copy, name: &str) -> usize {
+    let non_finite = values.into_iter().filter(|x| !x.is_finite()).count();
     if non_finite > 0 {
       eprintln!(
         "Model contains {} non-finite values in {}",
         non_finite, name
       );
       // print a few samples for diagnosis
-      let sample: Vec<_> = v.iter().take(10).cloned().collect();
+      let sample: Vec<_> = values.into_iter().take(10).cloned().collect();
       eprintln!("{} sample: {:?}", name, sample);
     }
     non_finite
-  };
+  }
 
-  let nf_w1 = check(&model.w1, "w1");
-  let nf_b1 = check(&model.b1, "b1");
-  let nf_w2 = check(&model.w2, "w2");
-  let nf_b2 = check(&model.b2, "b2");
+  // every tensor the file will hold, normalization statistics and preprocessing included
+  let (tensors, _) = model.to_tensors()?;
+  let mut non_finite = 0;
+  for (name, tensor) in &tensors {
+    non_finite += check(tensor, name);
+  }
 
-  if nf_w1 + nf_b1 + nf_w2 + nf_b2 > 0 {
-    eprintln!(
-      "Aborting save because model contains non-finite values (NaN/Inf). Try using a smaller learning rate or stabilizing activations."
+  if non_finite > 0 {
+    return Err(
+      "model contains non-finite values (NaN/Inf). Try using a smaller learning rate or stabilizing activations."
+        .into(),
     );
-    return;
   }
 
-  // Save tensors to safetensors file
-  let arr_w1 = Array2::from_shape_vec((model.w1_shape.0, model.w1_shape.1), model.w1.clone())
-    .expect("w1 shape mismatch");
-  let arr_b1 = Array2::from_shape_vec((model.b1_shape.0, model.b1_shape.1), model.b1.clone())
-    .expect("b1 shape mismatch");
-  let arr_w2 = Array2::from_shape_vec((model.w2_shape.0, model.w2_shape.1), model.w2.clone())
-    .expect("w2 shape mismatch");
-  let arr_b2 = Array2::from_shape_vec((model.b2_shape.0, model.b2_shape.1), model.b2.clone())
-    .expect("b2 shape mismatch");
-
-  match save_safetensors(
-    model_path,
-    &[
-      ("w1", &arr_w1),
-      ("b1", &arr_b1),
-      ("w2", &arr_w2),
-      ("b2", &arr_b2),
-    ],
-  ) {
-    Ok(()) => println!("Model saved to {} as safetensors", model_path),
-    Err(e) => eprintln!("Failed to write safetensors: {:?}", e),
+  let too_large = tensors
+    .iter()
+    .flat_map(|(_, tensor)| tensor.iter())
+    .filter(|x| x.abs() > dtype.max_value())
+    .count();
+  if too_large > 0 {
+    return Err(
+      format!(
+        "model contains {} weights too large to store as {:?} (max {})",
+        too_large,
+        dtype,
+        dtype.max_value()
+      )
+      .into(),
+    );
+  }
+
+  model.save_to_safetensors(model_path, dtype)
+}
+
+fn write_checkpoint(
+  checkpoints: &CheckpointPolicy,
+  progress: &CheckpointMetadata,
+  model: &InferrableModel,
+  best_model: Option<&InferrableModel>,
+  optimizer: &dyn Optimizer,
+) {
+  match save_checkpoint(&checkpoints.path, progress, model, best_model, optimizer) {
+    Ok(()) => println!(
+      "Checkpoint saved to {} (epoch {}, step {})",
+      checkpoints.path.display(),
+      progress.epoch,
+      progress.step_in_epoch
+    ),
+    Err(e) => eprintln!("Failed to write checkpoint: {:?}", e),
   }
 }
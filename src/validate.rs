@@ -1,38 +1,30 @@
 use indicatif::{ProgressBar, ProgressStyle};
 use ndarray::{Array2, Axis};
 
-use crate::inferrable_model::InferrableModel;
-use crate::math::{sigmoid, softmax};
-use crate::serializable_model::SerializableModel;
+use crate::evaluation::EvaluationReport;
+use crate::inferrable_model::{InferrableModel, Mode};
+use crate::math::argmax;
+use crate::misclassified::{CONTACT_SHEET_NAME, dump_errors, file_name};
 use mnist::MnistBuilder;
 
 const TEST_SIZE: usize = 10_000; // whole test dataset
 
-pub fn validate(model_path: &str) {
+/// Evaluate the model at `model_path` on the test set: print a report of per-class and
+/// overall metrics, and write it to `report_path` (JSON or CSV) if given. With a
+/// `baseline_path`, also report the baseline's accuracy and how much the model gains or
+/// loses against it. With an `errors_dir`, write the misclassified test images there,
+/// along with a contact sheet of the `contact_sheet_size` most confidently wrong ones.
+pub fn validate(
+  model_path: &str,
+  baseline_path: Option<&str>,
+  report_path: Option<&str>,
+  errors_dir: Option<&str>,
+  contact_sheet_size: usize,
+) {
   let mnist = MnistBuilder::new()
     .label_format_digit()
     .test_set_length(TEST_SIZE as u32)
     .finalize();
-  let _tst_lbl = mnist.tst_lbl.clone();
-  // Load the neural network model
-  let model = match SerializableModel::load_from_safetensors(model_path) {
-    Ok(serialized_model) => {
-      let model = InferrableModel::from_serializable_model(&serialized_model);
-      println!("Successfully loaded model from: {}", model_path);
-      println!(
-        "Model dimensions: w1={:?}, b1={:?}, w2={:?}, b2={:?}",
-        model.w1.dim(),
-        model.b1.dim(),
-        model.w2.dim(),
-        model.b2.dim()
-      );
-      model
-    }
-    Err(e) => {
-      panic!("Failed to load model from {}: {}", model_path, e);
-    }
-  };
-
   let tst_img = Array2::from_shape_vec(
     (TEST_SIZE, 28 * 28),
     mnist
@@ -43,37 +35,138 @@ pub fn validate(model_path: &str) {
       .collect(),
   )
   .unwrap();
-  let pb = ProgressBar::new(TEST_SIZE as u64);
+
+  let model = load_model(model_path);
+  let probabilities = predict(&model, &tst_img, &mnist.tst_lbl);
+  let report = evaluate(&probabilities, &mnist.tst_lbl, &model, model_path);
+  println!("{}", report);
+  println!("Validation accuracy: {:.2}%", report.accuracy * 100.0);
+
+  if let Some(baseline_path) = baseline_path {
+    let baseline = load_model(baseline_path);
+    let baseline_probabilities = predict(&baseline, &tst_img, &mnist.tst_lbl);
+    let baseline_report = evaluate(
+      &baseline_probabilities,
+      &mnist.tst_lbl,
+      &baseline,
+      baseline_path,
+    );
+    println!(
+      "Baseline accuracy: {:.2}%",
+      baseline_report.accuracy * 100.0
+    );
+    println!(
+      "Accuracy delta: {:+.2} percentage points",
+      (report.accuracy - baseline_report.accuracy) * 100.0
+    );
+  }
+
+  if let Some(report_path) = report_path {
+    match report.write(report_path) {
+      Ok(()) => println!("Report written to {}", report_path),
+      Err(e) => {
+        eprintln!("Failed to write report to {}: {}", report_path, e);
+        std::process::exit(1);
+      }
+    }
+  }
+
+  if let Some(errors_dir) = errors_dir {
+    let result = dump_errors(
+      errors_dir,
+      &tst_img,
+      &mnist.tst_lbl,
+      &probabilities,
+      &model.class_labels,
+      contact_sheet_size,
+    );
+    match result {
+      Ok(wrong) => {
+        println!(
+          "Wrote {} misclassified images and {} to {}",
+          wrong.len(),
+          CONTACT_SHEET_NAME,
+          errors_dir
+        );
+        println!("Most confidently wrong:");
+        for wrong in wrong.iter().take(10) {
+          println!("  {}", file_name(wrong, &model.class_labels));
+        }
+      }
+      Err(e) => {
+        eprintln!(
+          "Failed to write misclassified images to {}: {}",
+          errors_dir, e
+        );
+        std::process::exit(1);
+      }
+    }
+  }
+}
+
+fn load_model(model_path: &str) -> InferrableModel {
+  // Load the neural network model
+  match InferrableModel::load(model_path) {
+    Ok(model) => {
+      println!("Successfully loaded model from: {}", model_path);
+      if !model.conv_layers.is_empty() {
+        let conv: Vec<String> = model.conv_layers.iter().map(ToString::to_string).collect();
+        println!("Convolutional layers: {}", conv.join(", "));
+      }
+      println!("Model layers: {:?}", model.layer_sizes());
+      if model.is_quantized() {
+        println!("Weights: int8 (integer forward pass)");
+      }
+      model
+    }
+    Err(e) => {
+      eprintln!("Failed to load model from {}: {}", model_path, e);
+      std::process::exit(1);
+    }
+  }
+}
+
+fn evaluate(
+  probabilities: &Array2<f32>,
+  labels: &[u8],
+  model: &InferrableModel,
+  model_path: &str,
+) -> EvaluationReport {
+  match EvaluationReport::new(probabilities, labels, &model.class_labels) {
+    Ok(report) => report,
+    Err(e) => {
+      eprintln!("Cannot evaluate {} on the test set: {}", model_path, e);
+      std::process::exit(1);
+    }
+  }
+}
+
+/// Run the model on `images` (one per row), returning the class probabilities for each
+/// (classes x images). `labels` are only used to show progress.
+fn predict(model: &InferrableModel, images: &Array2<f32>, labels: &[u8]) -> Array2<f32> {
+  let pb = ProgressBar::new(images.nrows() as u64);
   pb.set_style(
     ProgressStyle::with_template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}")
       .unwrap()
       .progress_chars("##-"),
   );
+  let mut probabilities = Array2::zeros((model.class_labels.len(), images.nrows()));
   let mut total_correct: i32 = 0;
-  for (i, (image, &y)) in tst_img.outer_iter().zip(_tst_lbl.iter()).enumerate() {
+  for (i, (image, &y)) in images.outer_iter().zip(labels.iter()).enumerate() {
     // 784x1
     let image = image.insert_axis(Axis(1));
 
-    // multiply hidden layers
-    let z1 = model.w1.dot(&image) + &model.b1;
-    let a1 = sigmoid(&z1);
-    let z2 = model.w2.dot(&a1) + &model.b2;
-    let a2 = softmax(&z2);
+    let a2 = model.forward(&model.preprocessor.apply(&image.to_owned()), Mode::Eval);
 
-    let max_probability = a2.iter().fold(f32::NEG_INFINITY, |a, &b| a.max(b));
-
-    let correct_probability = a2[[y as usize, 0]];
-    let is_correct = correct_probability == max_probability;
-    if is_correct {
+    // labels the model has no output for count as wrong, `evaluate` reports them
+    if argmax(a2.column(0)) == y as usize {
       total_correct += 1;
     }
+    probabilities.column_mut(i).assign(&a2.column(0));
     pb.inc(1);
     pb.set_message(format!("Correct: {} / {}", total_correct, i + 1));
   }
   pb.finish();
 
-  println!(
-    "Validation accuracy: {:.2}%",
-    (total_correct as f32 / TEST_SIZE as f32) * 100.0
-  );
+  probabilities
 }
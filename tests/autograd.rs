@@ -0,0 +1,141 @@
+mod common;
+
+use common::{every_element, gradient_check, targets};
+use ndarray::{Array2, Axis};
+use neural_net::activation::Activation;
+use neural_net::autograd::{Tape, Var};
+
+fn values(dim: (usize, usize), seed: usize) -> Array2<f32> {
+  Array2::from_shape_fn(dim, |(i, j)| {
+    ((i * 7 + j * 5 + seed * 3) % 11) as f32 / 5.0 - 1.05
+  })
+}
+
+/// Compare the gradients `backward` finds for `parameters` against central differences of
+/// the loss `build` records from them.
+fn check_gradients(parameters: &[Array2<f32>], build: impl Fn(&Tape, &[Var]) -> Var) {
+  let record = |parameters: &[Array2<f32>]| {
+    let tape = Tape::new();
+    let vars: Vec<Var> = parameters
+      .iter()
+      .map(|p| tape.parameter(p.clone()))
+      .collect();
+    let loss = build(&tape, &vars);
+    (tape, vars, loss)
+  };
+
+  let (tape, vars, loss) = record(parameters);
+  let grads = tape.backward(loss);
+  let gradients: Vec<Array2<f32>> = vars
+    .iter()
+    .map(|&var| grads.get(var).unwrap().clone())
+    .collect();
+  gradient_check(
+    &mut parameters.to_vec(),
+    |parameters| parameters.iter_mut().collect(),
+    &gradients,
+    &every_element(&gradients),
+    |parameters| {
+      let (tape, _, loss) = record(parameters);
+      tape.value(loss)[[0, 0]]
+    },
+  );
+}
+
+#[test]
+fn dense_layer_and_cross_entropy_match_finite_differences() {
+  // w (3x4) . x (4x2) + b (3x1), softmax, cross-entropy
+  let parameters = [values((3, 4), 0), values((4, 2), 1), values((3, 1), 2)];
+  check_gradients(&parameters, |tape, vars| {
+    let z = tape.add(tape.matmul(vars[0], vars[1]), vars[2]);
+    tape.cross_entropy(tape.log_softmax(z), &targets(3, 2))
+  });
+}
+
+#[test]
+fn elementwise_operations_match_finite_differences() {
+  // a (2x3) broadcast against a row (1x3) and a column (2x1)
+  let parameters = [values((2, 3), 0), values((1, 3), 1), values((2, 1), 2)];
+  check_gradients(&parameters, |tape, vars| {
+    let product = tape.mul(tape.sub(vars[0], vars[1]), vars[2]);
+    let sum = tape.sum(tape.scale(product, 0.5));
+    tape.add(sum, tape.mean(tape.mul(vars[0], vars[0])))
+  });
+}
+
+#[test]
+fn means_along_an_axis_and_powers_match_finite_differences() {
+  // a normalization's statistics: x centered on its row and column means, over
+  // sqrt(x^2 + 1)
+  let parameters = [values((3, 4), 0)];
+  check_gradients(&parameters, |tape, vars| {
+    let rows = tape.mean_axis(vars[0], Axis(1));
+    let columns = tape.mean_axis(vars[0], Axis(0));
+    let centered = tape.sub(tape.sub(vars[0], rows), columns);
+    let one = tape.constant(Array2::ones((1, 1)));
+    let inv_std = tape.powf(tape.add(tape.mul(centered, centered), one), -0.5);
+    tape.sum(tape.mul(
+      tape.mul(centered, inv_std),
+      tape.constant(values((3, 4), 4)),
+    ))
+  });
+}
+
+#[test]
+fn activations_match_finite_differences() {
+  for activation in [
+    Activation::Sigmoid,
+    Activation::Relu,
+    Activation::LeakyRelu,
+    Activation::Tanh,
+    Activation::Gelu,
+    Activation::Elu,
+    Activation::Silu,
+    Activation::Identity,
+  ] {
+    let weights = Array2::from_shape_fn((2, 3), |(i, j)| (i * 3 + j) as f32 / 4.0 - 0.7);
+    check_gradients(&[weights], |tape, vars| {
+      let a = tape.activation(vars[0], activation);
+      tape.sum(tape.mul(a, tape.constant(values((2, 3), 4))))
+    });
+  }
+}
+
+#[test]
+fn gradients_flow_through_every_use_of_a_value() {
+  // d/dx sum(x * x + 3x) = 2x + 3, exact for quarters
+  let tape = Tape::new();
+  let x = tape.parameter(Array2::from_shape_fn((2, 2), |(i, j)| {
+    (i * 2 + j) as f32 / 4.0 - 0.5
+  }));
+  let loss = tape.sum(tape.add(tape.mul(x, x), tape.scale(x, 3.0)));
+  let grads = tape.backward(loss);
+  assert_eq!(grads.get(x).unwrap(), &(&*tape.value(x) * 2.0 + 3.0));
+}
+
+#[test]
+fn constants_and_intermediate_values_get_no_gradient() {
+  let tape = Tape::new();
+  let x = tape.constant(values((2, 2), 0));
+  let w = tape.parameter(values((2, 2), 1));
+  let y = tape.matmul(w, x);
+  let grads = tape.backward(tape.mean(y));
+  assert!(grads.get(x).is_none());
+  assert!(grads.get(y).is_none());
+  assert!(grads.get(w).is_some());
+}
+
+#[test]
+fn custom_operations_supply_their_own_gradients() {
+  // y = 2x as an opaque operation
+  let tape = Tape::new();
+  let x = tape.parameter(values((2, 2), 0));
+  let doubled = &*tape.value(x) * 2.0;
+  let y = tape.custom(
+    &[x],
+    doubled,
+    Box::new(|grad, needed| vec![needed[0].then(|| grad * 2.0)]),
+  );
+  let grads = tape.backward(tape.sum(y));
+  assert_eq!(grads.get(x).unwrap(), &Array2::from_elem((2, 2), 2.0));
+}
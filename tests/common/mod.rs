@@ -0,0 +1,127 @@
+//! Fixtures shared by the integration tests. Each test file uses its own subset, so the
+//! rest is dead code from its point of view.
+#![allow(dead_code)]
+
+use ndarray::Array2;
+use neural_net::activation::Activation;
+use neural_net::inferrable_model::{InferrableModel, Mode};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use safetensors::SafeTensors;
+use std::path::{Path, PathBuf};
+
+/// A dense model with layer `sizes` and hidden `activations`, initialized from a ChaCha
+/// RNG seeded with `seed`.
+pub fn model(sizes: &[usize], activations: &[Activation], seed: u64) -> InferrableModel {
+  let mut rng = ChaCha8Rng::seed_from_u64(seed);
+  InferrableModel::new(sizes, activations, &mut rng)
+}
+
+/// A `rows x cols` input whose values vary over every row and column within [-1, 1].
+pub fn input(rows: usize, cols: usize) -> Array2<f32> {
+  Array2::from_shape_fn((rows, cols), |(i, j)| {
+    ((i * 7 + j * 3) % 11) as f32 / 5.0 - 1.0
+  })
+}
+
+/// Raw pixel rows with some variation in every pixel.
+pub fn images(samples: usize) -> Array2<f32> {
+  Array2::from_shape_fn((samples, 28 * 28), |(i, pixel)| {
+    ((i * 37 + pixel * 11) % 256) as f32
+  })
+}
+
+/// Eval-mode output (classes x samples) of `model` on raw `images`, one per row.
+pub fn predictions(model: &InferrableModel, images: &Array2<f32>) -> Array2<f32> {
+  model.forward(
+    &model.preprocessor.apply(&images.t().to_owned()),
+    Mode::Eval,
+  )
+}
+
+/// Largest absolute elementwise difference between `a` and `b`.
+pub fn max_difference(a: &Array2<f32>, b: &Array2<f32>) -> f32 {
+  (a - b).mapv(f32::abs).fold(0.0, |m, &d| m.max(d))
+}
+
+/// Step the central differences in `gradient_check` take on either side of a value.
+const EPS: f32 = 1e-2;
+
+/// One-hot targets (classes x samples), sample `i` belonging to class `i % classes`.
+pub fn targets(classes: usize, samples: usize) -> Array2<f32> {
+  Array2::from_shape_fn((classes, samples), |(class, sample)| {
+    if class == sample % classes { 1.0 } else { 0.0 }
+  })
+}
+
+/// Mean cross-entropy of `probabilities` (classes x samples) against `targets`.
+pub fn cross_entropy(probabilities: &Array2<f32>, targets: &Array2<f32>) -> f32 {
+  -(probabilities.mapv(f32::ln) * targets).sum() / targets.ncols() as f32
+}
+
+/// Every `(parameter, index)` of `gradients`, for checking all of them.
+pub fn every_element(gradients: &[Array2<f32>]) -> Vec<(usize, [usize; 2])> {
+  gradients
+    .iter()
+    .enumerate()
+    .flat_map(|(p, gradient)| {
+      gradient
+        .indexed_iter()
+        .map(move |((row, col), _)| (p, [row, col]))
+    })
+    .collect()
+}
+
+/// Compare `gradients`, one per entry of `parameters(subject)`, against central
+/// differences of `loss(subject)` at each `(parameter, index)` of `points`.
+pub fn gradient_check<M>(
+  subject: &mut M,
+  parameters: impl Fn(&mut M) -> Vec<&mut Array2<f32>>,
+  gradients: &[Array2<f32>],
+  points: &[(usize, [usize; 2])],
+  loss: impl Fn(&M) -> f32,
+) {
+  let shapes: Vec<_> = parameters(subject).iter().map(|p| p.dim()).collect();
+  let gradient_shapes: Vec<_> = gradients.iter().map(Array2::dim).collect();
+  assert_eq!(gradient_shapes, shapes, "one gradient per parameter");
+
+  for &(p, index) in points {
+    let original = parameters(subject)[p][index];
+    parameters(subject)[p][index] = original + EPS;
+    let plus = loss(subject);
+    parameters(subject)[p][index] = original - EPS;
+    let minus = loss(subject);
+    parameters(subject)[p][index] = original;
+
+    let numeric = (plus - minus) / (2.0 * EPS);
+    let analytic = gradients[p][index];
+    assert!(
+      (numeric - analytic).abs() < 1e-3 + 1e-2 * analytic.abs(),
+      "parameter {} {:?}: backprop {} vs numeric {}",
+      p,
+      index,
+      analytic,
+      numeric
+    );
+  }
+}
+
+/// A path in the temp directory for a test to save a model to, unique to `name` and this
+/// test run. The caller removes the file.
+pub fn temp_model_path(name: &str) -> PathBuf {
+  std::env::temp_dir().join(format!(
+    "neural-net-{}-{}.safetensors",
+    name,
+    std::process::id()
+  ))
+}
+
+/// The `format_version` of the architecture a model file at `path` declares. Only the
+/// header is read, so this works whatever the tensors' dtypes.
+pub fn saved_format_version(path: &Path) -> u64 {
+  let buffer = std::fs::read(path).unwrap();
+  let (_, header) = SafeTensors::read_metadata(&buffer).unwrap();
+  let metadata = header.metadata().clone().unwrap();
+  let architecture: serde_json::Value = serde_json::from_str(&metadata["architecture"]).unwrap();
+  architecture["format_version"].as_u64().unwrap()
+}
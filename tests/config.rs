@@ -0,0 +1,95 @@
+use neural_net::config::TrainingConfig;
+use neural_net::early_stopping::StopMetric;
+
+/// Check that the default config with `change` applied fails validation with `error`.
+fn assert_rejected(change: impl FnOnce(&mut TrainingConfig), error: &str) {
+  let mut config = TrainingConfig::default();
+  change(&mut config);
+  assert_eq!(config.validate(), Err(error.into()));
+}
+
+#[test]
+fn hyperparameters_out_of_range_are_rejected() {
+  assert_eq!(TrainingConfig::default().validate(), Ok(()));
+
+  let lr = "learning rate must be greater than 0";
+  assert_rejected(|c| c.optimizer.lr = -0.1, lr);
+  assert_rejected(|c| c.optimizer.lr = f32::NAN, lr);
+  assert_rejected(
+    |c| c.optimizer.momentum = 1.0,
+    "momentum must be at least 0 and less than 1",
+  );
+  assert_rejected(
+    |c| c.schedule.gamma = f32::NAN,
+    "learning rate gamma must be greater than 0",
+  );
+  assert_rejected(
+    |c| c.schedule.min_lr = -1.0,
+    "minimum learning rate must not be negative",
+  );
+  assert_rejected(
+    |c| c.schedule.step_size = 0,
+    "learning rate step size must be at least 1",
+  );
+  let cycle = "cosine cycle length must be greater than 0";
+  assert_rejected(|c| c.schedule.cycle_epochs = 0.0, cycle);
+  assert_rejected(|c| c.schedule.cycle_epochs = -2.0, cycle);
+  assert_rejected(|c| c.schedule.cycle_epochs = f32::INFINITY, cycle);
+  let mult = "cosine cycle multiplier must be at least 1";
+  assert_rejected(|c| c.schedule.cycle_mult = 0.5, mult);
+  assert_rejected(|c| c.schedule.cycle_mult = f32::NAN, mult);
+  assert_rejected(
+    |c| c.early_stopping.patience = Some(0),
+    "early stopping patience must be at least 1",
+  );
+  let min_delta = "early stopping minimum delta must not be negative";
+  assert_rejected(|c| c.early_stopping.min_delta = -0.01, min_delta);
+  assert_rejected(|c| c.early_stopping.min_delta = f32::NAN, min_delta);
+  assert_rejected(
+    |c| c.rolling_mean_size = 0,
+    "rolling mean size must be at least 1",
+  );
+}
+
+#[test]
+fn resuming_keeps_the_shape_and_data_order() {
+  let checkpoint = TrainingConfig {
+    seed: Some(7),
+    ..Default::default()
+  };
+  let mut resumed = checkpoint.clone();
+  resumed.epochs = 30;
+  resumed.optimizer.lr = 0.01;
+  assert_eq!(resumed.check_resumable(&checkpoint), Ok(()));
+
+  resumed.batch_size = 32;
+  assert_eq!(
+    resumed.check_resumable(&checkpoint),
+    Err("cannot change batch_size when resuming (checkpoint has 1, got 32)".into())
+  );
+  resumed.batch_size = 1;
+  resumed.layers = vec![784, 64, 10];
+  assert!(resumed.check_resumable(&checkpoint).is_err());
+  resumed.layers = checkpoint.layers.clone();
+  resumed.dropout = vec![0.5];
+  assert_eq!(
+    resumed.check_resumable(&checkpoint),
+    Err("cannot change dropout when resuming (checkpoint has [0.0], got [0.5])".into())
+  );
+}
+
+#[test]
+fn resuming_can_change_patience_but_not_the_monitored_metric() {
+  let mut checkpoint = TrainingConfig::default();
+  checkpoint.early_stopping.patience = Some(3);
+  let mut resumed = checkpoint.clone();
+  resumed.early_stopping.patience = Some(5);
+  resumed.early_stopping.min_delta = 0.01;
+  assert_eq!(resumed.check_resumable(&checkpoint), Ok(()));
+
+  resumed.early_stopping.metric = StopMetric::TrainAccuracy;
+  assert!(resumed.check_resumable(&checkpoint).is_err());
+  // without early stopping in the checkpoint there is no best score to compare against
+  checkpoint.early_stopping.patience = None;
+  assert_eq!(resumed.check_resumable(&checkpoint), Ok(()));
+}
@@ -0,0 +1,243 @@
+mod common;
+
+use common::{cross_entropy, gradient_check, saved_format_version, targets, temp_model_path};
+use ndarray::{Array2, Array4};
+use neural_net::activation::Activation;
+use neural_net::config::{Arch, TrainingConfig};
+use neural_net::conv::{Conv2d, ConvLayer, ConvLayerConfig, Pool2d, PoolKind};
+use neural_net::inferrable_model::{InferrableModel, Mode};
+use neural_net::serialization::TensorDtype;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+fn feature_maps(dim: (usize, usize, usize, usize)) -> Array4<f32> {
+  Array4::from_shape_fn(dim, |(b, c, y, x)| {
+    ((b * 7 + c * 5 + y * 3 + x * 11) % 13) as f32 / 6.0 - 1.0
+  })
+}
+
+/// A small model on 28x28 images: a 5x5 stride 2 convolution to 3 channels (13x13), 2x2
+/// max pooling (6x6), a 3x3 convolution to 2 channels (4x4) and 2x2 average pooling
+/// (2x2), then dense layers.
+fn model() -> InferrableModel {
+  let mut model = common::model(&[2 * 2 * 2, 5, 3], &[Activation::Tanh], 4);
+  model.add_conv_layers(
+    &[
+      ConvLayerConfig::Conv2d {
+        channels: 3,
+        kernel_size: 5,
+        stride: 2,
+        padding: 1,
+        activation: Activation::Tanh,
+      },
+      ConvLayerConfig::MaxPool2d {
+        size: 2,
+        stride: None,
+      },
+      ConvLayerConfig::Conv2d {
+        channels: 2,
+        kernel_size: 3,
+        stride: 1,
+        padding: 0,
+        activation: Activation::Sigmoid,
+      },
+      ConvLayerConfig::AvgPool2d {
+        size: 2,
+        stride: None,
+      },
+    ],
+    &mut ChaCha8Rng::seed_from_u64(4),
+  );
+  model.class_labels = vec!["a".into(), "b".into(), "c".into()];
+  model
+}
+
+/// Four 28x28 images, one per column.
+fn input() -> Array2<f32> {
+  common::input(28 * 28, 4)
+}
+
+fn loss(model: &InferrableModel) -> f32 {
+  cross_entropy(&model.forward(&input(), Mode::Eval), &targets(3, 4))
+}
+
+#[test]
+fn convolution_matches_direct_computation() {
+  let mut rng = ChaCha8Rng::seed_from_u64(1);
+  let conv = Conv2d::new(2, 3, 3, 2, 1, Activation::Identity, &mut rng);
+  let mut layer = ConvLayer::Conv2d(conv);
+  if let ConvLayer::Conv2d(conv) = &mut layer {
+    conv.b = Array2::from_shape_fn((3, 1), |(c, _)| c as f32 * 0.1);
+  }
+  let conv = layer.as_conv2d().unwrap();
+  let x = feature_maps((2, 2, 7, 6));
+  let y = layer.apply(&x);
+  assert_eq!(y.dim(), (2, 3, 4, 3));
+
+  for ((b, out, oy, ox), &value) in y.indexed_iter() {
+    let mut expected = conv.b[[out, 0]];
+    for c in 0..2 {
+      for ky in 0..3 {
+        for kx in 0..3 {
+          let (iy, ix) = ((oy * 2 + ky) as isize - 1, (ox * 2 + kx) as isize - 1);
+          if (0..7).contains(&iy) && (0..6).contains(&ix) {
+            expected += conv.w[[out, (c * 3 + ky) * 3 + kx]] * x[[b, c, iy as usize, ix as usize]];
+          }
+        }
+      }
+    }
+    assert!(
+      (value - expected).abs() < 1e-5,
+      "output [{}, {}, {}, {}]: {} vs {}",
+      b,
+      out,
+      oy,
+      ox,
+      value,
+      expected
+    );
+  }
+}
+
+#[test]
+fn pooling_takes_max_or_mean_of_each_window() {
+  let x = Array4::from_shape_fn((1, 1, 4, 5), |(_, _, y, x)| (y * 5 + x) as f32);
+  let pool = |kind| Pool2d {
+    kind,
+    size: 2,
+    stride: 2,
+  };
+
+  // the last column doesn't fill a window and is left out
+  let max = ConvLayer::Pool2d(pool(PoolKind::Max)).apply(&x);
+  assert_eq!(max.dim(), (1, 1, 2, 2));
+  assert_eq!(
+    max.iter().cloned().collect::<Vec<_>>(),
+    vec![6.0, 8.0, 16.0, 18.0]
+  );
+  let avg = ConvLayer::Pool2d(pool(PoolKind::Avg)).apply(&x);
+  assert_eq!(
+    avg.iter().cloned().collect::<Vec<_>>(),
+    vec![3.0, 5.0, 13.0, 15.0]
+  );
+
+  // max pooling sends each gradient to the largest value, average pooling spreads it
+  let dy = Array4::ones((1, 1, 2, 2));
+  let layer = pool(PoolKind::Max);
+  let (_, cache) = layer.forward(&x);
+  let dx = layer.backward(&dy, &cache);
+  assert_eq!(dx.sum(), 4.0);
+  assert_eq!(dx[[0, 0, 1, 1]], 1.0);
+  assert_eq!(dx[[0, 0, 0, 0]], 0.0);
+  let layer = pool(PoolKind::Avg);
+  let (_, cache) = layer.forward(&x);
+  let dx = layer.backward(&dy, &cache);
+  assert_eq!(dx[[0, 0, 0, 0]], 0.25);
+  assert_eq!(dx[[0, 0, 0, 4]], 0.0);
+}
+
+#[test]
+fn gradients_match_finite_differences() {
+  let mut model = model();
+  let pass = model.forward_pass(&input(), Mode::Eval);
+  let gradients: Vec<Array2<f32>> = model
+    .backward(&pass, &targets(3, 4))
+    .tensors()
+    .into_iter()
+    .cloned()
+    .collect();
+  assert_eq!(
+    gradients.len(),
+    8,
+    "w and b for two convolutions and two dense layers"
+  );
+
+  let points: Vec<(usize, [usize; 2])> = (0..gradients.len())
+    .flat_map(|p| [(p, [0, 0]), (p, [1, 0])])
+    .collect();
+  gradient_check(
+    &mut model,
+    InferrableModel::parameters_mut,
+    &gradients,
+    &points,
+    loss,
+  );
+}
+
+#[test]
+fn lenet_config_fits_mnist() {
+  let mut config = TrainingConfig::default();
+  Arch::Lenet.apply(&mut config);
+  assert_eq!(config.input_size(), 400);
+  assert_eq!(config.validate(), Ok(()));
+
+  config.conv_layers.push(ConvLayerConfig::Conv2d {
+    channels: 8,
+    kernel_size: 7,
+    stride: 1,
+    padding: 0,
+    activation: Activation::Relu,
+  });
+  assert_eq!(
+    config.validate(),
+    Err("convolutional layer 5 does not fit: 7x7 windows over 5x5 inputs".into())
+  );
+}
+
+#[test]
+fn empty_conv_layers_are_rejected() {
+  let conv = |channels, kernel_size, stride| ConvLayerConfig::Conv2d {
+    channels,
+    kernel_size,
+    stride,
+    padding: 0,
+    activation: Activation::Relu,
+  };
+  for (layer, error) in [
+    (conv(0, 3, 1), "needs at least 1 output channel"),
+    (
+      conv(4, 0, 1),
+      "needs a window size and stride of at least 1",
+    ),
+    (
+      conv(4, 3, 0),
+      "needs a window size and stride of at least 1",
+    ),
+  ] {
+    let config = TrainingConfig {
+      conv_layers: vec![layer],
+      ..Default::default()
+    };
+    assert_eq!(
+      config.validate(),
+      Err(format!("convolutional layer 1 {}", error))
+    );
+  }
+}
+
+#[test]
+fn conv_layers_survive_saving() {
+  let model = model();
+  let path = temp_model_path("conv");
+  model
+    .to_serializable_model()
+    .save_to_safetensors(&path, TensorDtype::F32)
+    .unwrap();
+  let version = saved_format_version(&path);
+  let loaded = InferrableModel::load(&path).unwrap();
+  std::fs::remove_file(&path).unwrap();
+
+  assert_eq!(version, 3, "convolutional layers need format version 3");
+  assert_eq!(loaded.conv_layers.len(), 4);
+  for (layer, original) in loaded.conv_layers.iter().zip(&model.conv_layers) {
+    assert_eq!(layer.to_string(), original.to_string());
+    if let (Some(conv), Some(original)) = (layer.as_conv2d(), original.as_conv2d()) {
+      assert_eq!(conv.w, original.w);
+      assert_eq!(conv.b, original.b);
+    }
+  }
+  assert_eq!(
+    loaded.forward(&input(), Mode::Eval),
+    model.forward(&input(), Mode::Eval)
+  );
+}
@@ -0,0 +1,104 @@
+mod common;
+
+use common::{cross_entropy, gradient_check, targets};
+use ndarray::Array2;
+use neural_net::activation::Activation;
+use neural_net::inferrable_model::{InferrableModel, Mode};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+fn model() -> InferrableModel {
+  common::model(&[6, 40, 5, 3], &[Activation::Sigmoid, Activation::Tanh], 11)
+}
+
+fn input() -> Array2<f32> {
+  common::input(6, 4)
+}
+
+/// Mean cross-entropy with the dropout masks drawn from a fresh RNG seeded with `seed`,
+/// so repeated calls drop the same neurons.
+fn loss(model: &InferrableModel, dropout: &[f32], seed: u64) -> f32 {
+  let mut rng = ChaCha8Rng::seed_from_u64(seed);
+  let pass = model.forward_pass(
+    &input(),
+    Mode::Train {
+      dropout,
+      rng: &mut rng,
+    },
+  );
+  cross_entropy(pass.output(), &targets(3, 4))
+}
+
+#[test]
+fn train_mode_drops_and_rescales_hidden_outputs() {
+  let model = model();
+  let mut rng = ChaCha8Rng::seed_from_u64(1);
+  let pass = model.forward_pass(
+    &input(),
+    Mode::Train {
+      dropout: &[0.5, 0.0],
+      rng: &mut rng,
+    },
+  );
+  let eval = model.forward_pass(&input(), Mode::Eval);
+
+  let mask = pass.mask(0).expect("first hidden layer has dropout");
+  assert!(mask.iter().all(|&m| m == 0.0 || m == 2.0));
+  let dropped = mask.iter().filter(|&&m| m == 0.0).count();
+  assert!(
+    (40..120).contains(&dropped),
+    "{} of 160 dropped at rate 0.5",
+    dropped
+  );
+  assert_eq!(*pass.input(1), &*eval.input(1) * &*mask);
+  assert!(pass.mask(1).is_none(), "rate 0 drops nothing");
+  assert!(pass.mask(2).is_none(), "the output layer is never dropped");
+}
+
+#[test]
+fn eval_mode_keeps_every_output() {
+  let model = model();
+  let eval = model.forward(&input(), Mode::Eval);
+  assert_eq!(eval, model.forward(&input(), Mode::Eval));
+
+  let mut rng = ChaCha8Rng::seed_from_u64(1);
+  let without_dropout = model.forward(
+    &input(),
+    Mode::Train {
+      dropout: &[0.0, 0.0],
+      rng: &mut rng,
+    },
+  );
+  assert_eq!(eval, without_dropout);
+}
+
+#[test]
+fn gradients_match_finite_differences_through_dropout() {
+  let mut model = model();
+  let dropout = [0.3, 0.5];
+  let seed = 5;
+
+  let mut rng = ChaCha8Rng::seed_from_u64(seed);
+  let pass = model.forward_pass(
+    &input(),
+    Mode::Train {
+      dropout: &dropout,
+      rng: &mut rng,
+    },
+  );
+  let gradients: Vec<Array2<f32>> = model
+    .backward(&pass, &targets(3, 4))
+    .tensors()
+    .into_iter()
+    .cloned()
+    .collect();
+
+  // w1, b1, w2, b2, w3, b3
+  gradient_check(
+    &mut model,
+    InferrableModel::parameters_mut,
+    &gradients,
+    &[(0, [3, 2]), (0, [17, 5]), (2, [2, 11]), (4, [1, 4])],
+    |model| loss(model, &dropout, seed),
+  );
+}
@@ -0,0 +1,29 @@
+use neural_net::early_stopping::{EarlyStopping, EarlyStoppingConfig, StopMetric};
+use neural_net::stats::TrainingStats;
+
+fn epoch(loss: f32) -> TrainingStats {
+  let mut stats = TrainingStats::new();
+  stats.update(loss, true);
+  stats
+}
+
+#[test]
+fn resumed_runs_use_the_new_patience_and_keep_the_best_score() {
+  let mut config = EarlyStoppingConfig {
+    metric: StopMetric::TrainLoss,
+    patience: Some(3),
+    min_delta: 0.0,
+  };
+  let mut early_stopping = EarlyStopping::new(&config);
+  assert!(early_stopping.end_epoch(1, &epoch(1.0), None));
+  assert!(!early_stopping.end_epoch(2, &epoch(1.2), None));
+  assert!(!early_stopping.should_stop());
+
+  config.patience = Some(1);
+  config.min_delta = 0.5;
+  early_stopping.reconfigure(&config);
+  assert!(early_stopping.should_stop());
+  // 0.7 beats the best of 1.0, but not by more than the new min delta
+  assert!(!early_stopping.end_epoch(3, &epoch(0.7), None));
+  assert_eq!(early_stopping.best_epoch(), 1);
+}
@@ -0,0 +1,120 @@
+use ndarray::Array2;
+use neural_net::evaluation::EvaluationReport;
+
+fn labels(n: usize) -> Vec<String> {
+  (0..n).map(|i| i.to_string()).collect()
+}
+
+/// Four samples over three classes, one per column:
+/// class 0 predicted 0 (0.7), class 0 predicted 1 (0.6),
+/// class 1 predicted 1 (0.9), class 2 predicted 1 (0.5, true class second).
+fn report() -> EvaluationReport {
+  #[rustfmt::skip]
+  let probabilities = Array2::from_shape_vec((3, 4), vec![
+    0.7, 0.3, 0.05, 0.1,
+    0.2, 0.6, 0.9,  0.5,
+    0.1, 0.1, 0.05, 0.4,
+  ]).unwrap();
+  EvaluationReport::new(&probabilities, &[0, 0, 1, 2], &labels(3)).unwrap()
+}
+
+fn close(actual: f32, expected: f32) {
+  assert!(
+    (actual - expected).abs() < 1e-5,
+    "expected {}, got {}",
+    expected,
+    actual
+  );
+}
+
+#[test]
+fn metrics_match_hand_computation() {
+  let report = report();
+  assert_eq!(report.samples, 4);
+  assert_eq!(
+    report.confusion_matrix,
+    vec![vec![1, 1, 0], vec![0, 1, 0], vec![0, 1, 0]]
+  );
+  close(report.accuracy, 0.5);
+
+  let class = |c: usize| &report.classes[c];
+  assert_eq!(class(0).support, 2);
+  assert_eq!(class(1).predicted, 3);
+  close(class(0).precision, 1.0);
+  close(class(0).recall, 0.5);
+  close(class(0).f1, 2.0 / 3.0);
+  close(class(1).precision, 1.0 / 3.0);
+  close(class(1).recall, 1.0);
+  close(class(1).f1, 0.5);
+  close(class(2).precision, 0.0);
+  close(class(2).f1, 0.0);
+
+  close(report.macro_average.precision, (1.0 + 1.0 / 3.0) / 3.0);
+  close(report.macro_average.recall, 0.5);
+  close(report.micro_average.f1, 0.5);
+
+  let accuracies: Vec<(usize, f32)> = report
+    .top_k
+    .iter()
+    .map(|top_k| (top_k.k, top_k.accuracy))
+    .collect();
+  assert_eq!(accuracies, vec![(1, 0.5), (3, 1.0)], "only k <= classes");
+
+  let loss = -(0.7f32.ln() + 0.3f32.ln() + 0.9f32.ln() + 0.4f32.ln()) / 4.0;
+  close(report.mean_loss, loss);
+
+  // every sample lands in its own bin, so the error is the mean |correct - confidence|
+  close(
+    report.expected_calibration_error,
+    ((1.0 - 0.7) + 0.6 + (1.0 - 0.9) + 0.5) / 4.0,
+  );
+  let binned: usize = report.calibration.iter().map(|bin| bin.samples).sum();
+  assert_eq!(binned, 4);
+}
+
+#[test]
+fn top_k_counts_true_class_rank() {
+  #[rustfmt::skip]
+  let probabilities = Array2::from_shape_vec((5, 2), vec![
+    0.1, 0.30,
+    0.2, 0.25,
+    0.3, 0.20,
+    0.4, 0.15,
+    0.0, 0.10,
+  ]).unwrap();
+  // first sample's true class ranks third, second sample's ranks fifth
+  let report = EvaluationReport::new(&probabilities, &[1, 4], &labels(5)).unwrap();
+  let accuracies: Vec<(usize, f32)> = report
+    .top_k
+    .iter()
+    .map(|top_k| (top_k.k, top_k.accuracy))
+    .collect();
+  assert_eq!(accuracies, vec![(1, 0.0), (3, 0.5), (5, 1.0)]);
+}
+
+#[test]
+fn csv_has_one_value_per_row() {
+  let csv = report().to_csv();
+  let mut lines = csv.lines();
+  assert_eq!(lines.next(), Some("metric,class,predicted,value"));
+  let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+  assert!(rows.iter().all(|row| row.len() == 4));
+  assert!(rows.contains(&vec!["accuracy", "", "", "0.5"]));
+  assert!(rows.contains(&vec!["recall", "0", "", "0.5"]));
+  assert!(rows.contains(&vec!["confusion", "0", "1", "1"]));
+  assert_eq!(rows.iter().filter(|row| row[0] == "confusion").count(), 9);
+}
+
+#[test]
+fn labels_without_an_output_are_rejected() {
+  // a model with three outputs evaluated on data that also has a class 5
+  let probabilities = Array2::from_elem((3, 2), 1.0 / 3.0);
+  assert_eq!(
+    EvaluationReport::new(&probabilities, &[1, 5], &labels(3)).err(),
+    Some("sample 1 is labelled 5, but the model only has 3 outputs".into())
+  );
+  assert_eq!(
+    EvaluationReport::new(&probabilities, &[1, 2], &labels(10)).err(),
+    Some("model has 3 outputs but 10 class labels".into())
+  );
+}
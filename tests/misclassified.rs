@@ -0,0 +1,67 @@
+use ndarray::Array2;
+use neural_net::misclassified::{
+  Misclassification, UPSCALE, contact_sheet, file_name, misclassifications,
+};
+
+#[test]
+fn most_confidently_wrong_come_first() {
+  #[rustfmt::skip]
+  let probabilities = Array2::from_shape_vec((3, 4), vec![
+    0.7, 0.2, 0.1, 0.05,
+    0.2, 0.7, 0.8, 0.05,
+    0.1, 0.1, 0.1, 0.9,
+  ]).unwrap();
+  let wrong = misclassifications(&probabilities, &[0, 2, 0, 1]);
+  let summary: Vec<(usize, usize, usize)> = wrong
+    .iter()
+    .map(|w| (w.index, w.label, w.predicted))
+    .collect();
+  assert_eq!(summary, vec![(3, 1, 2), (2, 0, 1), (1, 2, 1)]);
+  assert_eq!(wrong[0].confidence, 0.9);
+}
+
+#[test]
+fn file_names_describe_the_mistake() {
+  let wrong = Misclassification {
+    index: 42,
+    label: 7,
+    predicted: 1,
+    confidence: 0.98123,
+  };
+  let labels: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+  assert_eq!(file_name(&wrong, &labels), "00042_true-7_pred-1_0.9812.png");
+
+  // a model with only three outputs has no label for class 7
+  let labels = ["zero", "one", "two"].map(String::from);
+  assert_eq!(
+    file_name(&wrong, &labels),
+    "00042_true-7_pred-one_0.9812.png"
+  );
+}
+
+#[test]
+fn contact_sheet_tiles_digits_in_order() {
+  // image i is filled with 10 * (i + 1)
+  let images = Array2::from_shape_fn((12, 784), |(i, _)| 10.0 * (i + 1) as f32);
+  let wrong: Vec<Misclassification> = (0..12)
+    .rev()
+    .map(|index| Misclassification {
+      index,
+      label: 0,
+      predicted: 1,
+      confidence: 0.5,
+    })
+    .collect();
+  let sheet = contact_sheet(&images, &wrong);
+
+  let tile = 28 * UPSCALE;
+  // 10 columns, 2 rows, 2 pixel gaps
+  assert_eq!(
+    sheet.dimensions(),
+    (10 * (tile + 2) + 2, 2 * (tile + 2) + 2)
+  );
+  assert_eq!(sheet.get_pixel(2, 2).0, [120]);
+  assert_eq!(sheet.get_pixel(tile + 4, 2).0, [110]);
+  assert_eq!(sheet.get_pixel(2, tile + 4).0, [20]);
+  assert_eq!(sheet.get_pixel(tile + 4, tile + 4).0, [10]);
+}
@@ -0,0 +1,268 @@
+mod common;
+
+use common::{
+  cross_entropy, gradient_check, max_difference, saved_format_version, targets, temp_model_path,
+};
+use ndarray::Array2;
+use neural_net::activation::Activation;
+use neural_net::config::TrainingConfig;
+use neural_net::error::ModelError;
+use neural_net::inferrable_model::{InferrableModel, Mode};
+use neural_net::norm::NormKind;
+use neural_net::quantization::{Granularity, quantize_model};
+use neural_net::serialization::TensorDtype;
+use neural_net::training::save_trained_model;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+fn model(kind: NormKind) -> InferrableModel {
+  model_with_inputs(kind, 5)
+}
+
+fn model_with_inputs(kind: NormKind, inputs: usize) -> InferrableModel {
+  let mut model = common::model(
+    &[inputs, 7, 6, 3],
+    &[Activation::Sigmoid, Activation::Tanh],
+    9,
+  );
+  model.add_norm_layers(&[kind, kind]);
+  // move gamma and beta away from 1 and 0 so their gradients matter
+  for (i, layer) in model.layers.iter_mut().enumerate() {
+    if let Some(norm) = &mut layer.norm {
+      norm.gamma = Array2::from_shape_fn(norm.gamma.dim(), |(j, _)| 0.5 + 0.1 * (i + j) as f32);
+      norm.beta = Array2::from_shape_fn(norm.beta.dim(), |(j, _)| 0.05 * j as f32 - 0.1);
+    }
+  }
+  model
+}
+
+fn input() -> Array2<f32> {
+  input_with_features(5)
+}
+
+fn input_with_features(features: usize) -> Array2<f32> {
+  common::input(features, 8)
+}
+
+fn train_mode_loss(model: &InferrableModel) -> f32 {
+  let mut rng = ChaCha8Rng::seed_from_u64(0);
+  let pass = model.forward_pass(
+    &input(),
+    Mode::Train {
+      dropout: &[],
+      rng: &mut rng,
+    },
+  );
+  cross_entropy(pass.output(), &targets(3, 8))
+}
+
+#[test]
+fn gradients_match_finite_differences() {
+  for kind in [NormKind::BatchNorm, NormKind::LayerNorm] {
+    let mut model = model(kind);
+    let mut rng = ChaCha8Rng::seed_from_u64(0);
+    let pass = model.forward_pass(
+      &input(),
+      Mode::Train {
+        dropout: &[],
+        rng: &mut rng,
+      },
+    );
+    let gradients: Vec<Array2<f32>> = model
+      .backward(&pass, &targets(3, 8))
+      .tensors()
+      .into_iter()
+      .cloned()
+      .collect();
+
+    assert_eq!(
+      gradients.len(),
+      10,
+      "w, b, gamma, beta for two layers, w, b for one"
+    );
+    let points: Vec<(usize, [usize; 2])> = (0..gradients.len())
+      .flat_map(|p| [(p, [0, 0]), (p, [1, 0])])
+      .collect();
+    gradient_check(
+      &mut model,
+      InferrableModel::parameters_mut,
+      &gradients,
+      &points,
+      train_mode_loss,
+    );
+  }
+}
+
+#[test]
+fn batch_norm_tracks_running_statistics_for_eval() {
+  let mut model = model(NormKind::BatchNorm);
+  let mut rng = ChaCha8Rng::seed_from_u64(0);
+  let pass = model.forward_pass(
+    &input(),
+    Mode::Train {
+      dropout: &[],
+      rng: &mut rng,
+    },
+  );
+
+  // training normalizes each feature over the batch
+  let normalized = pass.normalized(0).unwrap();
+  for row in normalized.rows() {
+    assert!(row.mean().unwrap().abs() < 1e-5);
+    assert!((row.mapv(|v| v * v).mean().unwrap() - 1.0).abs() < 1e-3);
+  }
+
+  let z = model.layers[0].linear(&input());
+  model.update_running_stats(&pass);
+  let running = model.layers[0]
+    .norm
+    .as_ref()
+    .unwrap()
+    .running
+    .clone()
+    .unwrap();
+  for (feature, row) in z.rows().into_iter().enumerate() {
+    let mean = row.mean().unwrap();
+    let var = row.var(1.0);
+    assert!((running.mean[[feature, 0]] - 0.1 * mean).abs() < 1e-5);
+    assert!((running.var[[feature, 0]] - (0.9 + 0.1 * var)).abs() < 1e-5);
+  }
+
+  // eval mode normalizes with the running statistics instead
+  let eval = model.forward_pass(&input(), Mode::Eval);
+  let expected = (&z - &running.mean) / running.var.mapv(|v| (v + 1e-5).sqrt());
+  assert!(max_difference(&eval.normalized(0).unwrap(), &expected) < 1e-5);
+}
+
+#[test]
+fn layer_norm_normalizes_each_sample() {
+  let model = model(NormKind::LayerNorm);
+  let pass = model.forward_pass(&input(), Mode::Eval);
+  let normalized = pass.normalized(0).unwrap();
+  for column in normalized.columns() {
+    assert!(column.mean().unwrap().abs() < 1e-5);
+  }
+  assert!(model.layers[0].norm.as_ref().unwrap().running.is_none());
+}
+
+/// `model_with_inputs(kind, 784)` after one training step's update of its running
+/// statistics, ready to be saved.
+fn trained_for_saving(kind: NormKind) -> InferrableModel {
+  let mut model = model_with_inputs(kind, 784);
+  model.class_labels = vec!["a".into(), "b".into(), "c".into()];
+  let mut rng = ChaCha8Rng::seed_from_u64(0);
+  let pass = model.forward_pass(
+    &input_with_features(784),
+    Mode::Train {
+      dropout: &[],
+      rng: &mut rng,
+    },
+  );
+  model.update_running_stats(&pass);
+  model
+}
+
+/// Save `model` and load it back.
+fn reload(model: &InferrableModel, name: &str) -> Result<InferrableModel, ModelError> {
+  let path = temp_model_path(name);
+  model
+    .to_serializable_model()
+    .save_to_safetensors(&path, TensorDtype::F32)
+    .unwrap();
+  let loaded = InferrableModel::load(&path);
+  std::fs::remove_file(&path).unwrap();
+  loaded
+}
+
+#[test]
+fn norm_parameters_survive_saving() {
+  // model files describe 28x28 inputs
+  let input = input_with_features(784);
+  for kind in [NormKind::BatchNorm, NormKind::LayerNorm] {
+    let model = trained_for_saving(kind);
+    let loaded = reload(&model, &format!("norm-{:?}", kind)).unwrap();
+
+    for (layer, original) in loaded.layers.iter().zip(&model.layers) {
+      match (&layer.norm, &original.norm) {
+        (Some(norm), Some(original)) => {
+          assert_eq!(norm.kind, original.kind);
+          assert_eq!(norm.gamma, original.gamma);
+          assert_eq!(norm.beta, original.beta);
+          assert_eq!(
+            norm.running.as_ref().map(|r| (&r.mean, &r.var)),
+            original.running.as_ref().map(|r| (&r.mean, &r.var))
+          );
+        }
+        (None, None) => {}
+        _ => panic!("{:?}: normalization lost or invented on load", kind),
+      }
+    }
+    assert_eq!(
+      loaded.forward(&input, Mode::Eval),
+      model.forward(&input, Mode::Eval)
+    );
+  }
+}
+
+#[test]
+fn invalid_norm_tensors_are_rejected_on_load() {
+  let mut model = trained_for_saving(NormKind::BatchNorm);
+  let norm = model.layers[1].norm.as_mut().unwrap();
+  norm.running.as_mut().unwrap().var[[2, 0]] = -0.5;
+  assert!(matches!(
+    reload(&model, "norm-negative-var"),
+    Err(ModelError::NegativeVariance { name, count: 1 }) if name == "norm2.running_var"
+  ));
+
+  let mut model = trained_for_saving(NormKind::LayerNorm);
+  model.layers[0].norm.as_mut().unwrap().gamma[[1, 0]] = f32::NAN;
+  assert!(matches!(
+    reload(&model, "norm-nan-gamma"),
+    Err(ModelError::NonFinite { name, count: 1 }) if name == "norm1.gamma"
+  ));
+}
+
+#[test]
+fn diverged_norm_statistics_are_not_saved() {
+  let mut model = trained_for_saving(NormKind::BatchNorm);
+  model.layers[0]
+    .norm
+    .as_mut()
+    .unwrap()
+    .running
+    .as_mut()
+    .unwrap()
+    .var[[3, 0]] = f32::NAN;
+  let path = temp_model_path("norm-diverged");
+  let saved = save_trained_model(
+    path.to_str().unwrap(),
+    &model,
+    &TrainingConfig::default(),
+    TensorDtype::F32,
+  );
+  assert!(saved.unwrap_err().to_string().contains("non-finite"));
+  assert!(!path.exists(), "a model the loader rejects was written");
+}
+
+#[test]
+fn files_declare_the_oldest_format_that_describes_them() {
+  let save = |model: &InferrableModel, name: &str| {
+    let path = temp_model_path(name);
+    model
+      .to_serializable_model()
+      .save_to_safetensors(&path, TensorDtype::F32)
+      .unwrap();
+    let version = saved_format_version(&path);
+    std::fs::remove_file(&path).unwrap();
+    version
+  };
+  let mut model = trained_for_saving(NormKind::BatchNorm);
+  assert_eq!(save(&model, "norm-version"), 2);
+  let images = input_with_features(784).t().to_owned();
+  let quantized = quantize_model(&model, &images, Granularity::PerChannel);
+  assert_eq!(save(&quantized, "quantized-version"), 4);
+  for layer in &mut model.layers {
+    layer.norm = None;
+  }
+  assert_eq!(save(&model, "dense-version"), 1);
+}
@@ -0,0 +1,421 @@
+mod common;
+
+use common::{images, max_difference, predictions};
+use ndarray::{Array2, Array4, ArrayD, ArrayView3, Axis, Ix2, Ix4, s};
+use neural_net::activation::Activation;
+use neural_net::conv::ConvLayerConfig;
+use neural_net::inferrable_model::InferrableModel;
+use neural_net::norm::NormKind;
+use neural_net::onnx::export::to_onnx;
+use neural_net::onnx::proto::{
+  AttributeValue, DATA_TYPE_FLOAT, DATA_TYPE_INT64, Dimension, ModelProto, NodeProto,
+};
+use neural_net::onnx::{INPUT_NAME, OUTPUT_NAME};
+use neural_net::preprocessing::{Normalization, PreprocessingConfig, Preprocessor};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+
+fn model(normalization: Normalization, pca_components: Option<usize>) -> InferrableModel {
+  let mut rng = ChaCha8Rng::seed_from_u64(7);
+  let input_size = pca_components.unwrap_or(784);
+  let mut model = InferrableModel::new(
+    &[input_size, 12, 8, 10],
+    &[Activation::Relu, Activation::Gelu],
+    &mut rng,
+  );
+  let config = PreprocessingConfig {
+    normalization,
+    pca_components,
+  };
+  model.preprocessor = Preprocessor::fit(&config, &images(50));
+  model
+}
+
+fn round_trip(model: &InferrableModel) -> ModelProto {
+  let bytes = to_onnx(model).encode();
+  ModelProto::decode(&bytes).expect("exported model should decode")
+}
+
+/// The `Ints` attribute `name` of `node` as sizes.
+fn sizes(node: &NodeProto, name: &str) -> Vec<usize> {
+  match node.attribute(name) {
+    Some(AttributeValue::Ints(values)) => values.iter().map(|&v| v as usize).collect(),
+    other => panic!("{} has {} {:?}", node.name, name, other),
+  }
+}
+
+/// Slide `kernel` windows `stride` apart over the zero-`padding`ed images of `x` (batch x
+/// channels x height x width), reducing each window of each output channel with `window`,
+/// which gets the image, the output channel and the window's top left corner (which can
+/// lie in the padding).
+fn windows(
+  x: &ArrayD<f32>,
+  out_channels: usize,
+  kernel: usize,
+  stride: usize,
+  padding: usize,
+  window: impl Fn(ArrayView3<f32>, usize, isize, isize) -> f32,
+) -> ArrayD<f32> {
+  let x = x.view().into_dimensionality::<Ix4>().unwrap();
+  let (batch, _, height, width) = x.dim();
+  let out = |size: usize| (size + 2 * padding - kernel) / stride + 1;
+  Array4::from_shape_fn(
+    (batch, out_channels, out(height), out(width)),
+    |(b, c, y, x_)| {
+      let corner = |i: usize| (i * stride) as isize - padding as isize;
+      window(x.index_axis(Axis(0), b), c, corner(y), corner(x_))
+    },
+  )
+  .into_dyn()
+}
+
+/// Evaluate a decoded graph on `input` (one sample per row), supporting the operators
+/// the exporter emits. Element-wise operators broadcast the way ONNX does.
+fn evaluate(onnx: &ModelProto, input: &Array2<f32>) -> Array2<f32> {
+  let mut values: HashMap<String, ArrayD<f32>> = HashMap::new();
+  let mut int64s: HashMap<String, Vec<i64>> = HashMap::new();
+  for tensor in &onnx.graph.initializer {
+    if tensor.data_type == DATA_TYPE_INT64 {
+      int64s.insert(tensor.name.clone(), tensor.int64_data.clone());
+      continue;
+    }
+    let shape: Vec<usize> = tensor.dims.iter().map(|&d| d as usize).collect();
+    let arr = ArrayD::from_shape_vec(shape, tensor.float_data.clone()).unwrap();
+    values.insert(tensor.name.clone(), arr);
+  }
+  values.insert(INPUT_NAME.to_string(), input.clone().into_dyn());
+
+  let matrix = |x: &ArrayD<f32>| x.view().into_dimensionality::<Ix2>().unwrap().to_owned();
+  for node in &onnx.graph.node {
+    let x = |i: usize| &values[&node.input[i]];
+    let out = match node.op_type.as_str() {
+      "Mul" => x(0) * x(1),
+      "Add" => x(0) + x(1),
+      "Sub" => x(0) - x(1),
+      "Div" => x(0) / x(1),
+      "MatMul" => matrix(x(0)).dot(&matrix(x(1))).into_dyn(),
+      "Gemm" => matrix(x(0)).dot(&matrix(x(1)).t()).into_dyn() + x(2),
+      "Relu" => x(0).mapv(|v| v.max(0.0)),
+      "Sigmoid" => x(0).mapv(|v| 1.0 / (1.0 + (-v).exp())),
+      "Tanh" => x(0).mapv(f32::tanh),
+      "Sqrt" => x(0).mapv(f32::sqrt),
+      "ReduceMean" => {
+        assert_eq!(node.attribute("axes"), Some(&AttributeValue::Ints(vec![1])));
+        x(0).mean_axis(Axis(1)).unwrap().insert_axis(Axis(1))
+      }
+      "Softmax" => {
+        let exp = x(0).mapv(f32::exp);
+        let sums = exp.sum_axis(Axis(1)).insert_axis(Axis(1));
+        exp / sums
+      }
+      "Reshape" => {
+        let elements = x(0).len() as i64;
+        let shape = &int64s[&node.input[1]];
+        let known: i64 = shape.iter().filter(|&&d| d != -1).product();
+        let shape: Vec<usize> = shape
+          .iter()
+          .map(|&d| if d == -1 { elements / known } else { d } as usize)
+          .collect();
+        x(0).to_owned().into_shape_with_order(shape).unwrap()
+      }
+      "Flatten" => {
+        assert_eq!(node.attribute("axis"), Some(&AttributeValue::Int(1)));
+        let batch = x(0).shape()[0];
+        let features = x(0).len() / batch;
+        x(0)
+          .to_owned()
+          .into_shape_with_order(vec![batch, features])
+          .unwrap()
+      }
+      "Conv" => {
+        let (weight, bias) = (x(1), x(2));
+        let (kernel, stride, padding) = (
+          sizes(node, "kernel_shape")[0],
+          sizes(node, "strides")[0],
+          sizes(node, "pads")[0],
+        );
+        windows(
+          x(0),
+          weight.shape()[0],
+          kernel,
+          stride,
+          padding,
+          |image, out, top, left| {
+            let (channels, height, width) = image.dim();
+            let mut sum = bias[[out]];
+            for (c, ky, kx) in ndarray::indices((channels, kernel, kernel)) {
+              let (y, x) = (top + ky as isize, left + kx as isize);
+              // the padding is zeros
+              if (0..height as isize).contains(&y) && (0..width as isize).contains(&x) {
+                sum += weight[[out, c, ky, kx]] * image[[c, y as usize, x as usize]];
+              }
+            }
+            sum
+          },
+        )
+      }
+      "MaxPool" | "AveragePool" => {
+        let (kernel, stride) = (sizes(node, "kernel_shape")[0], sizes(node, "strides")[0]);
+        let channels = x(0).shape()[1];
+        let max = node.op_type == "MaxPool";
+        windows(x(0), channels, kernel, stride, 0, |image, c, top, left| {
+          let (top, left) = (top as usize, left as usize);
+          let window = image.slice(s![c, top..top + kernel, left..left + kernel]);
+          if max {
+            window.fold(f32::NEG_INFINITY, |m, &v| m.max(v))
+          } else {
+            window.mean().unwrap()
+          }
+        })
+      }
+      op => panic!("evaluator does not support {}", op),
+    };
+    values.insert(node.output[0].clone(), out);
+  }
+  let output = values
+    .remove(OUTPUT_NAME)
+    .expect("graph should produce the output");
+  matrix(&output)
+}
+
+#[test]
+fn graph_structure_matches_model() {
+  let model = model(Normalization::Standardize, None);
+  let onnx = round_trip(&model);
+
+  assert_eq!(onnx.ir_version, 8);
+  assert_eq!(onnx.opset_import.len(), 1);
+  assert_eq!(onnx.opset_import[0].domain, "");
+  assert_eq!(onnx.opset_import[0].version, 13);
+
+  let ops: Vec<&str> = onnx
+    .graph
+    .node
+    .iter()
+    .map(|node| node.op_type.as_str())
+    .collect();
+  #[rustfmt::skip]
+  let expected = [
+    "Sub", "Div", // standardize
+    "Gemm", "Relu",
+    "Gemm", "Mul", "Mul", "Mul", "Add", "Mul", "Tanh", "Add", "Mul", "Mul", // gelu
+    "Gemm", "Softmax",
+  ];
+  assert_eq!(ops, expected);
+
+  // every node reads values that exist by the time it runs
+  let mut available: Vec<&str> = vec![INPUT_NAME];
+  available.extend(onnx.graph.initializer.iter().map(|t| t.name.as_str()));
+  for node in &onnx.graph.node {
+    for input in &node.input {
+      assert!(
+        available.contains(&input.as_str()),
+        "{} reads unknown {}",
+        node.name,
+        input
+      );
+    }
+    available.extend(node.output.iter().map(String::as_str));
+  }
+
+  for node in onnx.graph.node.iter().filter(|node| node.op_type == "Gemm") {
+    assert_eq!(node.attribute("transB"), Some(&AttributeValue::Int(1)));
+  }
+  let softmax = onnx.graph.node.last().unwrap();
+  assert_eq!(softmax.attribute("axis"), Some(&AttributeValue::Int(1)));
+  assert_eq!(softmax.output, vec![OUTPUT_NAME.to_string()]);
+
+  let batch = Dimension::Param("batch".to_string());
+  assert_eq!(onnx.graph.input.len(), 1);
+  assert_eq!(onnx.graph.input[0].name, INPUT_NAME);
+  assert_eq!(onnx.graph.input[0].elem_type, DATA_TYPE_FLOAT);
+  assert_eq!(
+    onnx.graph.input[0].shape,
+    vec![batch.clone(), Dimension::Value(784)]
+  );
+  assert_eq!(onnx.graph.output.len(), 1);
+  assert_eq!(onnx.graph.output[0].name, OUTPUT_NAME);
+  assert_eq!(
+    onnx.graph.output[0].shape,
+    vec![batch, Dimension::Value(10)]
+  );
+
+  let labels = onnx
+    .metadata_props
+    .iter()
+    .find(|(key, _)| key == "class_labels")
+    .map(|(_, value)| value.as_str());
+  assert_eq!(labels, Some(r#"["0","1","2","3","4","5","6","7","8","9"]"#));
+}
+
+#[test]
+fn initializers_hold_model_weights() {
+  let model = model(Normalization::Scale, None);
+  let onnx = round_trip(&model);
+  let initializer = |name: &str| {
+    onnx
+      .graph
+      .initializer
+      .iter()
+      .find(|tensor| tensor.name == name)
+      .unwrap_or_else(|| panic!("no initializer {}", name))
+  };
+
+  for (i, layer) in model.layers.iter().enumerate() {
+    let w = initializer(&format!("w{}", i + 1));
+    assert_eq!(w.data_type, DATA_TYPE_FLOAT);
+    assert_eq!(w.dims, vec![layer.outputs() as i64, layer.inputs() as i64]);
+    assert_eq!(w.float_data, layer.w.iter().cloned().collect::<Vec<_>>());
+
+    let b = initializer(&format!("b{}", i + 1));
+    assert_eq!(b.dims, vec![layer.outputs() as i64]);
+    assert_eq!(b.float_data, layer.b.iter().cloned().collect::<Vec<_>>());
+  }
+
+  let factor = initializer("preprocess1.factor");
+  assert!(factor.dims.is_empty());
+  assert_eq!(factor.float_data, vec![1.0 / 255.0]);
+}
+
+#[test]
+fn graph_computes_model_predictions() {
+  for (normalization, pca_components) in [
+    (Normalization::Raw, None),
+    (Normalization::Scale, None),
+    (Normalization::Standardize, Some(20)),
+  ] {
+    let model = model(normalization, pca_components);
+    let onnx = round_trip(&model);
+    let input = images(5);
+
+    let expected = predictions(&model, &input);
+    let actual = evaluate(&onnx, &input).t().to_owned();
+    let difference = max_difference(&expected, &actual);
+    assert!(
+      difference < 1e-5,
+      "{:?}/{:?}: ONNX graph differs from the model by {}",
+      normalization,
+      pca_components,
+      difference
+    );
+  }
+}
+
+#[test]
+fn norm_layers_export() {
+  for (kind, norm_ops) in [(NormKind::BatchNorm, 0), (NormKind::LayerNorm, 9)] {
+    let mut model = model(Normalization::Scale, None);
+    model.add_norm_layers(&[kind, kind]);
+    for layer in &mut model.layers {
+      if let Some(norm) = &mut layer.norm {
+        norm.gamma.mapv_inplace(|_| 1.5);
+        norm.beta.mapv_inplace(|_| -0.2);
+        if let Some(running) = &mut norm.running {
+          running.mean.mapv_inplace(|_| 0.3);
+          running.var.mapv_inplace(|_| 2.0);
+        }
+      }
+    }
+    let onnx = round_trip(&model);
+    // scale, three gemms, relu, gelu (9 nodes), softmax, plus the norms
+    assert_eq!(onnx.graph.node.len(), 15 + 2 * norm_ops, "{:?}", kind);
+
+    let input = images(5);
+    let expected = predictions(&model, &input);
+    let actual = evaluate(&onnx, &input).t().to_owned();
+    let difference = max_difference(&expected, &actual);
+    assert!(
+      difference < 1e-5,
+      "{:?}: ONNX graph differs from the model by {}",
+      kind,
+      difference
+    );
+  }
+}
+
+#[test]
+fn conv_layers_export() {
+  let mut rng = ChaCha8Rng::seed_from_u64(3);
+  let mut model = InferrableModel::new(&[4 * 12 * 12, 10], &[], &mut rng);
+  model.add_conv_layers(
+    &[
+      ConvLayerConfig::Conv2d {
+        channels: 4,
+        kernel_size: 5,
+        stride: 1,
+        padding: 0,
+        activation: Activation::Relu,
+      },
+      ConvLayerConfig::AvgPool2d {
+        size: 2,
+        stride: None,
+      },
+    ],
+    &mut rng,
+  );
+  model.preprocessor = Preprocessor::fit(&PreprocessingConfig::default(), &images(10));
+  let onnx = round_trip(&model);
+
+  let ops: Vec<&str> = onnx
+    .graph
+    .node
+    .iter()
+    .map(|node| node.op_type.as_str())
+    .collect();
+  assert_eq!(
+    ops,
+    [
+      "Mul",
+      "Reshape",
+      "Conv",
+      "Relu",
+      "AveragePool",
+      "Flatten",
+      "Gemm",
+      "Softmax"
+    ]
+  );
+
+  let initializer = |name: &str| {
+    onnx
+      .graph
+      .initializer
+      .iter()
+      .find(|tensor| tensor.name == name)
+      .unwrap_or_else(|| panic!("no initializer {}", name))
+  };
+  assert_eq!(initializer("image_shape").int64_data, vec![-1, 1, 28, 28]);
+  let weight = initializer("conv1.weight");
+  assert_eq!(weight.dims, vec![4, 1, 5, 5]);
+  let conv = model.conv_layers[0].as_conv2d().unwrap();
+  assert_eq!(
+    weight.float_data,
+    conv.w.iter().cloned().collect::<Vec<_>>()
+  );
+  assert_eq!(initializer("conv1.bias").dims, vec![4]);
+
+  let input = images(3);
+  let expected = predictions(&model, &input);
+  let actual = evaluate(&onnx, &input).t().to_owned();
+  let difference = max_difference(&expected, &actual);
+  assert!(
+    difference < 1e-5,
+    "ONNX graph differs from the model by {}",
+    difference
+  );
+
+  let conv_node = &onnx.graph.node[2];
+  assert_eq!(
+    conv_node.attribute("kernel_shape"),
+    Some(&AttributeValue::Ints(vec![5, 5]))
+  );
+  assert_eq!(
+    conv_node.attribute("pads"),
+    Some(&AttributeValue::Ints(vec![0; 4]))
+  );
+  assert_eq!(
+    onnx.graph.node[4].attribute("strides"),
+    Some(&AttributeValue::Ints(vec![2, 2]))
+  );
+}
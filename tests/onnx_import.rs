@@ -0,0 +1,224 @@
+mod common;
+
+use common::{images, max_difference, predictions};
+use ndarray::Array2;
+use neural_net::activation::Activation;
+use neural_net::error::ModelError;
+use neural_net::inferrable_model::InferrableModel;
+use neural_net::onnx::export::to_onnx;
+use neural_net::onnx::import::from_onnx;
+use neural_net::onnx::proto::{
+  DATA_TYPE_FLOAT, Dimension, GraphProto, ModelProto, NodeProto, TensorProto, ValueInfo,
+};
+use neural_net::preprocessing::{Normalization, PreprocessingConfig, Preprocessor};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+#[test]
+fn exported_models_import_unchanged() {
+  for (normalization, pca_components, activations) in [
+    (
+      Normalization::Raw,
+      None,
+      [Activation::Sigmoid, Activation::Tanh],
+    ),
+    (
+      Normalization::Scale,
+      None,
+      [Activation::Relu, Activation::LeakyRelu],
+    ),
+    (
+      Normalization::Standardize,
+      Some(20),
+      [Activation::Elu, Activation::Relu],
+    ),
+  ] {
+    let mut rng = ChaCha8Rng::seed_from_u64(3);
+    let mut model = InferrableModel::new(
+      &[pca_components.unwrap_or(784), 12, 8, 10],
+      &activations,
+      &mut rng,
+    );
+    let config = PreprocessingConfig {
+      normalization,
+      pca_components,
+    };
+    model.preprocessor = Preprocessor::fit(&config, &images(50));
+
+    let bytes = to_onnx(&model).encode();
+    let imported = from_onnx(&ModelProto::decode(&bytes).unwrap()).unwrap();
+
+    assert_eq!(imported.layer_sizes(), model.layer_sizes());
+    let imported_activations: Vec<Activation> = imported
+      .layers
+      .iter()
+      .map(|layer| layer.activation)
+      .collect();
+    let expected: Vec<Activation> = model.layers.iter().map(|layer| layer.activation).collect();
+    assert_eq!(imported_activations, expected);
+    assert_eq!(imported.class_labels, model.class_labels);
+
+    let input = images(5);
+    let difference = max_difference(
+      &predictions(&model, &input),
+      &predictions(&imported, &input),
+    );
+    assert!(
+      difference < 1e-5,
+      "{:?}: predictions differ by {}",
+      normalization,
+      difference
+    );
+  }
+}
+
+fn tensor(name: &str, dims: &[i64], values: Vec<f32>) -> TensorProto {
+  TensorProto {
+    name: name.to_string(),
+    dims: dims.to_vec(),
+    data_type: DATA_TYPE_FLOAT,
+    float_data: values,
+    int64_data: Vec::new(),
+  }
+}
+
+fn node(op_type: &str, inputs: &[&str], output: &str) -> NodeProto {
+  NodeProto {
+    name: output.to_string(),
+    op_type: op_type.to_string(),
+    input: inputs.iter().map(|input| input.to_string()).collect(),
+    output: vec![output.to_string()],
+    ..Default::default()
+  }
+}
+
+fn value_info(name: &str, width: i64) -> ValueInfo {
+  ValueInfo {
+    name: name.to_string(),
+    elem_type: DATA_TYPE_FLOAT,
+    shape: vec![Dimension::Param("N".to_string()), Dimension::Value(width)],
+  }
+}
+
+/// A graph in the style other frameworks write: MatMul + Add layers with
+/// `inputs x outputs` weights and no Softmax.
+fn matmul_graph(nodes: Vec<NodeProto>) -> ModelProto {
+  let w1: Vec<f32> = (0..784 * 4)
+    .map(|i| ((i % 13) as f32 - 6.0) * 1e-3)
+    .collect();
+  let w2: Vec<f32> = (0..4 * 10).map(|i| ((i % 7) as f32 - 3.0) * 0.1).collect();
+  let output = nodes.last().unwrap().output[0].clone();
+  ModelProto {
+    graph: GraphProto {
+      node: nodes,
+      initializer: vec![
+        tensor("scale", &[], vec![1.0 / 255.0]),
+        tensor("W1", &[784, 4], w1),
+        tensor("B1", &[4], vec![0.1, -0.2, 0.3, 0.0]),
+        tensor("W2", &[4, 10], w2),
+        tensor("B2", &[10], vec![0.0; 10]),
+      ],
+      input: vec![value_info("x", 784)],
+      output: vec![value_info(&output, 10)],
+      ..Default::default()
+    },
+    ..Default::default()
+  }
+}
+
+#[test]
+fn matmul_add_graphs_import() {
+  let onnx = matmul_graph(vec![
+    node("Mul", &["x", "scale"], "scaled"),
+    node("MatMul", &["scaled", "W1"], "h1"),
+    node("Add", &["h1", "B1"], "z1"),
+    node("Relu", &["z1"], "a1"),
+    node("MatMul", &["a1", "W2"], "h2"),
+    node("Add", &["h2", "B2"], "logits"),
+  ]);
+  let model = from_onnx(&onnx).unwrap();
+  assert_eq!(model.layer_sizes(), vec![784, 4, 10]);
+  assert_eq!(model.layers[0].activation, Activation::Relu);
+  assert_eq!(model.layers[1].activation, Activation::Identity);
+  // weights are stored transposed in the graph
+  assert_eq!(
+    model.layers[0].w[[1, 0]],
+    onnx.graph.initializer[1].float_data[1]
+  );
+  assert_eq!(model.layers[0].b[[1, 0]], -0.2);
+
+  // compute the graph by hand for one image
+  let image = images(1);
+  let scaled = &image / 255.0;
+  let w1 = Array2::from_shape_vec((784, 4), onnx.graph.initializer[1].float_data.clone()).unwrap();
+  let w2 = Array2::from_shape_vec((4, 10), onnx.graph.initializer[3].float_data.clone()).unwrap();
+  let b1 = Array2::from_shape_vec((1, 4), vec![0.1, -0.2, 0.3, 0.0]).unwrap();
+  let hidden = (scaled.dot(&w1) + b1).mapv(|v| v.max(0.0));
+  let logits = hidden.dot(&w2);
+  let exp = logits.mapv(|v| v.exp());
+  let expected = (&exp / exp.sum()).t().to_owned();
+
+  let difference = max_difference(&predictions(&model, &image), &expected);
+  assert!(difference < 1e-5, "predictions differ by {}", difference);
+}
+
+#[test]
+fn unsupported_ops_are_named() {
+  let onnx = matmul_graph(vec![
+    node("MatMul", &["x", "W1"], "h1"),
+    node("Add", &["h1", "B1"], "z1"),
+    node("HardSwish", &["z1"], "a1"),
+    node("MatMul", &["a1", "W2"], "logits"),
+  ]);
+  let error = from_onnx(&onnx).err().expect("HardSwish should not import");
+  assert!(
+    matches!(&error, ModelError::UnsupportedOp { op, node } if op == "HardSwish" && node == "a1"),
+    "unexpected error: {}",
+    error
+  );
+  assert!(error.to_string().contains("HardSwish"));
+}
+
+#[test]
+fn non_finite_preprocessing_is_rejected() {
+  let mut divide_by_zero = matmul_graph(vec![
+    node("Div", &["x", "zero"], "scaled"),
+    node("MatMul", &["scaled", "W1"], "h1"),
+    node("Add", &["h1", "B1"], "z1"),
+    node("MatMul", &["z1", "W2"], "logits"),
+  ]);
+  divide_by_zero
+    .graph
+    .initializer
+    .push(tensor("zero", &[], vec![0.0]));
+  let error = from_onnx(&divide_by_zero)
+    .err()
+    .expect("an infinite scale should not import");
+  assert!(
+    matches!(&error, ModelError::NonFinite { name, count: 1 } if name == "zero"),
+    "unexpected error: {}",
+    error
+  );
+
+  let mut nan_mean = matmul_graph(vec![
+    // a Sub then MatMul is whitening, so W1 is the projection
+    node("Sub", &["x", "mean"], "centered"),
+    node("MatMul", &["centered", "W1"], "whitened"),
+    node("MatMul", &["whitened", "W2"], "h2"),
+    node("Add", &["h2", "B2"], "logits"),
+  ]);
+  let mut mean = vec![0.5; 784];
+  mean[3] = f32::NAN;
+  nan_mean
+    .graph
+    .initializer
+    .push(tensor("mean", &[784], mean));
+  let error = from_onnx(&nan_mean)
+    .err()
+    .expect("a NaN mean should not import");
+  assert!(
+    matches!(&error, ModelError::NonFinite { name, count: 1 } if name == "preprocess1.mean"),
+    "unexpected error: {}",
+    error
+  );
+}
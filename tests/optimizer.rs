@@ -0,0 +1,55 @@
+use ndarray::Array2;
+use neural_net::optimizer::{OptimizerConfig, OptimizerKind, OptimizerState};
+
+const SHAPES: [(usize, usize); 2] = [(3, 2), (3, 1)];
+
+/// The state of an Adam optimizer after one step on parameters of `SHAPES`.
+fn adam_state() -> OptimizerState {
+  let config = OptimizerConfig {
+    kind: OptimizerKind::Adam,
+    ..Default::default()
+  };
+  let mut optimizer = config.build();
+  let mut params: Vec<Array2<f32>> = SHAPES.iter().map(|&s| Array2::ones(s)).collect();
+  let grads: Vec<Array2<f32>> = SHAPES.iter().map(|&s| Array2::ones(s)).collect();
+  optimizer.step(
+    &mut params.iter_mut().collect::<Vec<_>>(),
+    &grads.iter().collect::<Vec<_>>(),
+  );
+  optimizer.state()
+}
+
+fn load(state: OptimizerState, shapes: &[(usize, usize)]) -> Result<(), String> {
+  let config = OptimizerConfig {
+    kind: OptimizerKind::Adam,
+    ..Default::default()
+  };
+  config.build().load_state(state, shapes)
+}
+
+#[test]
+fn state_loads_for_the_same_parameters() {
+  assert_eq!(load(adam_state(), &SHAPES), Ok(()));
+  // an optimizer that never stepped has no tensors to check
+  assert_eq!(load(Default::default(), &SHAPES), Ok(()));
+}
+
+#[test]
+fn state_for_other_parameters_is_rejected() {
+  assert_eq!(
+    load(adam_state(), &[(3, 2), (4, 1)]),
+    Err("optimizer state tensor m.1 is (3, 1), the parameter is (4, 1)".into())
+  );
+  assert_eq!(
+    load(adam_state(), &[(3, 2)]),
+    Err("optimizer state has more m tensors than the model has parameters (1)".into())
+  );
+  assert_eq!(
+    load(adam_state(), &[(3, 2), (3, 1), (2, 2)]),
+    Err("optimizer state is missing tensor m.2".into())
+  );
+
+  let mut state = adam_state();
+  state.tensors.remove("v.0");
+  assert!(load(state, &SHAPES).is_err());
+}
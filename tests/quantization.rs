@@ -0,0 +1,199 @@
+mod common;
+
+use ndarray::{Array2, Axis};
+use neural_net::activation::Activation;
+use neural_net::inferrable_model::{InferrableModel, Mode};
+use neural_net::quantization::{
+  Granularity, QuantizedWeights, agreement, calibrate, quantize_model,
+};
+use neural_net::serialization::TensorDtype;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+fn model() -> InferrableModel {
+  common::model(&[784, 12, 10], &[Activation::Relu], 5)
+}
+
+/// Raw inputs, one image per row.
+fn images() -> Array2<f32> {
+  common::input(30, 784)
+}
+
+/// Weights whose rows differ in magnitude by up to 1000x.
+fn weights() -> Array2<f32> {
+  Array2::from_shape_fn((4, 6), |(i, j)| {
+    10f32.powi(i as i32 - 2) * (((i * 5 + j * 7) % 13) as f32 / 6.0 - 1.0)
+  })
+}
+
+fn max_abs(values: &Array2<f32>) -> f32 {
+  values.iter().fold(0.0, |m, v| m.max(v.abs()))
+}
+
+#[test]
+fn dequantized_weights_are_within_half_a_step() {
+  let w = weights();
+
+  let per_tensor = QuantizedWeights::new(&w, Granularity::PerTensor, 1.0);
+  assert_eq!(per_tensor.scales.dim(), (1, 1));
+  let scale = per_tensor.scales[[0, 0]];
+  assert!((scale - max_abs(&w) / 127.0).abs() <= f32::EPSILON * scale);
+  let error = max_abs(&(per_tensor.dequantize() - &w));
+  assert!(
+    error <= scale * 0.5 * 1.0001,
+    "{} vs scale {}",
+    error,
+    scale
+  );
+
+  let per_channel = QuantizedWeights::new(&w, Granularity::PerChannel, 1.0);
+  assert_eq!(per_channel.scales.dim(), (4, 1));
+  let errors = per_channel.dequantize() - &w;
+  for (row, scale) in errors.axis_iter(Axis(0)).zip(per_channel.scales.iter()) {
+    let error = row.iter().fold(0.0f32, |m, v| m.max(v.abs()));
+    assert!(
+      error <= scale * 0.5 * 1.0001,
+      "{} vs scale {}",
+      error,
+      scale
+    );
+  }
+  // the smallest row keeps its precision with its own scale, but not with the shared one
+  let small_row = |q: &QuantizedWeights| {
+    let error = (q.dequantize() - &w).row(0).mapv(f32::abs);
+    error.iter().cloned().fold(0.0, f32::max)
+  };
+  assert!(small_row(&per_channel) * 100.0 < small_row(&per_tensor));
+}
+
+#[test]
+fn integer_linear_matches_the_float_linear() {
+  let w = weights();
+  let b = Array2::from_shape_fn((4, 1), |(i, _)| i as f32 * 0.3 - 0.5);
+  let x = Array2::from_shape_fn((6, 5), |(i, j)| ((i * 3 + j * 2) % 9) as f32 / 4.0 - 1.0);
+  let expected = w.dot(&x) + &b;
+
+  for granularity in [Granularity::PerTensor, Granularity::PerChannel] {
+    let quantized = QuantizedWeights::new(&w, granularity, max_abs(&x));
+    let actual = quantized.linear(&quantized.quantize_input(&x), &b);
+
+    // each product is off by at most |w| dx + |x| dw + dw dx, plus half a step of bias
+    let dx = quantized.input_scale / 2.0;
+    let dw = &quantized.scales / 2.0;
+    let bound = w.mapv(f32::abs).sum_axis(Axis(1)).insert_axis(Axis(1)) * dx
+      + &dw * x.mapv(f32::abs).sum_axis(Axis(0)).insert_axis(Axis(0))
+      + &dw * (dx * 6.0)
+      + &dw * (quantized.input_scale * 2.0);
+    for ((index, &actual), &bound) in actual.indexed_iter().zip(bound.iter()) {
+      let error = (actual - expected[index]).abs();
+      assert!(
+        error <= bound * 1.001 + 1e-6,
+        "{:?} at {:?}: {} vs {} (bound {})",
+        granularity,
+        index,
+        actual,
+        expected[index],
+        bound
+      );
+    }
+  }
+}
+
+#[test]
+fn calibration_finds_the_input_range_of_every_layer() {
+  let model = model();
+  let inputs = images().t().to_owned();
+  let ranges = calibrate(&model, &inputs);
+
+  let hidden = model.layers[0]
+    .activation
+    .apply(&model.layers[0].linear(&inputs));
+  assert_eq!(ranges, vec![max_abs(&inputs), max_abs(&hidden)]);
+
+  let quantized = quantize_model(&model, &images(), Granularity::PerChannel);
+  for (layer, range) in quantized.layers.iter().zip(&ranges) {
+    let weights = layer.quantized.as_ref().unwrap();
+    assert_eq!(weights.input_scale, range / 127.0);
+    assert_eq!(layer.w, weights.dequantize());
+  }
+}
+
+#[test]
+fn quantized_models_round_trip_through_safetensors() {
+  let quantized = quantize_model(&model(), &images(), Granularity::PerChannel);
+  let path = std::env::temp_dir().join(format!(
+    "neural-net-quantization-{}.safetensors",
+    std::process::id()
+  ));
+  quantized
+    .to_serializable_model()
+    .save_to_safetensors(&path, TensorDtype::F32)
+    .unwrap();
+  let loaded = InferrableModel::load(&path).unwrap();
+  std::fs::remove_file(&path).unwrap();
+
+  for (expected, actual) in quantized.layers.iter().zip(&loaded.layers) {
+    let (expected, actual) = (
+      expected.quantized.as_ref().unwrap(),
+      actual.quantized.as_ref().unwrap(),
+    );
+    assert_eq!(actual.weights, expected.weights);
+    assert_eq!(actual.scales, expected.scales);
+    assert_eq!(actual.input_scale, expected.input_scale);
+  }
+  let inputs = images().t().to_owned();
+  assert_eq!(
+    loaded.forward(&inputs, Mode::Eval),
+    quantized.forward(&inputs, Mode::Eval)
+  );
+}
+
+#[test]
+fn agreement_counts_matching_predictions() {
+  let model = model();
+  assert_eq!(agreement(&model, &model, &images()), 1.0);
+
+  let predictions: Vec<usize> = model
+    .forward(&images().t().to_owned(), Mode::Eval)
+    .axis_iter(Axis(1))
+    .map(|column| {
+      (0..10)
+        .max_by(|&a, &b| column[a].total_cmp(&column[b]))
+        .unwrap()
+    })
+    .collect();
+  // a model that always predicts the first image's class agrees only where the float
+  // model predicts that class too
+  let class = predictions[0];
+  let mut always_one_class = model.clone();
+  always_one_class.layers[1].b[[class, 0]] = 1e6;
+  let matching = predictions.iter().filter(|&&c| c == class).count();
+  assert!(
+    matching < 30,
+    "the float model predicts {} for every image",
+    class
+  );
+  assert_eq!(
+    agreement(&model, &always_one_class, &images()),
+    matching as f32 / 30.0
+  );
+}
+
+#[test]
+fn train_mode_runs_on_the_dequantized_weights() {
+  let quantized = quantize_model(&model(), &images(), Granularity::PerTensor);
+  let mut float = quantized.clone();
+  for layer in &mut float.layers {
+    layer.quantized = None;
+  }
+  let inputs = images().t().to_owned();
+  let mut rng = ChaCha8Rng::seed_from_u64(0);
+  let train = quantized.forward(
+    &inputs,
+    Mode::Train {
+      dropout: &[],
+      rng: &mut rng,
+    },
+  );
+  assert_eq!(train, float.forward(&inputs, Mode::Eval));
+}
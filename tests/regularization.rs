@@ -0,0 +1,137 @@
+mod common;
+
+use common::gradient_check;
+use ndarray::Array2;
+use neural_net::activation::Activation;
+use neural_net::inferrable_model::{DenseGradients, Gradients, InferrableModel};
+use neural_net::regularization::RegularizationConfig;
+
+fn model() -> InferrableModel {
+  common::model(&[8, 6, 4], &[Activation::Relu], 3)
+}
+
+fn zero_gradients(model: &InferrableModel) -> Gradients {
+  Gradients {
+    conv: Vec::new(),
+    dense: model
+      .layers
+      .iter()
+      .map(|layer| DenseGradients {
+        dw: Array2::zeros(layer.w.dim()),
+        db: Array2::zeros(layer.b.dim()),
+        norm: None,
+      })
+      .collect(),
+  }
+}
+
+#[test]
+fn penalty_gradients_match_finite_differences() {
+  let regularization = RegularizationConfig {
+    l1: 0.01,
+    l2: 0.1,
+    max_norm: None,
+  };
+  let mut model = model();
+  model.layers[0].b.fill(1.0);
+  let mut gradients = zero_gradients(&model);
+  regularization.add_gradients(&model, &mut gradients);
+
+  let tensors: Vec<Array2<f32>> = gradients.tensors().into_iter().cloned().collect();
+  // w1, b1, w2, b2
+  gradient_check(
+    &mut model,
+    InferrableModel::parameters_mut,
+    &tensors,
+    &[(0, [0, 0]), (0, [5, 7]), (2, [3, 2])],
+    |model| regularization.penalty(model),
+  );
+  assert!(
+    gradients
+      .dense
+      .iter()
+      .all(|g| g.db.iter().all(|&d| d == 0.0)),
+    "biases are not regularized"
+  );
+
+  let expected: f32 = model
+    .layers
+    .iter()
+    .flat_map(|layer| layer.w.iter())
+    .map(|&w| 0.01 * w.abs() + 0.05 * w * w)
+    .sum();
+  assert!((regularization.penalty(&model) - expected).abs() < 1e-5);
+}
+
+#[test]
+fn no_penalty_by_default() {
+  let model = model();
+  let regularization = RegularizationConfig::default();
+  assert_eq!(regularization.penalty(&model), 0.0);
+  let mut gradients = zero_gradients(&model);
+  regularization.add_gradients(&model, &mut gradients);
+  assert!(
+    gradients
+      .dense
+      .iter()
+      .all(|g| g.dw.iter().all(|&d| d == 0.0))
+  );
+}
+
+#[test]
+fn max_norm_rescales_only_large_rows() {
+  let mut model = model();
+  model.layers[0].w.row_mut(0).fill(1.0); // norm sqrt(8)
+  model.layers[0].w.row_mut(1).fill(0.1); // norm ~0.28
+  let small_row = model.layers[0].w.row(1).to_owned();
+
+  let regularization = RegularizationConfig {
+    max_norm: Some(2.0),
+    ..Default::default()
+  };
+  regularization.apply_max_norm(&mut model);
+
+  let row = model.layers[0].w.row(0);
+  assert!((row.dot(&row).sqrt() - 2.0).abs() < 1e-5);
+  assert!(row.iter().all(|&w| (w - 2.0 / 8f32.sqrt()).abs() < 1e-6));
+  assert_eq!(model.layers[0].w.row(1), small_row);
+  for layer in &model.layers {
+    for row in layer.w.rows() {
+      assert!(row.dot(&row).sqrt() <= 2.0 + 1e-5);
+    }
+  }
+}
+
+#[test]
+fn invalid_coefficients_are_rejected() {
+  let valid = RegularizationConfig {
+    l1: 0.01,
+    l2: 0.1,
+    max_norm: Some(3.0),
+  };
+  assert_eq!(valid.validate(), Ok(()));
+  for invalid in [
+    RegularizationConfig {
+      l1: -0.1,
+      ..valid.clone()
+    },
+    RegularizationConfig {
+      l2: f32::NAN,
+      ..valid.clone()
+    },
+    RegularizationConfig {
+      l1: f32::INFINITY,
+      ..valid.clone()
+    },
+    RegularizationConfig {
+      max_norm: Some(0.0),
+      ..valid.clone()
+    },
+    RegularizationConfig {
+      max_norm: Some(f32::NAN),
+      ..valid.clone()
+    },
+  ] {
+    assert!(invalid.validate().is_err(), "{:?} was accepted", invalid);
+  }
+}
@@ -0,0 +1,119 @@
+use ndarray::Array2;
+use neural_net::checkpoint::{CheckpointPolicy, load_checkpoint};
+use neural_net::config::TrainingConfig;
+use neural_net::inferrable_model::InferrableModel;
+use neural_net::optimizer::OptimizerKind;
+use neural_net::serialization::TensorDtype;
+use neural_net::training::{TrainingData, save_trained_model, train};
+use std::path::PathBuf;
+
+/// Small synthetic dataset: each digit lights up its own band of pixels.
+fn synthetic_data(samples: usize) -> TrainingData {
+  let labels: Vec<u8> = (0..samples).map(|i| (i * 7 % 10) as u8).collect();
+  let images = Array2::from_shape_fn((samples, 28 * 28), |(i, pixel)| {
+    let band = pixel / 78;
+    if band == labels[i] as usize {
+      255.0
+    } else {
+      ((i * 31 + pixel * 17) % 64) as f32
+    }
+  });
+  TrainingData { images, labels }
+}
+
+fn config(seed: u64) -> TrainingConfig {
+  let mut config = TrainingConfig {
+    layers: vec![784, 16, 10],
+    epochs: 2,
+    batch_size: 8,
+    seed: Some(seed),
+    ..Default::default()
+  };
+  config.optimizer.kind = OptimizerKind::Adam;
+  config
+}
+
+fn test_dir() -> PathBuf {
+  let dir = std::env::temp_dir().join(format!("neural-net-test-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  dir
+}
+
+/// Checkpoints written every `every_steps` optimizer steps to a file named `name`.
+fn checkpoint_policy(name: &str, every_steps: Option<usize>) -> CheckpointPolicy {
+  CheckpointPolicy {
+    path: test_dir().join(name),
+    every_epochs: None,
+    every_steps,
+  }
+}
+
+/// The bytes of `model` saved as `name`.
+fn saved_bytes(model: &InferrableModel, config: &TrainingConfig, name: &str) -> Vec<u8> {
+  let path = test_dir().join(name);
+  save_trained_model(path.to_str().unwrap(), model, config, TensorDtype::F32).unwrap();
+  let bytes = std::fs::read(&path).unwrap();
+  std::fs::remove_file(&path).unwrap();
+  bytes
+}
+
+fn train_to_file(config: &TrainingConfig, name: &str) -> Vec<u8> {
+  let checkpoints = checkpoint_policy("unused.checkpoint.safetensors", None);
+  let model = train(config, &synthetic_data(200), None, &checkpoints, None);
+  saved_bytes(&model, config, name)
+}
+
+#[test]
+fn same_seed_produces_identical_files() {
+  let first = train_to_file(&config(42), "first.safetensors");
+  let second = train_to_file(&config(42), "second.safetensors");
+  assert!(
+    first == second,
+    "two runs with seed 42 wrote different files"
+  );
+}
+
+#[test]
+fn different_seeds_produce_different_files() {
+  let first = train_to_file(&config(1), "seed1.safetensors");
+  let second = train_to_file(&config(2), "seed2.safetensors");
+  assert!(
+    first != second,
+    "runs with seeds 1 and 2 wrote the same file"
+  );
+}
+
+#[test]
+fn resuming_mid_epoch_matches_an_uninterrupted_run() {
+  let mut config = config(3);
+  config.dropout = vec![0.2];
+  let uninterrupted = train_to_file(&config, "uninterrupted.safetensors");
+
+  // 25 steps per epoch: the last checkpoint is written after step 34, 9 steps into the
+  // second epoch
+  let checkpoints = checkpoint_policy("resume.checkpoint.safetensors", Some(17));
+  train(&config, &synthetic_data(200), None, &checkpoints, None);
+  let checkpoint = load_checkpoint(&checkpoints.path).unwrap();
+  let changed_lr = load_checkpoint(&checkpoints.path).unwrap();
+  std::fs::remove_file(&checkpoints.path).unwrap();
+  assert_eq!(
+    (checkpoint.metadata.epoch, checkpoint.metadata.step_in_epoch),
+    (1, 9)
+  );
+
+  let no_checkpoints = checkpoint_policy("unused.checkpoint.safetensors", None);
+  let data = synthetic_data(200);
+  let resumed = train(&config, &data, None, &no_checkpoints, Some(checkpoint));
+  assert!(
+    saved_bytes(&resumed, &config, "resumed.safetensors") == uninterrupted,
+    "the resumed run wrote a different file than the uninterrupted one"
+  );
+
+  // the flags of the resumed run apply to the rest of it
+  config.optimizer.lr *= 10.0;
+  let changed = train(&config, &data, None, &no_checkpoints, Some(changed_lr));
+  assert_ne!(
+    changed.layers[0].w, resumed.layers[0].w,
+    "resuming with a different learning rate kept the checkpoint's"
+  );
+}
@@ -0,0 +1,54 @@
+use neural_net::schedule::{LrScheduler, ScheduleConfig, ScheduleKind};
+
+fn cosine(cycle_epochs: f32, cycle_mult: f32) -> LrScheduler {
+  let config = ScheduleConfig {
+    kind: ScheduleKind::Cosine,
+    cycle_epochs,
+    cycle_mult,
+    ..Default::default()
+  };
+  assert_eq!(config.validate(), Ok(()));
+  // 10 steps per epoch
+  LrScheduler::new(&config, 1.0, 10, 20)
+}
+
+fn assert_close(actual: f32, expected: f32, step: usize) {
+  assert!(
+    (actual - expected).abs() < 1e-4,
+    "step {}: lr {} vs {}",
+    step,
+    actual,
+    expected
+  );
+}
+
+#[test]
+fn cosine_restarts_after_each_cycle() {
+  // cycles of 2, 4 and 8 epochs, restarting at epochs 2, 6 and 14
+  let growing = cosine(2.0, 2.0);
+  for (step, expected) in [
+    (0, 1.0),
+    (10, 0.5),
+    (20, 1.0),
+    (40, 0.5),
+    (59, 0.0015),
+    (60, 1.0),
+    (100, 0.5),
+    (140, 1.0),
+  ] {
+    assert_close(growing.lr(step), expected, step);
+  }
+
+  let fixed = cosine(2.0, 1.0);
+  for (step, expected) in [(0, 1.0), (10, 0.5), (20, 1.0), (30, 0.5), (180, 1.0)] {
+    assert_close(fixed.lr(step), expected, step);
+  }
+}
+
+#[test]
+fn tiny_cosine_cycles_still_give_a_learning_rate() {
+  for mult in [1.0, 1.5] {
+    let lr = cosine(1e-6, mult).lr(1_000_000);
+    assert!((0.0..=1.0).contains(&lr), "lr {} with mult {}", lr, mult);
+  }
+}
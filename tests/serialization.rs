@@ -0,0 +1,195 @@
+use ndarray::Array2;
+use neural_net::serialization::{TensorDtype, load_safetensors, save_safetensors_as};
+use safetensors::tensor::TensorView;
+use safetensors::{Dtype, serialize};
+use std::path::PathBuf;
+
+fn temp_path(name: &str) -> PathBuf {
+  std::env::temp_dir().join(format!(
+    "neural-net-serialization-{}-{}.safetensors",
+    std::process::id(),
+    name
+  ))
+}
+
+/// `values` after being saved as `dtype` and loaded back.
+fn round_trip(dtype: TensorDtype, values: &[f32]) -> Vec<f32> {
+  let path = temp_path(&format!("{:?}", dtype));
+  let tensor = Array2::from_shape_vec((1, values.len()), values.to_vec()).unwrap();
+  save_safetensors_as(&path, &[("t", &tensor)], None, dtype).unwrap();
+  let (tensors, _) = load_safetensors(&path).unwrap();
+  std::fs::remove_file(&path).unwrap();
+  tensors[0].1.iter().cloned().collect()
+}
+
+fn assert_rounds(dtype: TensorDtype, cases: &[(f32, f32)]) {
+  let (values, expected): (Vec<f32>, Vec<f32>) = cases.iter().cloned().unzip();
+  let loaded = round_trip(dtype, &values);
+  for ((value, expected), loaded) in values.iter().zip(&expected).zip(&loaded) {
+    assert_eq!(
+      loaded.to_bits(),
+      expected.to_bits(),
+      "{:?}: {:e} loaded as {:e}, expected {:e}",
+      dtype,
+      value,
+      loaded,
+      expected
+    );
+  }
+}
+
+#[test]
+fn representable_values_round_trip_exactly() {
+  let values = [0.0, -0.0, 1.0, -2.5, 0.099975586, 1024.0, -3.140625];
+  for dtype in [TensorDtype::F16, TensorDtype::F32, TensorDtype::F64] {
+    assert_rounds(dtype, &values.map(|v| (v, v)));
+  }
+  assert_rounds(TensorDtype::F16, &[(65504.0, 65504.0)]);
+  assert_rounds(
+    TensorDtype::Bf16,
+    &[0.0, -0.0, 1.0, -2.5, 1024.0, -3.140625, 2f32.powi(100)].map(|v| (v, v)),
+  );
+  let bf16_max = TensorDtype::Bf16.max_value();
+  assert_rounds(TensorDtype::Bf16, &[(bf16_max, bf16_max)]);
+}
+
+#[test]
+fn f16_subnormals_keep_their_value() {
+  let smallest = 2f32.powi(-24);
+  let smallest_normal = 2f32.powi(-14);
+  assert_rounds(
+    TensorDtype::F16,
+    &[
+      (smallest, smallest),
+      (-smallest, -smallest),
+      (smallest_normal, smallest_normal),
+      (smallest_normal - smallest, smallest_normal - smallest),
+      (37.0 * smallest, 37.0 * smallest),
+      // below half the smallest subnormal rounds to zero, keeping the sign
+      (smallest * 0.25, 0.0),
+      (-smallest * 0.25, -0.0),
+      // three quarters of the way to the smallest subnormal rounds up to it
+      (smallest * 0.75, smallest),
+    ],
+  );
+}
+
+#[test]
+fn ties_round_to_even() {
+  assert_rounds(
+    TensorDtype::F16,
+    &[
+      // halfway between 1 and the next f16 (1 + 2^-10), whose mantissa is odd
+      (1.0 + 2f32.powi(-11), 1.0),
+      // halfway between 1 + 2^-10 (odd) and 1 + 2^-9 (even)
+      (1.0 + 3.0 * 2f32.powi(-11), 1.0 + 2f32.powi(-9)),
+      // just past halfway rounds up
+      (1.0 + 2f32.powi(-11) + 2f32.powi(-20), 1.0 + 2f32.powi(-10)),
+      // halfway between 0 and the smallest subnormal
+      (2f32.powi(-25), 0.0),
+      // halfway between the smallest subnormal (odd) and twice it (even)
+      (3.0 * 2f32.powi(-25), 2f32.powi(-23)),
+      // a carry out of the mantissa moves to the next power of two
+      (2.0 - 2f32.powi(-11), 2.0),
+    ],
+  );
+  assert_rounds(
+    TensorDtype::Bf16,
+    &[
+      (1.0 + 2f32.powi(-8), 1.0),
+      (1.0 + 3.0 * 2f32.powi(-8), 1.0 + 2f32.powi(-6)),
+      (1.0 + 2f32.powi(-8) + 2f32.powi(-20), 1.0 + 2f32.powi(-7)),
+      (2.0 - 2f32.powi(-8), 2.0),
+    ],
+  );
+}
+
+#[test]
+fn values_out_of_range_become_infinite() {
+  assert_rounds(
+    TensorDtype::F16,
+    &[
+      (1e6, f32::INFINITY),
+      (-1e6, f32::NEG_INFINITY),
+      // halfway between 65504 (odd) and 65536, which is past the largest f16
+      (65520.0, f32::INFINITY),
+      (65519.0, 65504.0),
+      (f32::INFINITY, f32::INFINITY),
+      (f32::NEG_INFINITY, f32::NEG_INFINITY),
+    ],
+  );
+  assert_rounds(
+    TensorDtype::Bf16,
+    &[
+      (f32::MAX, f32::INFINITY),
+      (-f32::MAX, f32::NEG_INFINITY),
+      (f32::INFINITY, f32::INFINITY),
+    ],
+  );
+}
+
+#[test]
+fn nan_stays_nan() {
+  // a NaN whose payload is only in bits f16 and bf16 drop
+  let low_payload = f32::from_bits(0x7f80_0001);
+  for dtype in [
+    TensorDtype::F16,
+    TensorDtype::Bf16,
+    TensorDtype::F32,
+    TensorDtype::F64,
+  ] {
+    let loaded = round_trip(dtype, &[f32::NAN, -f32::NAN, low_payload]);
+    assert!(
+      loaded.iter().all(|v| v.is_nan()),
+      "{:?}: {:?}",
+      dtype,
+      loaded
+    );
+  }
+}
+
+#[test]
+fn tensors_stored_in_other_dtypes_load_as_f32() {
+  let f16: Vec<u8> = [0x3c00u16, 0xc100, 0x0001, 0x7c00]
+    .iter()
+    .flat_map(|v| v.to_le_bytes())
+    .collect();
+  let bf16: Vec<u8> = [0x3f80u16, 0xc020, 0x0001, 0xff80]
+    .iter()
+    .flat_map(|v| v.to_le_bytes())
+    .collect();
+  let f64: Vec<u8> = [1.0f64, -2.5, 1e-300, 1e300]
+    .iter()
+    .flat_map(|v| v.to_le_bytes())
+    .collect();
+  let tensors = vec![
+    (
+      "bf16",
+      TensorView::new(Dtype::BF16, vec![2, 2], &bf16).unwrap(),
+    ),
+    (
+      "f16",
+      TensorView::new(Dtype::F16, vec![2, 2], &f16).unwrap(),
+    ),
+    (
+      "f64",
+      TensorView::new(Dtype::F64, vec![2, 2], &f64).unwrap(),
+    ),
+  ];
+  let path = temp_path("dtypes");
+  std::fs::write(&path, serialize(tensors, None).unwrap()).unwrap();
+  let (loaded, _) = load_safetensors(&path).unwrap();
+  std::fs::remove_file(&path).unwrap();
+
+  let values = |name: &str| -> Vec<f32> {
+    let (_, tensor) = loaded.iter().find(|(n, _)| n == name).unwrap();
+    tensor.iter().cloned().collect()
+  };
+  assert_eq!(values("f16"), [1.0, -2.5, 2f32.powi(-24), f32::INFINITY]);
+  assert_eq!(
+    values("bf16"),
+    [1.0, -2.5, f32::from_bits(0x0001_0000), f32::NEG_INFINITY]
+  );
+  // f64 values outside the f32 range saturate the same way a cast does
+  assert_eq!(values("f64"), [1.0, -2.5, 0.0, f32::INFINITY]);
+}